@@ -303,6 +303,10 @@ modules = [Bin("demo-noclip", ["snowmew", "snowmew-render", "snowmew-loader"]),
            Lib("snowmew-loader", ["snowmew", "snowmew-graphics", "stb-image"]),
            Lib("snowmew-physics", ["snowmew", "collision", "snowmew-position", "cow"]),
            Lib("snowmew-position", ["snowmew", "cgmath", "OpenCL", "cow"]),
+           Lib("snowmew-ai", ["snowmew", "snowmew-position", "snowmew-physics", "cgmath", "cow"]),
+           Lib("snowmew-audio", ["snowmew", "snowmew-position", "cgmath", "cow"]),
+           Lib("snowmew-animation", ["snowmew", "snowmew-position", "snowmew-graphics", "cgmath", "cow"]),
+           Lib("snowmew-net", ["snowmew", "snowmew-position", "cgmath", "cow"]),
            Lib("snowmew-graphics", ["snowmew", "cgmath", "cow", "collision"]),
            Lib("cgmath", ext="cgmath.rs"),
            Lib("cow"),
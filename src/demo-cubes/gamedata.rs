@@ -1,44 +1,50 @@
 
+use std::io::{File, IoResult, BufferedReader, BufferedWriter};
+
 use snowmew::common::{Common, CommonData};
+use snowmew::snapshot;
+use snowmew::snapshot::Snapshot;
 use position::{Positions, PositionData};
 use graphics::{Graphics, GraphicsData};
 use graphics::default::load_default;
 use render::RenderData;
 
-#[deriving(Clone)]
-pub struct GameData {
-    common: CommonData,
-    position: PositionData,
-    graphics: GraphicsData
-}
+game_data!(GameData {
+    common: CommonData => Common { get_common, get_common_mut },
+    position: PositionData => Positions { get_position, get_position_mut },
+    graphics: GraphicsData => Graphics { get_graphics, get_graphics_mut }
+} hooks {
+    instantiate_position, delete_position;
+    instantiate_graphics, delete_graphics
+})
 
 impl GameData {
     pub fn new() -> GameData {
-        let mut gd = GameData {
-            common: CommonData::new(),
-            position: PositionData::new(),
-            graphics: GraphicsData::new()
-        };
+        let mut gd = GameData::empty();
 
         load_default(&mut gd);
 
         gd
     }
-}
-
-impl Common for GameData {
-    fn get_common<'a>(&'a self) -> &'a CommonData { &self.common }
-    fn get_common_mut<'a>(&'a mut self) -> &'a mut CommonData { &mut self.common }
-}
 
-impl Positions for GameData {
-    fn get_position<'a>(&'a self) -> &'a PositionData { &self.position }
-    fn get_position_mut<'a>(&'a mut self) -> &'a mut PositionData { &mut self.position }
-}
+    pub fn save(&self, path: &Path) -> IoResult<()> {
+        let mut w = BufferedWriter::new(try!(File::create(path)));
+        try!(snapshot::write_header(&mut w));
+        try!(self.common.write_to(&mut w));
+        try!(self.position.write_to(&mut w));
+        try!(self.graphics.write_to(&mut w));
+        w.flush()
+    }
 
-impl Graphics for GameData {
-    fn get_graphics<'a>(&'a self) -> &'a GraphicsData { &self.graphics }
-    fn get_graphics_mut<'a>(&'a mut self) -> &'a mut GraphicsData { &mut self.graphics }
+    pub fn load(path: &Path) -> IoResult<GameData> {
+        let mut r = BufferedReader::new(try!(File::open(path)));
+        let version = try!(snapshot::read_header(&mut r));
+        Ok(GameData {
+            common: try!(Snapshot::read_version(&mut r, version)),
+            position: try!(Snapshot::read_version(&mut r, version)),
+            graphics: try!(Snapshot::read_version(&mut r, version))
+        })
+    }
 }
 
 impl RenderData for GameData {}
\ No newline at end of file
@@ -1,7 +1,9 @@
 #![crate_id = "demo-cubes"]
 #![feature(macro_rules)]
 #![feature(globs)]
+#![feature(phase)]
 
+#[phase(plugin, link)]
 extern crate snowmew;
 extern crate render = "snowmew-render";
 extern crate position = "snowmew-position";
@@ -29,7 +31,7 @@ use graphics::light;
 
 
 use render::RenderFactory;
-use snowmew::common::Common;
+use snowmew::common::{Common, ObjectKey};
 
 use gamedata::GameData;
 
@@ -44,12 +46,6 @@ fn main() {
     let mut sc = snowmew::SnowmewConfig::new();
     sc.render = Some(RenderFactory::new());
 
-    let mut gd = GameData::new();
-    let scene = gd.new_scene("scene");
-
-    let cube = gd.find("core/geometry/cube").expect("cube not found");
-    let red = gd.find("core/material/flat/red").expect("red not found");
-
     let args = std::os::args();
     let count = if args.len() >= 2 {
         FromStr::from_str(args.get(1).as_slice()).expect("Could not parse int")
@@ -57,6 +53,30 @@ fn main() {
         10i
     };
 
+    // a previously saved level can be loaded instead of generating one
+    let level = if args.len() >= 3 {
+        Some(Path::new(args.get(2).as_slice()))
+    } else {
+        None
+    };
+
+    match level {
+        Some(ref path) if path.exists() => {
+            let gd = GameData::load(path).ok().expect("Could not load level");
+            let scene = gd.find("scene").expect("scene not found");
+            let camera_loc = gd.find("camera").expect("camera not found");
+            run(sc, gd, scene, camera_loc);
+            return;
+        }
+        _ => ()
+    }
+
+    let mut gd = GameData::new();
+    let scene = gd.new_scene("scene");
+
+    let cube = gd.find("core/geometry/cube").expect("cube not found");
+    let red = gd.find("core/material/flat/red").expect("red not found");
+
     for x in range(-count, count) {
         for y in range(-count, count) {
             for z in range(-count, count) {
@@ -71,9 +91,6 @@ fn main() {
         }
     }
 
-    let (mut rot_x, mut rot_y) = (0_f64, 0_f64);
-    let mut pos = Point3::new(0f32, 0f32, 0f32);
-
     let sun = light::Directional::new(Vector3::new(0.5f32, 1., 0.5),
                                       Vector3::new(1f32, 1., 1.), 0.25);
     gd.new_light(scene, "sun", light::Directional(sun));
@@ -81,7 +98,20 @@ fn main() {
     let camera_loc = gd.new_object(None, "camera");
     gd.set_to_identity(camera_loc);
 
-    sc.start(gd, |gd, current_input, last_input| {
+    match level {
+        Some(ref path) => gd.save(path).ok().expect("Could not save level"),
+        None => ()
+    }
+
+    run(sc, gd, scene, camera_loc);
+}
+
+fn run(sc: snowmew::SnowmewConfig<GameData, render::RenderFactory>,
+       gd: GameData, scene: ObjectKey, camera_loc: ObjectKey) {
+    let (mut rot_x, mut rot_y) = (0_f64, 0_f64);
+    let mut pos = Point3::new(0f32, 0f32, 0f32);
+
+    sc.start(gd, |gd, frame, current_input, last_input| {
         let mut gd = gd;
         match current_input.is_focused() {
             true => {
@@ -104,12 +134,14 @@ fn main() {
             false => {}
         }
 
+        // units per second
+        let speed = 6.25f32 * frame.delta as f32;
         let input_vec = Vector3::new(
-            if current_input.key_down(glfw::KeyA) {0.05f32} else {0f32} +
-            if current_input.key_down(glfw::KeyD) {-0.05f32} else {0f32}, 
+            if current_input.key_down(glfw::KeyA) {speed} else {0f32} +
+            if current_input.key_down(glfw::KeyD) {-speed} else {0f32}, 
             0f32,
-            if current_input.key_down(glfw::KeyW) {0.05f32} else {0f32} +
-            if current_input.key_down(glfw::KeyS) {-0.05f32} else {0f32}
+            if current_input.key_down(glfw::KeyW) {speed} else {0f32} +
+            if current_input.key_down(glfw::KeyS) {-speed} else {0f32}
         );
 
         let rot: Quaternion<f32> = Rotation3::from_axis_angle(&Vector3::new(0f32, 1f32, 0f32), deg(-rot_x as f32).to_rad());
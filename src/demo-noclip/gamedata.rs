@@ -5,20 +5,18 @@ use graphics::{Graphics, GraphicsData};
 use graphics::default::load_default;
 use render::RenderData;
 
-#[deriving(Clone)]
-pub struct GameData {
-    common: CommonData,
-    position: PositionData,
-    graphics: GraphicsData
-}
+game_data!(GameData {
+    common: CommonData => Common { get_common, get_common_mut },
+    position: PositionData => Positions { get_position, get_position_mut },
+    graphics: GraphicsData => Graphics { get_graphics, get_graphics_mut }
+} hooks {
+    instantiate_position, delete_position;
+    instantiate_graphics, delete_graphics
+})
 
 impl GameData {
     pub fn new() -> GameData {
-        let mut gd = GameData {
-            common: CommonData::new(),
-            position: PositionData::new(),
-            graphics: GraphicsData::new()
-        };
+        let mut gd = GameData::empty();
 
         load_default(&mut gd);
 
@@ -26,19 +24,4 @@ impl GameData {
     }
 }
 
-impl Common for GameData {
-    fn get_common<'a>(&'a self) -> &'a CommonData { &self.common }
-    fn get_common_mut<'a>(&'a mut self) -> &'a mut CommonData { &mut self.common }
-}
-
-impl Positions for GameData {
-    fn get_position<'a>(&'a self) -> &'a PositionData { &self.position }
-    fn get_position_mut<'a>(&'a mut self) -> &'a mut PositionData { &mut self.position }
-}
-
-impl Graphics for GameData {
-    fn get_graphics<'a>(&'a self) -> &'a GraphicsData { &self.graphics }
-    fn get_graphics_mut<'a>(&'a mut self) -> &'a mut GraphicsData { &mut self.graphics }
-}
-
 impl RenderData for GameData {}
\ No newline at end of file
@@ -1,9 +1,11 @@
 #![crate_id = "demo-noclip"]
 #![feature(macro_rules)]
 #![feature(globs)]
+#![feature(phase)]
 
 extern crate glfw;
 extern crate gl;
+#[phase(plugin, link)]
 extern crate snowmew;
 extern crate render = "snowmew-render";
 extern crate loader = "snowmew-loader";
@@ -102,7 +104,7 @@ fn main() {
                                       Vector3::new(1f32, 1., 1.), 1.);
     db.new_light(scene, "sun", light::Directional(sun));
 
-    sc.start(db, |gd, input_state, last_input| {
+    sc.start(db, |gd, frame, input_state, last_input| {
         let mut gd = gd;
         match input_state.is_focused() {
             true => {
@@ -125,12 +127,14 @@ fn main() {
             false => {}
         }
 
+        // units per second
+        let speed = 1.25f32 * frame.delta as f32;
         let input_vec = Vector3::new(
-            if input_state.key_down(glfw::KeyA) {0.01f32} else {0f32} +
-            if input_state.key_down(glfw::KeyD) {-0.01f32} else {0f32}, 
+            if input_state.key_down(glfw::KeyA) {speed} else {0f32} +
+            if input_state.key_down(glfw::KeyD) {-speed} else {0f32}, 
             0f32,
-            if input_state.key_down(glfw::KeyW) {0.01f32} else {0f32} +
-            if input_state.key_down(glfw::KeyS) {-0.01f32} else {0f32}
+            if input_state.key_down(glfw::KeyW) {speed} else {0f32} +
+            if input_state.key_down(glfw::KeyS) {-speed} else {0f32}
         );
 
         let rot: Quaternion<f32> = Rotation3::from_axis_angle(&Vector3::new(0f32, 1f32, 0f32), deg(-rot_x as f32).to_rad());
@@ -0,0 +1,137 @@
+#![crate_id = "github.com/csherratt/snowmew#snowmew-ai:0.1"]
+#![license = "ASL2"]
+#![crate_type = "lib"]
+#![comment = "A behaviour tree manager for snowmew"]
+
+extern crate snowmew;
+extern crate cow;
+extern crate cgmath;
+extern crate collections;
+extern crate position = "snowmew-position";
+extern crate physics = "snowmew-physics";
+
+use cgmath::vector::Vector3;
+
+use cow::btree::{BTreeMap, BTreeMapIterator};
+
+use snowmew::common::{ObjectKey, Remap};
+use physics::Physics;
+
+pub use manager::{AiManager, Action, Write, SetVelocity, SetLocation, apply};
+
+pub mod manager;
+
+#[deriving(Clone, PartialEq, Show)]
+pub enum Status {
+    Success,
+    Failure,
+    Running
+}
+
+/// A node of a behaviour tree. Sequences and selectors remember the child
+/// that is running and resume from it on the next tick.
+#[deriving(Clone, PartialEq, Show)]
+pub enum Node {
+    /// Run each child in turn, fails as soon as one child fails.
+    Sequence(Vec<Node>),
+    /// Run each child in turn, succeeds as soon as one child succeeds.
+    Selector(Vec<Node>),
+    /// Swap the success and failure of the child.
+    Invert(Box<Node>),
+    /// Run the child forever, restarting it each time it finishes.
+    Repeat(Box<Node>),
+    /// Running until the given number of seconds have passed.
+    Wait(f32),
+    /// Succeeds if the object is within a distance of the target.
+    Near(ObjectKey, f32),
+    /// Move towards the target at a speed, succeeds once it arrives.
+    Seek(ObjectKey, f32),
+    /// Move away from the target at a speed for one tick.
+    Flee(ObjectKey, f32),
+    /// Move towards a point at a speed, succeeds once it arrives.
+    MoveTo(Vector3<f32>, f32),
+    /// Zero the velocity.
+    Stop,
+    /// An action registered with the `AiManager` by name.
+    Act(String)
+}
+
+impl Node {
+    /// Number of nodes in the tree, including this one.
+    pub fn size(&self) -> uint {
+        match *self {
+            Sequence(ref children) | Selector(ref children) => {
+                children.iter().fold(1, |acc, c| acc + c.size())
+            }
+            Invert(ref child) | Repeat(ref child) => 1 + child.size(),
+            _ => 1
+        }
+    }
+
+    /// A copy of the tree with its targets passed through `remap`.
+    pub fn remap(&self, remap: &Remap) -> Node {
+        match *self {
+            Sequence(ref children) => Sequence(children.iter().map(|c| c.remap(remap)).collect()),
+            Selector(ref children) => Selector(children.iter().map(|c| c.remap(remap)).collect()),
+            Invert(ref child) => Invert(box child.remap(remap)),
+            Repeat(ref child) => Repeat(box child.remap(remap)),
+            Near(key, dist) => Near(remap.remap(key), dist),
+            Seek(key, speed) => Seek(remap.remap(key), speed),
+            Flee(key, speed) => Flee(remap.remap(key), speed),
+            ref node => node.clone()
+        }
+    }
+}
+
+#[deriving(Clone)]
+pub struct AiData {
+    behaviours: BTreeMap<ObjectKey, Node>
+}
+
+impl std::default::Default for AiData {
+    fn default() -> AiData { AiData::new() }
+}
+
+impl AiData {
+    pub fn new() -> AiData {
+        AiData {
+            behaviours: BTreeMap::new()
+        }
+    }
+}
+
+pub trait Ai: Physics {
+    fn get_ai<'a>(&'a self) -> &'a AiData;
+    fn get_ai_mut<'a>(&'a mut self) -> &'a mut AiData;
+
+    /// Attach a behaviour to an object, replacing any behaviour it had.
+    fn set_behaviour(&mut self, key: ObjectKey, node: Node) {
+        self.get_ai_mut().behaviours.insert(key, node);
+    }
+
+    fn behaviour<'a>(&'a self, key: ObjectKey) -> Option<&'a Node> {
+        self.get_ai().behaviours.find(&key)
+    }
+
+    fn behaviour_iter<'a>(&'a self) -> BTreeMapIterator<'a, ObjectKey, Node> {
+        self.get_ai().behaviours.iter()
+    }
+
+    /// Copy the behaviours of a prefab to the instance made by
+    /// `Common::instantiate`. Targets inside the prefab are pointed at
+    /// their copies.
+    fn instantiate_behaviour(&mut self, remap: &Remap) {
+        for (old, new) in remap.iter() {
+            let node = self.behaviour(*old).map(|n| n.remap(remap));
+            match node {
+                Some(node) => self.set_behaviour(*new, node),
+                None => ()
+            }
+        }
+    }
+
+    /// Drop the behaviour of an object that was removed from `Common`.
+    fn delete_behaviour(&mut self, key: ObjectKey) {
+        self.get_ai_mut().behaviours.remove(&key);
+    }
+}
@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+
+use collections::TrieMap;
+
+use cgmath::vector::{Vector, EuclideanVector, Vector3};
+use cgmath::transform::Decomposed;
+use cgmath::quaternion::Quaternion;
+
+use snowmew::common::{ObjectKey, FrameInfo};
+use snowmew::manager::Manager;
+use position::Positions;
+use physics::Physics;
+
+use {Ai, Node, Status, Success, Failure, Running};
+use {Sequence, Selector, Invert, Repeat, Wait, Near, Seek, Flee, MoveTo, Stop, Act};
+
+/// A change that a behaviour wants to make. Behaviours only ever see a
+/// read-only generation, the writes are applied once every tree was ticked.
+#[deriving(Clone, PartialEq, Show)]
+pub enum Write {
+    SetVelocity(ObjectKey, Vector3<f32>),
+    SetLocation(ObjectKey, Decomposed<f32, Vector3<f32>, Quaternion<f32>>)
+}
+
+/// A leaf that is implemented in code, it is given the object the behaviour
+/// is attached to and the time since the last tick.
+pub type Action<GD> = fn(&GD, ObjectKey, f64, &mut Vec<Write>) -> Status;
+
+pub fn apply<GD: Physics>(gd: &mut GD, writes: &[Write]) {
+    for w in writes.iter() {
+        match *w {
+            SetVelocity(key, v) => gd.set_velocity(key, v),
+            SetLocation(key, loc) => gd.update_location(key, loc)
+        }
+    }
+}
+
+#[deriving(Clone)]
+struct NodeState {
+    child: uint,
+    timer: f64
+}
+
+struct Agent {
+    tree: Node,
+    state: Vec<NodeState>,
+    status: Status
+}
+
+fn world_pos<GD: Positions>(gd: &GD, key: ObjectKey) -> Vector3<f32> {
+    let mat = gd.position(key);
+    Vector3::new(mat.w.x, mat.w.y, mat.w.z)
+}
+
+struct Ticker<'a, GD> {
+    gd: &'a GD,
+    key: ObjectKey,
+    delta: f64,
+    actions: &'a HashMap<String, Action<GD>>,
+    writes: &'a mut Vec<Write>
+}
+
+impl<'a, GD: Ai> Ticker<'a, GD> {
+    fn move_to(&mut self, target: Vector3<f32>, speed: f32) -> Status {
+        let offset = target.sub_v(&world_pos(self.gd, self.key));
+        let dist = offset.length();
+        if dist <= (speed * self.delta as f32).max(1e-3) {
+            self.writes.push(SetVelocity(self.key, Vector3::new(0f32, 0., 0.)));
+            Success
+        } else {
+            self.writes.push(SetVelocity(self.key, offset.div_s(dist).mul_s(speed)));
+            Running
+        }
+    }
+
+    // `idx` is the pre-order index of `node` in the agent's state
+    fn tick(&mut self, node: &Node, idx: uint, state: &mut [NodeState]) -> Status {
+        match *node {
+            Sequence(ref children) | Selector(ref children) => {
+                let (stop, done) = match *node {
+                    Sequence(_) => (Failure, Success),
+                    _ => (Success, Failure)
+                };
+
+                // skip over the children that already finished
+                let mut child_idx = idx + 1;
+                for c in children.slice_to(state[idx].child).iter() {
+                    child_idx += c.size();
+                }
+
+                while state[idx].child < children.len() {
+                    let child = children.get(state[idx].child);
+                    let status = self.tick(child, child_idx, state);
+                    if status == Running {
+                        return Running;
+                    } else if status == stop {
+                        state[idx].child = 0;
+                        return stop;
+                    }
+                    child_idx += child.size();
+                    state[idx].child += 1;
+                }
+                state[idx].child = 0;
+                done
+            }
+            Invert(ref child) => {
+                match self.tick(&**child, idx + 1, state) {
+                    Success => Failure,
+                    Failure => Success,
+                    Running => Running
+                }
+            }
+            Repeat(ref child) => {
+                self.tick(&**child, idx + 1, state);
+                Running
+            }
+            Wait(secs) => {
+                state[idx].timer += self.delta;
+                if state[idx].timer >= secs as f64 {
+                    state[idx].timer = 0.;
+                    Success
+                } else {
+                    Running
+                }
+            }
+            Near(target, dist) => {
+                let offset = world_pos(self.gd, target).sub_v(&world_pos(self.gd, self.key));
+                if offset.length() <= dist { Success } else { Failure }
+            }
+            Seek(target, speed) => {
+                let target = world_pos(self.gd, target);
+                self.move_to(target, speed)
+            }
+            Flee(target, speed) => {
+                let offset = world_pos(self.gd, self.key).sub_v(&world_pos(self.gd, target));
+                let dist = offset.length();
+                let dir = if dist > 1e-6 { offset.div_s(dist) } else { Vector3::new(1f32, 0., 0.) };
+                self.writes.push(SetVelocity(self.key, dir.mul_s(speed)));
+                Success
+            }
+            MoveTo(point, speed) => self.move_to(point, speed),
+            Stop => {
+                self.writes.push(SetVelocity(self.key, Vector3::new(0f32, 0., 0.)));
+                Success
+            }
+            Act(ref name) => {
+                match self.actions.find(name) {
+                    Some(action) => (*action)(self.gd, self.key, self.delta, &mut *self.writes),
+                    None => Failure
+                }
+            }
+        }
+    }
+}
+
+/// Ticks every behaviour in the database. The running state of each tree is
+/// kept here, so a tree starts over if its behaviour is replaced.
+pub struct AiManager<GD> {
+    actions: HashMap<String, Action<GD>>,
+    agents: TrieMap<Agent>
+}
+
+impl<GD: Ai> AiManager<GD> {
+    pub fn new() -> AiManager<GD> {
+        AiManager {
+            actions: HashMap::new(),
+            agents: TrieMap::new()
+        }
+    }
+
+    /// Make an action available to `Act` leaves under `name`.
+    pub fn register(&mut self, name: &str, action: Action<GD>) {
+        self.actions.insert(name.to_string(), action);
+    }
+
+    /// The status the behaviour of `key` returned on the last tick.
+    pub fn status(&self, key: ObjectKey) -> Option<Status> {
+        self.agents.find(&(key as uint)).map(|a| a.status)
+    }
+
+    /// Tick every behaviour against `gd`, returning the writes they made.
+    pub fn tick(&mut self, gd: &GD, delta: f64) -> Vec<Write> {
+        let mut writes = Vec::new();
+
+        let stale: Vec<uint> = self.agents.iter()
+            .map(|(key, _)| key)
+            .filter(|key| gd.behaviour(*key as ObjectKey).is_none())
+            .collect();
+        for key in stale.iter() {
+            self.agents.remove(key);
+        }
+
+        for (key, node) in gd.behaviour_iter() {
+            let reset = match self.agents.find(&(*key as uint)) {
+                Some(agent) => agent.tree != *node,
+                None => true
+            };
+            if reset {
+                self.agents.insert(*key as uint, Agent {
+                    tree: node.clone(),
+                    state: Vec::from_elem(node.size(), NodeState { child: 0, timer: 0. }),
+                    status: Running
+                });
+            }
+
+            let agent = self.agents.find_mut(&(*key as uint)).unwrap();
+            let mut ticker = Ticker {
+                gd: gd,
+                key: *key,
+                delta: delta,
+                actions: &self.actions,
+                writes: &mut writes
+            };
+            agent.status = ticker.tick(&agent.tree, 0, agent.state.as_mut_slice());
+        }
+
+        writes
+    }
+}
+
+impl<GD: Ai + Send> Manager<GD> for AiManager<GD> {
+    fn step(&mut self, gd: GD, frame: &FrameInfo) -> Option<GD> {
+        let writes = self.tick(&gd, frame.delta);
+        if writes.len() == 0 {
+            return None;
+        }
+        let mut gd = gd;
+        apply(&mut gd, writes.as_slice());
+        Some(gd)
+    }
+}
@@ -0,0 +1,150 @@
+extern crate snowmew;
+extern crate cgmath;
+extern crate position = "snowmew-position";
+extern crate physics = "snowmew-physics";
+extern crate ai = "snowmew-ai";
+
+use snowmew::common::{Common, CommonData, ObjectKey};
+use position::{Positions, PositionData};
+use physics::{Physics, PhysicsData};
+use ai::{Ai, AiData, AiManager, Write, SetVelocity, apply};
+use ai::{Status, Success, Failure, Running};
+use ai::{Sequence, Selector, Repeat, Wait, Near, Seek, Flee, Stop, Act};
+
+use cgmath::vector::Vector3;
+
+#[deriving(Clone)]
+struct TestData {
+    common: CommonData,
+    position: PositionData,
+    physics: PhysicsData,
+    ai: AiData
+}
+
+impl TestData {
+    fn new() -> TestData {
+        TestData {
+            common: CommonData::new(),
+            position: PositionData::new(),
+            physics: PhysicsData::new(),
+            ai: AiData::new()
+        }
+    }
+}
+
+impl Common for TestData {
+    fn get_common<'a>(&'a self) -> &'a CommonData { &self.common }
+    fn get_common_mut<'a>(&'a mut self) -> &'a mut CommonData { &mut self.common }
+}
+
+impl Positions for TestData {
+    fn get_position<'a>(&'a self) -> &'a PositionData { &self.position }
+    fn get_position_mut<'a>(&'a mut self) -> &'a mut PositionData { &mut self.position }
+}
+
+impl Physics for TestData {
+    fn get_physics<'a>(&'a self) -> &'a PhysicsData { &self.physics }
+    fn get_physics_mut<'a>(&'a mut self) -> &'a mut PhysicsData { &mut self.physics }
+}
+
+impl Ai for TestData {
+    fn get_ai<'a>(&'a self) -> &'a AiData { &self.ai }
+    fn get_ai_mut<'a>(&'a mut self) -> &'a mut AiData { &mut self.ai }
+}
+
+fn setup() -> (TestData, ObjectKey, ObjectKey) {
+    let mut db = TestData::new();
+    let npc = db.new_object(None, "npc");
+    let player = db.new_object(None, "player");
+    db.set_displacement(npc, Vector3::new(0f32, 0., 0.));
+    db.set_displacement(player, Vector3::new(10f32, 0., 0.));
+    (db, npc, player)
+}
+
+#[test]
+fn seek_target() {
+    let (mut db, npc, player) = setup();
+    db.set_behaviour(npc, Seek(player, 2.));
+
+    let mut ai = AiManager::new();
+    let writes = ai.tick(&db, 0.1);
+    assert!(writes == vec!(SetVelocity(npc, Vector3::new(2f32, 0., 0.))));
+    assert!(ai.status(npc) == Some(Running));
+
+    apply(&mut db, writes.as_slice());
+    assert!(db.get_velocity(npc) == Some(Vector3::new(2f32, 0., 0.)));
+
+    db.set_displacement(npc, Vector3::new(9.9f32, 0., 0.));
+    let writes = ai.tick(&db, 0.1);
+    assert!(writes == vec!(SetVelocity(npc, Vector3::new(0f32, 0., 0.))));
+    assert!(ai.status(npc) == Some(Success));
+}
+
+#[test]
+fn selector_guard() {
+    let (mut db, npc, player) = setup();
+    db.set_behaviour(npc, Selector(vec!(
+        Sequence(vec!(Near(player, 5.), Flee(player, 1.))),
+        Stop
+    )));
+
+    let mut ai = AiManager::new();
+    let writes = ai.tick(&db, 0.1);
+    assert!(writes == vec!(SetVelocity(npc, Vector3::new(0f32, 0., 0.))));
+
+    db.set_displacement(player, Vector3::new(3f32, 0., 0.));
+    let writes = ai.tick(&db, 0.1);
+    assert!(writes == vec!(SetVelocity(npc, Vector3::new(-1f32, 0., 0.))));
+    assert!(ai.status(npc) == Some(Success));
+}
+
+#[test]
+fn sequence_resumes() {
+    let (mut db, npc, _) = setup();
+    db.set_behaviour(npc, Repeat(box Sequence(vec!(Wait(0.25), Stop))));
+
+    let mut ai = AiManager::new();
+    assert!(ai.tick(&db, 0.1).len() == 0);
+    assert!(ai.tick(&db, 0.1).len() == 0);
+    assert!(ai.tick(&db, 0.1).len() == 1);
+    assert!(ai.tick(&db, 0.1).len() == 0);
+
+    // replacing the behaviour starts it over
+    db.set_behaviour(npc, Sequence(vec!(Wait(0.15), Stop)));
+    assert!(ai.tick(&db, 0.1).len() == 0);
+    assert!(ai.tick(&db, 0.1).len() == 1);
+}
+
+fn jump(_: &TestData, key: ObjectKey, _: f64, writes: &mut Vec<Write>) -> Status {
+    writes.push(SetVelocity(key, Vector3::new(0f32, 5., 0.)));
+    Success
+}
+
+#[test]
+fn registered_action() {
+    let (mut db, npc, _) = setup();
+    db.set_behaviour(npc, Sequence(vec!(Act("jump".to_string()), Act("missing".to_string()))));
+
+    let mut ai = AiManager::new();
+    ai.register("jump", jump);
+    let writes = ai.tick(&db, 0.1);
+    assert!(writes == vec!(SetVelocity(npc, Vector3::new(0f32, 5., 0.))));
+    assert!(ai.status(npc) == Some(Failure));
+
+    db.delete_behaviour(npc);
+    assert!(ai.tick(&db, 0.1).len() == 0);
+    assert!(ai.status(npc) == None);
+}
+
+#[test]
+fn instantiate_remaps_targets() {
+    let (mut db, npc, player) = setup();
+    let eyes = db.new_object(Some(npc), "eyes");
+    db.set_behaviour(npc, Sequence(vec!(Near(eyes, 1.), Seek(player, 2.))));
+
+    let remap = db.instantiate(npc, None, "npc2").unwrap();
+    db.instantiate_behaviour(&remap);
+
+    let eyes2 = remap.get(eyes).unwrap();
+    assert!(db.behaviour(remap.root()) == Some(&Sequence(vec!(Near(eyes2, 1.), Seek(player, 2.)))));
+}
@@ -0,0 +1,132 @@
+use cgmath::vector::{Vector, Vector3};
+use cgmath::quaternion::Quaternion;
+use cgmath::transform::Decomposed;
+
+use snowmew::common::{ObjectKey, Remap};
+
+/// The value of a channel at `time` seconds into the clip.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Key<T> {
+    pub time: f64,
+    pub value: T
+}
+
+impl<T> Key<T> {
+    pub fn new(time: f64, value: T) -> Key<T> {
+        Key {
+            time: time,
+            value: value
+        }
+    }
+}
+
+// find the keys around `time` and blend them, the first and last keys are
+// held before and after the range of the channel
+fn sample_keys<T: Clone>(keys: &[Key<T>], time: f64, mix: |&T, &T, f32| -> T) -> Option<T> {
+    if keys.len() == 0 {
+        return None;
+    }
+
+    let next = match keys.iter().position(|k| k.time > time) {
+        Some(idx) => idx,
+        None => return Some(keys[keys.len()-1].value.clone())
+    };
+    if next == 0 {
+        return Some(keys[0].value.clone());
+    }
+
+    let (a, b) = (&keys[next-1], &keys[next]);
+    let alpha = ((time - a.time) / (b.time - a.time)) as f32;
+    Some(mix(&a.value, &b.value, alpha))
+}
+
+/// Keyframes for the local transform of one object. Each channel is
+/// optional, an empty channel leaves that part of the transform alone.
+/// Keys must be sorted by time.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Track {
+    pub target: ObjectKey,
+    pub disp: Vec<Key<Vector3<f32>>>,
+    pub rot: Vec<Key<Quaternion<f32>>>,
+    pub scale: Vec<Key<f32>>
+}
+
+/// A track sampled at a point in time, `None` for the empty channels.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Pose {
+    pub disp: Option<Vector3<f32>>,
+    pub rot: Option<Quaternion<f32>>,
+    pub scale: Option<f32>
+}
+
+impl Pose {
+    /// Fill in the channels that were not animated from `base`.
+    pub fn apply(&self, base: &Decomposed<f32, Vector3<f32>, Quaternion<f32>>)
+            -> Decomposed<f32, Vector3<f32>, Quaternion<f32>> {
+        Decomposed {
+            disp: self.disp.unwrap_or(base.disp),
+            rot: self.rot.unwrap_or(base.rot),
+            scale: self.scale.unwrap_or(base.scale)
+        }
+    }
+}
+
+impl Track {
+    pub fn new(target: ObjectKey) -> Track {
+        Track {
+            target: target,
+            disp: Vec::new(),
+            rot: Vec::new(),
+            scale: Vec::new()
+        }
+    }
+
+    /// The time of the last key in any channel.
+    pub fn duration(&self) -> f64 {
+        let last = |t: Option<f64>| t.unwrap_or(0.);
+        last(self.disp.last().map(|k| k.time))
+            .max(last(self.rot.last().map(|k| k.time)))
+            .max(last(self.scale.last().map(|k| k.time)))
+    }
+
+    /// Displacement and scale are interpolated linearly, rotation is
+    /// slerped.
+    pub fn sample(&self, time: f64) -> Pose {
+        Pose {
+            disp: sample_keys(self.disp.as_slice(), time, |a, b, t| {
+                a.add_v(&b.sub_v(a).mul_s(t))
+            }),
+            rot: sample_keys(self.rot.as_slice(), time, |a, b, t| a.slerp(b, t)),
+            scale: sample_keys(self.scale.as_slice(), time, |a, b, t| *a + (*b - *a) * t)
+        }
+    }
+}
+
+/// A set of tracks that are played together.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Clip {
+    pub duration: f64,
+    pub tracks: Vec<Track>
+}
+
+impl Clip {
+    /// A clip that lasts until the last key of its tracks.
+    pub fn new(tracks: Vec<Track>) -> Clip {
+        let duration = tracks.iter().fold(0., |d, t| t.duration().max(d));
+        Clip {
+            duration: duration,
+            tracks: tracks
+        }
+    }
+
+    pub fn sample(&self, time: f64) -> Vec<(ObjectKey, Pose)> {
+        self.tracks.iter().map(|t| (t.target, t.sample(time))).collect()
+    }
+
+    /// Point the tracks that target objects inside a prefab at the copies.
+    pub fn remap(&mut self, remap: &Remap) {
+        for t in self.tracks.mut_iter() {
+            t.target = remap.remap(t.target);
+        }
+    }
+}
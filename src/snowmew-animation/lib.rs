@@ -0,0 +1,160 @@
+#![crate_id = "github.com/csherratt/snowmew#snowmew-animation:0.1"]
+#![license = "ASL2"]
+#![crate_type = "lib"]
+#![comment = "A keyframe animation manager for snowmew"]
+
+extern crate snowmew;
+extern crate cow;
+extern crate cgmath;
+extern crate collections;
+extern crate position = "snowmew-position";
+extern crate graphics = "snowmew-graphics";
+
+use cgmath::vector::Vector3;
+use cgmath::quaternion::Quaternion;
+use cgmath::transform::Decomposed;
+
+use snowmew::common::{ObjectKey, Common, Remap};
+use position::Positions;
+
+use cow::btree::{BTreeMap, BTreeMapIterator};
+
+pub use clip::{Key, Track, Pose, Clip};
+pub use player::{Playback, Player, AnimationManager, blend};
+pub use skeleton::{Joint, new_skeleton, bind_skin};
+
+pub mod clip;
+pub mod player;
+pub mod skeleton;
+
+#[deriving(Clone)]
+pub struct AnimationData {
+    clips: BTreeMap<ObjectKey, Clip>,
+    players: BTreeMap<ObjectKey, Player>
+}
+
+impl std::default::Default for AnimationData {
+    fn default() -> AnimationData { AnimationData::new() }
+}
+
+impl AnimationData {
+    pub fn new() -> AnimationData {
+        AnimationData {
+            clips: BTreeMap::new(),
+            players: BTreeMap::new()
+        }
+    }
+}
+
+pub trait Animation: Common + Positions {
+    fn get_animation<'a>(&'a self) -> &'a AnimationData;
+    fn get_animation_mut<'a>(&'a mut self) -> &'a mut AnimationData;
+
+    fn new_clip(&mut self, parent: ObjectKey, name: &str, clip: Clip) -> ObjectKey {
+        let oid = self.new_object(Some(parent), name);
+        self.get_animation_mut().clips.insert(oid, clip);
+        oid
+    }
+
+    fn clip<'a>(&'a self, oid: ObjectKey) -> Option<&'a Clip> {
+        self.get_animation().clips.find(&oid)
+    }
+
+    /// A player can be attached to any object, the tracks of its clips say
+    /// which objects are moved.
+    fn set_player(&mut self, key: ObjectKey, player: Player) {
+        self.get_animation_mut().players.insert(key, player);
+    }
+
+    fn player<'a>(&'a self, key: ObjectKey) -> Option<&'a Player> {
+        self.get_animation().players.find(&key)
+    }
+
+    fn player_iter<'a>(&'a self) -> BTreeMapIterator<'a, ObjectKey, Player> {
+        self.get_animation().players.iter()
+    }
+
+    /// Play `clip` on the player of `key` from `time`, a player is
+    /// attached if the object does not have one.
+    fn play_clip(&mut self, key: ObjectKey, clip: ObjectKey, time: f64, looping: bool) {
+        let mut player = self.player(key).map(|p| p.clone()).unwrap_or(Player::new());
+        player.play(clip, time, looping);
+        self.set_player(key, player);
+    }
+
+    /// Blend from whatever the player of `key` is playing to `clip`.
+    fn crossfade_clip(&mut self, key: ObjectKey, clip: ObjectKey, time: f64, fade: f64, looping: bool) {
+        let mut player = self.player(key).map(|p| p.clone()).unwrap_or(Player::new());
+        player.crossfade(clip, time, fade, looping);
+        self.set_player(key, player);
+    }
+
+    fn stop_clips(&mut self, key: ObjectKey, time: f64, fade: f64) {
+        let player = self.player(key).map(|p| p.clone());
+        match player {
+            Some(mut player) => {
+                player.stop(time, fade);
+                self.set_player(key, player);
+            }
+            None => ()
+        }
+    }
+
+    /// The local transforms the player of `key` gives its targets at `time`.
+    fn sample_player(&self, key: ObjectKey, time: f64)
+            -> Vec<(ObjectKey, Decomposed<f32, Vector3<f32>, Quaternion<f32>>)> {
+        match self.player(key) {
+            Some(player) => blend(self, player, time),
+            None => Vec::new()
+        }
+    }
+
+    /// Move every animated object to where its clips put it at `time` and
+    /// drop the clips that have faded out.
+    fn animate(&mut self, time: f64) {
+        let keys: Vec<ObjectKey> = self.player_iter().map(|(k, _)| *k).collect();
+        for key in keys.iter() {
+            for &(target, loc) in self.sample_player(*key, time).iter() {
+                self.update_location(target, loc);
+            }
+
+            let mut player = self.player(*key).unwrap().clone();
+            if player.retire(time) {
+                self.set_player(*key, player);
+            }
+        }
+    }
+
+    /// Copy the clips and players of a prefab to the instance made by
+    /// `Common::instantiate`. Tracks and players that point inside the
+    /// prefab are pointed at the copies.
+    fn instantiate_animation(&mut self, remap: &Remap) {
+        for (old, new) in remap.iter() {
+            let clip = self.clip(*old).map(|c| c.clone());
+            match clip {
+                Some(mut clip) => {
+                    clip.remap(remap);
+                    self.get_animation_mut().clips.insert(*new, clip);
+                }
+                None => ()
+            }
+
+            let player = self.player(*old).map(|p| p.clone());
+            match player {
+                Some(mut player) => {
+                    for p in player.playing.mut_iter() {
+                        p.clip = remap.remap(p.clip);
+                    }
+                    self.set_player(*new, player);
+                }
+                None => ()
+            }
+        }
+    }
+
+    /// Drop the clip and player of an object that was removed from `Common`.
+    fn delete_animation(&mut self, key: ObjectKey) {
+        self.get_animation_mut().clips.remove(&key);
+        self.get_animation_mut().players.remove(&key);
+    }
+}
@@ -0,0 +1,275 @@
+use collections::TreeMap;
+
+use cgmath::vector::{Vector, Vector3};
+use cgmath::quaternion::Quaternion;
+use cgmath::transform::{Transform, Decomposed};
+
+use snowmew::common::{ObjectKey, Common, FrameInfo};
+use snowmew::manager::Manager;
+use position::Positions;
+
+use {Animation, Clip};
+
+// goes from 0 to 1 over `fade` seconds once `t` reaches 0
+fn ramp(t: f64, fade: f64) -> f64 {
+    if fade > 0. {
+        (t / fade).max(0.).min(1.)
+    } else if t >= 0. {
+        1.
+    } else {
+        0.
+    }
+}
+
+/// A clip being played. Everything is worked out from the time it is
+/// sampled at, nothing is accumulated from frame to frame, so sampling the
+/// same time always gives the same pose.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Playback {
+    pub clip: ObjectKey,
+    /// The time the clip was started at.
+    pub start: f64,
+    pub speed: f64,
+    pub looping: bool,
+    pub weight: f32,
+    /// Seconds taken to fade in after `start`.
+    pub fade_in: f64,
+    /// Seconds taken to fade out after `stop`.
+    pub fade_out: f64,
+    pub stop: Option<f64>
+}
+
+impl Playback {
+    pub fn new(clip: ObjectKey, start: f64) -> Playback {
+        Playback {
+            clip: clip,
+            start: start,
+            speed: 1.,
+            looping: false,
+            weight: 1.,
+            fade_in: 0.,
+            fade_out: 0.,
+            stop: None
+        }
+    }
+
+    /// How far into the clip the playback is at `time`. A looping clip
+    /// wraps around, the others hold their last frame.
+    pub fn clip_time(&self, clip: &Clip, time: f64) -> f64 {
+        let t = ((time - self.start) * self.speed).max(0.);
+        if self.looping && clip.duration > 0. {
+            t % clip.duration
+        } else {
+            t.min(clip.duration)
+        }
+    }
+
+    pub fn weight_at(&self, time: f64) -> f32 {
+        let w = match self.stop {
+            Some(stop) => ramp(time - self.start, self.fade_in)
+                .min(1. - ramp(time - stop, self.fade_out)),
+            None => ramp(time - self.start, self.fade_in)
+        };
+        self.weight * w as f32
+    }
+
+    /// True once the playback has faded out.
+    pub fn finished(&self, time: f64) -> bool {
+        match self.stop {
+            Some(stop) => time >= stop + self.fade_out,
+            None => false
+        }
+    }
+}
+
+/// The clips playing on an object. Their poses are blended by weight.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Player {
+    pub playing: Vec<Playback>
+}
+
+impl Player {
+    pub fn new() -> Player {
+        Player {
+            playing: Vec::new()
+        }
+    }
+
+    /// Drop whatever is playing and play `clip` from `time`.
+    pub fn play(&mut self, clip: ObjectKey, time: f64, looping: bool) {
+        let mut p = Playback::new(clip, time);
+        p.looping = looping;
+        self.playing = vec!(p);
+    }
+
+    /// Fade out whatever is playing and fade `clip` in over `fade` seconds.
+    pub fn crossfade(&mut self, clip: ObjectKey, time: f64, fade: f64, looping: bool) {
+        self.stop(time, fade);
+        let mut p = Playback::new(clip, time);
+        p.looping = looping;
+        p.fade_in = fade;
+        self.playing.push(p);
+    }
+
+    /// Fade out everything that is playing over `fade` seconds.
+    pub fn stop(&mut self, time: f64, fade: f64) {
+        for p in self.playing.mut_iter() {
+            if p.stop.is_none() {
+                p.stop = Some(time);
+                p.fade_out = fade;
+            }
+        }
+    }
+
+    /// Drop the playbacks that have faded out by `time`, returns true if
+    /// any were dropped.
+    pub fn retire(&mut self, time: f64) -> bool {
+        let len = self.playing.len();
+        self.playing.retain(|p| !p.finished(time));
+        len != self.playing.len()
+    }
+}
+
+struct Accum {
+    disp: Vector3<f32>,
+    disp_weight: f32,
+    rot: Option<Quaternion<f32>>,
+    rot_weight: f32,
+    scale: f32,
+    scale_weight: f32
+}
+
+impl Accum {
+    fn new() -> Accum {
+        Accum {
+            disp: Vector3::new(0f32, 0., 0.),
+            disp_weight: 0.,
+            rot: None,
+            rot_weight: 0.,
+            scale: 0.,
+            scale_weight: 0.
+        }
+    }
+
+    // the normalized pose, moved only `weight` of the way from `base` when
+    // the weights add up to less than 1
+    fn finish(&self, base: &Decomposed<f32, Vector3<f32>, Quaternion<f32>>)
+            -> Decomposed<f32, Vector3<f32>, Quaternion<f32>> {
+        let disp = if self.disp_weight > 0. {
+            let d = self.disp.div_s(self.disp_weight);
+            if self.disp_weight < 1. {
+                base.disp.add_v(&d.sub_v(&base.disp).mul_s(self.disp_weight))
+            } else {
+                d
+            }
+        } else {
+            base.disp
+        };
+
+        let rot = match self.rot {
+            Some(r) if self.rot_weight < 1. => base.rot.slerp(&same_hemisphere(&base.rot, r), self.rot_weight),
+            Some(r) => r,
+            None => base.rot
+        };
+
+        let scale = if self.scale_weight > 0. {
+            let s = self.scale / self.scale_weight;
+            if self.scale_weight < 1. {
+                base.scale + (s - base.scale) * self.scale_weight
+            } else {
+                s
+            }
+        } else {
+            base.scale
+        };
+
+        Decomposed {
+            disp: disp,
+            rot: rot,
+            scale: scale
+        }
+    }
+}
+
+// `r` flipped if needed so that it is on the same hemisphere as `q`, a slerp
+// between them then takes the shorter arc
+fn same_hemisphere(q: &Quaternion<f32>, r: Quaternion<f32>) -> Quaternion<f32> {
+    if q.s * r.s + q.v.dot(&r.v) < 0. {
+        Quaternion::from_sv(-r.s, r.v.mul_s(-1.))
+    } else {
+        r
+    }
+}
+
+/// Blend the clips playing on `player` at `time`. The weights are
+/// normalized between the clips, when they add up to less than 1 the
+/// target is only moved that far from its current location, so a lone clip
+/// fades in and out. Channels that no clip animates are taken from the
+/// current location of the target.
+pub fn blend<A: Animation>(gd: &A, player: &Player, time: f64)
+        -> Vec<(ObjectKey, Decomposed<f32, Vector3<f32>, Quaternion<f32>>)> {
+    let mut acc: TreeMap<ObjectKey, Accum> = TreeMap::new();
+
+    for p in player.playing.iter() {
+        let w = p.weight_at(time);
+        let clip = match gd.clip(p.clip) {
+            Some(clip) if w > 0. => clip,
+            _ => continue
+        };
+
+        for (target, pose) in clip.sample(p.clip_time(clip, time)).move_iter() {
+            if acc.find(&target).is_none() {
+                acc.insert(target, Accum::new());
+            }
+            let a = acc.find_mut(&target).unwrap();
+
+            match pose.disp {
+                Some(d) => {
+                    a.disp = a.disp.add_v(&d.mul_s(w));
+                    a.disp_weight += w;
+                }
+                None => ()
+            }
+            match pose.scale {
+                Some(s) => {
+                    a.scale += s * w;
+                    a.scale_weight += w;
+                }
+                None => ()
+            }
+            match pose.rot {
+                Some(r) => {
+                    a.rot_weight += w;
+                    a.rot = Some(match a.rot {
+                        Some(q) => q.slerp(&same_hemisphere(&q, r), w / a.rot_weight),
+                        None => r
+                    });
+                }
+                None => ()
+            }
+        }
+    }
+
+    acc.iter().filter(|&(key, _)| gd.object(*key).is_some()).map(|(key, a)| {
+        let base = gd.location(*key).unwrap_or(Transform::identity());
+        (*key, a.finish(&base))
+    }).collect()
+}
+
+/// Steps every `Player` in the database to `FrameInfo.time`.
+pub struct AnimationManager;
+
+impl AnimationManager {
+    pub fn new() -> AnimationManager { AnimationManager }
+}
+
+impl<GD: Animation + Send> Manager<GD> for AnimationManager {
+    fn step(&mut self, gd: GD, frame: &FrameInfo) -> Option<GD> {
+        if gd.player_iter().next().is_none() {
+            return None;
+        }
+        let mut gd = gd;
+        gd.animate(frame.time);
+        Some(gd)
+    }
+}
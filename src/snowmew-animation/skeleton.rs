@@ -0,0 +1,58 @@
+use cgmath::vector::Vector3;
+use cgmath::quaternion::Quaternion;
+use cgmath::transform::Decomposed;
+use cgmath::matrix::Matrix;
+
+use snowmew::common::ObjectKey;
+use position::Positions;
+use graphics::{Graphics, Skin};
+
+/// A joint of a skeleton. `parent` is the index of an earlier joint, joints
+/// without one are placed under the object the skeleton is made in.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Joint {
+    pub name: String,
+    pub parent: Option<uint>,
+    pub bind: Decomposed<f32, Vector3<f32>, Quaternion<f32>>
+}
+
+impl Joint {
+    pub fn new(name: &str, parent: Option<uint>,
+               bind: Decomposed<f32, Vector3<f32>, Quaternion<f32>>) -> Joint {
+        Joint {
+            name: name.to_string(),
+            parent: parent,
+            bind: bind
+        }
+    }
+}
+
+/// Make an object for each joint under `parent`, at its bind transform.
+/// The keys are returned in the order of `joints`, they are animated like
+/// any other object by targeting them from a `Track`.
+pub fn new_skeleton<GD: Positions>(gd: &mut GD, parent: ObjectKey, joints: &[Joint]) -> Vec<ObjectKey> {
+    let mut keys: Vec<ObjectKey> = Vec::with_capacity(joints.len());
+    for j in joints.iter() {
+        let p = match j.parent {
+            Some(idx) => {
+                assert!(idx < keys.len(), "a joint must come after its parent");
+                *keys.get(idx)
+            }
+            None => parent
+        };
+        let key = gd.new_object(Some(p), j.name.as_slice());
+        gd.update_location(key, j.bind);
+        keys.push(key);
+    }
+    keys
+}
+
+/// Bind `drawable` to `joints` in the pose they are in now. The vertices
+/// of the mesh are expected to be in world space for that pose. Fails if
+/// the mesh is weighted to more joints than it is given.
+pub fn bind_skin<GD: Positions + Graphics>(gd: &mut GD, drawable: ObjectKey, joints: Vec<ObjectKey>) -> bool {
+    let inverse_bind = joints.iter().map(|j| {
+        gd.position(*j).invert().expect("joint has a singular world matrix")
+    }).collect();
+    gd.set_skin(drawable, Skin::new(joints, inverse_bind))
+}
@@ -0,0 +1,352 @@
+extern crate snowmew;
+extern crate cgmath;
+extern crate position = "snowmew-position";
+extern crate graphics = "snowmew-graphics";
+extern crate animation = "snowmew-animation";
+
+use snowmew::common::{Common, CommonData, FrameInfo};
+use snowmew::manager::Manager;
+use position::{Positions, PositionData};
+use graphics::{Graphics, GraphicsData, Geometry, VertexBuffer, Material};
+use graphics::geometry::VertexGeoTexNormSkin;
+use graphics::skin::skin_vertices;
+use animation::{Animation, AnimationData, AnimationManager, Clip, Track, Key, Playback};
+use animation::{Joint, new_skeleton, bind_skin};
+
+use cgmath::vector::{Vector2, Vector3, Vector4};
+use cgmath::matrix::{Matrix4, Matrix};
+use cgmath::transform::Decomposed;
+use cgmath::quaternion::Quaternion;
+use cgmath::rotation::Rotation3;
+use cgmath::angle::{ToRad, deg};
+use cgmath::approx::ApproxEq;
+
+#[deriving(Clone)]
+struct TestData {
+    common: CommonData,
+    position: PositionData,
+    graphics: GraphicsData,
+    animation: AnimationData
+}
+
+impl TestData {
+    fn new() -> TestData {
+        TestData {
+            common: CommonData::new(),
+            position: PositionData::new(),
+            graphics: GraphicsData::new(),
+            animation: AnimationData::new()
+        }
+    }
+}
+
+impl Common for TestData {
+    fn get_common<'a>(&'a self) -> &'a CommonData { &self.common }
+    fn get_common_mut<'a>(&'a mut self) -> &'a mut CommonData { &mut self.common }
+}
+
+impl Positions for TestData {
+    fn get_position<'a>(&'a self) -> &'a PositionData { &self.position }
+    fn get_position_mut<'a>(&'a mut self) -> &'a mut PositionData { &mut self.position }
+}
+
+impl Graphics for TestData {
+    fn get_graphics<'a>(&'a self) -> &'a GraphicsData { &self.graphics }
+    fn get_graphics_mut<'a>(&'a mut self) -> &'a mut GraphicsData { &mut self.graphics }
+}
+
+impl Animation for TestData {
+    fn get_animation<'a>(&'a self) -> &'a AnimationData { &self.animation }
+    fn get_animation_mut<'a>(&'a mut self) -> &'a mut AnimationData { &mut self.animation }
+}
+
+fn slide(target: u32, from: f32, to: f32) -> Clip {
+    let mut track = Track::new(target);
+    track.disp.push(Key::new(0., Vector3::new(from, 0., 0.)));
+    track.disp.push(Key::new(2., Vector3::new(to, 0., 0.)));
+    Clip::new(vec!(track))
+}
+
+fn disp_x(db: &TestData, key: u32) -> f32 {
+    db.location(key).unwrap().disp.x
+}
+
+#[test]
+fn track_sample() {
+    let mut track = Track::new(1);
+    track.disp.push(Key::new(1., Vector3::new(0f32, 0., 0.)));
+    track.disp.push(Key::new(3., Vector3::new(4f32, 0., 0.)));
+    track.scale.push(Key::new(0., 1f32));
+    track.scale.push(Key::new(1., 3f32));
+    let quarter: Quaternion<f32> = Rotation3::from_axis_angle(&Vector3::new(0f32, 1., 0.), deg(90f32).to_rad());
+    track.rot.push(Key::new(0., Quaternion::identity()));
+    track.rot.push(Key::new(1., quarter));
+    let half: Quaternion<f32> = Rotation3::from_axis_angle(&Vector3::new(0f32, 1., 0.), deg(45f32).to_rad());
+
+    assert!(track.duration() == 3.);
+
+    // the first and last keys are held
+    assert!(track.sample(0.).disp == Some(Vector3::new(0f32, 0., 0.)));
+    assert!(track.sample(5.).disp == Some(Vector3::new(4f32, 0., 0.)));
+
+    let pose = track.sample(0.5);
+    assert!(pose.scale == Some(2.));
+    assert!(pose.rot.unwrap().approx_eq(&half));
+    assert!(track.sample(2.).disp == Some(Vector3::new(2f32, 0., 0.)));
+
+    let empty = Track::new(1).sample(1.);
+    assert!(empty.disp.is_none() && empty.rot.is_none() && empty.scale.is_none());
+}
+
+#[test]
+fn playback_time() {
+    let clip = slide(1, 0., 4.);
+    let mut p = Playback::new(2, 10.);
+    assert!(p.clip_time(&clip, 9.) == 0.);
+    assert!(p.clip_time(&clip, 11.) == 1.);
+    assert!(p.clip_time(&clip, 15.) == 2.);
+
+    p.looping = true;
+    assert!(p.clip_time(&clip, 15.) == 1.);
+
+    p.speed = 2.;
+    assert!(p.clip_time(&clip, 10.5) == 1.);
+}
+
+#[test]
+fn animate_deterministic() {
+    let mut db = TestData::new();
+    let obj = db.new_object(None, "obj");
+    db.set_scale(obj, 2.);
+    let clip = db.new_clip(obj, "slide", slide(obj, 0., 4.));
+    db.play_clip(obj, clip, 0., true);
+
+    db.animate(1.);
+    assert!(disp_x(&db, obj) == 2.);
+    // channels without keys are left alone
+    assert!(db.location(obj).unwrap().scale == 2.);
+
+    // the same time gives the same pose, whatever was sampled before
+    let mut other = db.clone();
+    other.animate(0.5);
+    other.animate(5.);
+    db.animate(3.);
+    db.animate(5.);
+    assert!(disp_x(&db, obj) == disp_x(&other, obj));
+    assert!(disp_x(&db, obj) == 2.);
+}
+
+#[test]
+fn crossfade_blends() {
+    let mut db = TestData::new();
+    let obj = db.new_object(None, "obj");
+    let a = db.new_clip(obj, "a", slide(obj, 0., 0.));
+    let b = db.new_clip(obj, "b", slide(obj, 4., 4.));
+
+    db.play_clip(obj, a, 0., true);
+    db.crossfade_clip(obj, b, 1., 2., true);
+    assert!(db.player(obj).unwrap().playing.len() == 2);
+
+    db.animate(1.);
+    assert!(disp_x(&db, obj) == 0.);
+    db.animate(2.);
+    assert!(disp_x(&db, obj) == 2.);
+    db.animate(3.);
+    assert!(disp_x(&db, obj) == 4.);
+
+    // the faded out clip was dropped
+    let player = db.player(obj).unwrap();
+    assert!(player.playing.len() == 1);
+    assert!(player.playing.get(0).clip == b);
+}
+
+#[test]
+fn stop_fades_lone_clip() {
+    let mut db = TestData::new();
+    let obj = db.new_object(None, "obj");
+    let clip = db.new_clip(obj, "slide", slide(obj, 0., 4.));
+
+    db.play_clip(obj, clip, 0., false);
+    db.animate(1.);
+    assert!(disp_x(&db, obj) == 2.);
+
+    // half faded out, the clip is at 4. but only moves the object half way
+    db.stop_clips(obj, 1., 2.);
+    db.animate(2.);
+    assert!(disp_x(&db, obj) == 3.);
+
+    // faded out, the object is left where the fade put it
+    db.animate(3.);
+    assert!(disp_x(&db, obj) == 3.);
+    assert!(db.player(obj).unwrap().playing.len() == 0);
+}
+
+#[test]
+fn manager_steps_to_frame_time() {
+    let mut db = TestData::new();
+    let obj = db.new_object(None, "obj");
+    let clip = db.new_clip(obj, "slide", slide(obj, 0., 4.));
+
+    let mut manager = AnimationManager::new();
+    let mut frame = FrameInfo::new(0.5);
+    assert!(manager.step(db.clone(), &frame).is_none());
+
+    db.play_clip(obj, clip, 0., false);
+    frame.time = 1.5;
+    let db = manager.step(db, &frame).unwrap();
+    assert!(disp_x(&db, obj) == 3.);
+}
+
+#[test]
+fn instantiate_remaps_tracks() {
+    let mut db = TestData::new();
+    let prefab = db.new_object(None, "prefab");
+    let arm = db.new_object(Some(prefab), "arm");
+    let clip = db.new_clip(prefab, "wave", slide(arm, 0., 4.));
+    db.play_clip(prefab, clip, 0., false);
+
+    let remap = db.instantiate(prefab, None, "copy").unwrap();
+    db.instantiate_position(&remap);
+    db.instantiate_animation(&remap);
+    let copy = remap.root();
+    let arm2 = remap.get(arm).unwrap();
+
+    assert!(db.clip(remap.get(clip).unwrap()).unwrap().tracks.get(0).target == arm2);
+    assert!(db.player(copy).unwrap().playing.get(0).clip == remap.get(clip).unwrap());
+
+    db.animate(1.);
+    assert!(disp_x(&db, arm) == 2.);
+    assert!(disp_x(&db, arm2) == 2.);
+}
+
+fn at(x: f32, y: f32, z: f32) -> Decomposed<f32, Vector3<f32>, Quaternion<f32>> {
+    Decomposed {
+        scale: 1.,
+        rot: Quaternion::identity(),
+        disp: Vector3::new(x, y, z)
+    }
+}
+
+fn skinned(x: f32, y: f32, z: f32, joints: [u32, ..4], weights: Vector4<f32>) -> VertexGeoTexNormSkin {
+    VertexGeoTexNormSkin {
+        position: Vector3::new(x, y, z),
+        texture: Vector2::new(0f32, 0.),
+        normal: Vector3::new(0f32, 1., 0.),
+        joints: joints,
+        weights: weights
+    }
+}
+
+#[test]
+fn skeleton_joints_are_objects() {
+    let mut db = TestData::new();
+    let root = db.new_object(None, "character");
+    let joints = new_skeleton(&mut db, root, [
+        Joint::new("hip", None, at(0., 1., 0.)),
+        Joint::new("knee", Some(0), at(0., -0.5, 0.))
+    ]);
+
+    assert!(joints.len() == 2);
+    assert!(db.object(*joints.get(0)).unwrap().parent == root);
+    assert!(db.object(*joints.get(1)).unwrap().parent == *joints.get(0));
+    let knee = db.position(*joints.get(1)).mul_v(&Vector4::new(0f32, 0., 0., 1.));
+    assert!(knee.approx_eq(&Vector4::new(0f32, 0.5, 0., 1.)));
+}
+
+#[test]
+fn skin_follows_joints() {
+    let mut db = TestData::new();
+    let root = db.new_object(None, "character");
+    let joints = new_skeleton(&mut db, root, [
+        Joint::new("hip", None, at(0., 1., 0.)),
+        Joint::new("knee", Some(0), at(0., -0.5, 0.))
+    ]);
+    let mesh = db.new_object(Some(root), "mesh");
+    assert!(bind_skin(&mut db, mesh, joints.clone()));
+
+    // in the bind pose the palette leaves the mesh alone
+    let skin = db.skin(mesh).unwrap().clone();
+    let palette = db.palette(skin.joints.as_slice(), skin.inverse_bind.as_slice());
+    let ident: Matrix4<f32> = Matrix4::identity();
+    for mat in palette.iter() {
+        assert!(mat.approx_eq(&ident));
+    }
+
+    let verts = [
+        skinned(0., 1., 0., [0, 0, 0, 0], Vector4::new(1f32, 0., 0., 0.)),
+        skinned(0., 0.5, 0., [1, 0, 0, 0], Vector4::new(1f32, 0., 0., 0.)),
+        skinned(0., 0.5, 0., [0, 1, 0, 0], Vector4::new(0.5f32, 0.5, 0., 0.))
+    ];
+
+    // move the knee and the vertices weighted to it follow
+    db.update_location(*joints.get(1), at(1., -0.5, 0.));
+    let palette = db.palette(skin.joints.as_slice(), skin.inverse_bind.as_slice());
+    let out = skin_vertices(verts, palette.as_slice());
+
+    assert!(out.get(0).position.approx_eq(&Vector3::new(0f32, 1., 0.)));
+    assert!(out.get(1).position.approx_eq(&Vector3::new(1f32, 0.5, 0.)));
+    assert!(out.get(2).position.approx_eq(&Vector3::new(0.5f32, 0.5, 0.)));
+    assert!(out.get(1).normal.approx_eq(&Vector3::new(0f32, 1., 0.)));
+}
+
+#[test]
+fn skin_rejects_unknown_joints() {
+    let mut db = TestData::new();
+    let root = db.new_object(None, "character");
+    let joints = new_skeleton(&mut db, root, [
+        Joint::new("hip", None, at(0., 1., 0.))
+    ]);
+
+    let verts = vec!(
+        skinned(0., 1., 0., [0, 0, 0, 0], Vector4::new(1f32, 0., 0., 0.)),
+        skinned(1., 1., 0., [0, 1, 0, 0], Vector4::new(0.5f32, 0.5, 0., 0.)),
+        skinned(0., 0., 1., [0, 3, 0, 0], Vector4::new(1f32, 0., 0., 0.))
+    );
+    let vb = db.new_vertex_buffer(root, "vb", VertexBuffer::new_position_texture_normal_skin(verts.clone(), vec!(0, 1, 2)));
+    let geo = db.new_geometry(root, "geo", Geometry::triangles(vb, 0, 3));
+    let material = db.new_material(root, "material", Material::simple(Vector3::new(1f32, 1., 1.)));
+    let mesh = db.new_object(Some(root), "mesh");
+    db.set_draw(mesh, geo, material);
+
+    // the second vertex is weighted to a joint the skin does not have
+    assert!(!bind_skin(&mut db, mesh, joints.clone()));
+    assert!(db.skin(mesh).is_none());
+
+    // a joint with no weight is never read
+    let mut two = joints.clone();
+    two.push(root);
+    assert!(bind_skin(&mut db, mesh, two));
+
+    // the cpu path skips joints the palette does not have
+    let out = skin_vertices(verts.slice(1, 2), &[Matrix4::identity()]);
+    assert!(out.get(0).position.approx_eq(&Vector3::new(0.5f32, 0.5, 0.)));
+}
+
+#[test]
+fn skin_dropped_by_unfit_draw() {
+    let mut db = TestData::new();
+    let root = db.new_object(None, "character");
+    let joints = new_skeleton(&mut db, root, [
+        Joint::new("hip", None, at(0., 1., 0.))
+    ]);
+
+    let fit = vec!(skinned(0., 1., 0., [0, 0, 0, 0], Vector4::new(1f32, 0., 0., 0.)));
+    let unfit = vec!(skinned(0., 1., 0., [2, 0, 0, 0], Vector4::new(1f32, 0., 0., 0.)));
+    let vb_fit = db.new_vertex_buffer(root, "vb_fit", VertexBuffer::new_position_texture_normal_skin(fit, vec!(0)));
+    let vb_unfit = db.new_vertex_buffer(root, "vb_unfit", VertexBuffer::new_position_texture_normal_skin(unfit, vec!(0)));
+    let geo_fit = db.new_geometry(root, "geo_fit", Geometry::triangles(vb_fit, 0, 1));
+    let geo_unfit = db.new_geometry(root, "geo_unfit", Geometry::triangles(vb_unfit, 0, 1));
+    let material = db.new_material(root, "material", Material::simple(Vector3::new(1f32, 1., 1.)));
+    let mesh = db.new_object(Some(root), "mesh");
+    db.set_draw(mesh, geo_fit, material);
+    assert!(bind_skin(&mut db, mesh, joints.clone()));
+
+    // swapping in geometry that fits keeps the skin
+    db.set_draw(mesh, geo_fit, material);
+    assert!(db.skin(mesh).is_some());
+
+    // the palette would be read past its end, so the skin goes
+    db.set_draw(mesh, geo_unfit, material);
+    assert!(db.get_draw(mesh).unwrap().geometry == geo_unfit);
+    assert!(db.skin(mesh).is_none());
+}
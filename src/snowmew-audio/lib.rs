@@ -0,0 +1,166 @@
+#![crate_id = "github.com/csherratt/snowmew#snowmew-audio:0.1"]
+#![license = "ASL2"]
+#![crate_type = "lib"]
+#![comment = "A positional audio manager for snowmew"]
+
+extern crate snowmew;
+extern crate cow;
+extern crate cgmath;
+extern crate collections;
+extern crate position = "snowmew-position";
+
+use snowmew::common::{ObjectKey, Common, Remap};
+use position::Positions;
+
+use cow::btree::{BTreeMap, BTreeMapIterator};
+
+pub use mixer::{AudioManager, Sink, Voice, spatialize};
+pub use wav::WavSink;
+
+pub mod mixer;
+pub mod wav;
+
+/// A mono clip, samples are in the range -1 to 1.
+#[deriving(Clone, PartialEq)]
+pub struct Sound {
+    pub rate: u32,
+    pub samples: Vec<f32>
+}
+
+impl Sound {
+    pub fn new(rate: u32, samples: Vec<f32>) -> Sound {
+        Sound {
+            rate: rate,
+            samples: samples
+        }
+    }
+
+    /// Length of the clip in seconds.
+    pub fn duration(&self) -> f64 {
+        self.samples.len() as f64 / self.rate as f64
+    }
+}
+
+/// Plays a `Sound` from the position of the object it is attached to.
+#[deriving(Clone, PartialEq)]
+pub struct Source {
+    pub sound: ObjectKey,
+    pub gain: f32,
+    pub looping: bool,
+    pub playing: bool,
+    /// Bumped each time the sound is started over, see `restart_sound`.
+    pub plays: u32
+}
+
+impl Source {
+    pub fn new(sound: ObjectKey) -> Source {
+        Source {
+            sound: sound,
+            gain: 1.,
+            looping: false,
+            playing: true,
+            plays: 0
+        }
+    }
+}
+
+#[deriving(Clone)]
+pub struct AudioData {
+    sounds: BTreeMap<ObjectKey, Sound>,
+    sources: BTreeMap<ObjectKey, Source>,
+    listener: Option<ObjectKey>
+}
+
+impl std::default::Default for AudioData {
+    fn default() -> AudioData { AudioData::new() }
+}
+
+impl AudioData {
+    pub fn new() -> AudioData {
+        AudioData {
+            sounds: BTreeMap::new(),
+            sources: BTreeMap::new(),
+            listener: None
+        }
+    }
+}
+
+pub trait Audio: Common + Positions {
+    fn get_audio<'a>(&'a self) -> &'a AudioData;
+    fn get_audio_mut<'a>(&'a mut self) -> &'a mut AudioData;
+
+    fn new_sound(&mut self, parent: ObjectKey, name: &str, sound: Sound) -> ObjectKey {
+        let oid = self.new_object(Some(parent), name);
+        self.get_audio_mut().sounds.insert(oid, sound);
+        oid
+    }
+
+    fn sound<'a>(&'a self, oid: ObjectKey) -> Option<&'a Sound> {
+        self.get_audio().sounds.find(&oid)
+    }
+
+    fn set_sound_source(&mut self, key: ObjectKey, source: Source) {
+        self.get_audio_mut().sources.insert(key, source);
+    }
+
+    fn sound_source<'a>(&'a self, key: ObjectKey) -> Option<&'a Source> {
+        self.get_audio().sources.find(&key)
+    }
+
+    /// Play the sound of the source of `key` again from the start, even
+    /// if it is still playing or has already played to the end.
+    fn restart_sound(&mut self, key: ObjectKey) {
+        match self.get_audio_mut().sources.find_mut(&key) {
+            Some(source) => {
+                source.playing = true;
+                source.plays += 1;
+            }
+            None => ()
+        }
+    }
+
+    fn sound_source_iter<'a>(&'a self) -> BTreeMapIterator<'a, ObjectKey, Source> {
+        self.get_audio().sources.iter()
+    }
+
+    /// Sources are heard relative to the position of the listener, there is
+    /// no panning or doppler if no listener is set.
+    fn set_listener(&mut self, key: Option<ObjectKey>) {
+        self.get_audio_mut().listener = key;
+    }
+
+    fn listener(&self) -> Option<ObjectKey> {
+        self.get_audio().listener
+    }
+
+    /// Copy the sounds and sources of a prefab to the instance made by
+    /// `Common::instantiate`. A copied source plays the copy of its sound if
+    /// the sound is part of the prefab.
+    fn instantiate_audio(&mut self, remap: &Remap) {
+        for (old, new) in remap.iter() {
+            let sound = self.sound(*old).map(|s| s.clone());
+            match sound {
+                Some(sound) => { self.get_audio_mut().sounds.insert(*new, sound); }
+                None => ()
+            }
+
+            let source = self.sound_source(*old).map(|s| s.clone());
+            match source {
+                Some(mut source) => {
+                    source.sound = remap.remap(source.sound);
+                    self.set_sound_source(*new, source);
+                }
+                None => ()
+            }
+        }
+    }
+
+    /// Drop the sound and source of an object that was removed from `Common`.
+    fn delete_audio(&mut self, key: ObjectKey) {
+        self.get_audio_mut().sounds.remove(&key);
+        self.get_audio_mut().sources.remove(&key);
+        if self.get_audio().listener == Some(key) {
+            self.get_audio_mut().listener = None;
+        }
+    }
+}
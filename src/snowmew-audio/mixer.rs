@@ -0,0 +1,230 @@
+use std::f32::consts::PI;
+use std::io::IoResult;
+
+use collections::TrieMap;
+
+use cgmath::vector::{Vector, EuclideanVector, Vector3, Vector4};
+use cgmath::matrix::{Matrix, Matrix4};
+
+use snowmew::common::{ObjectKey, FrameInfo};
+use snowmew::manager::Manager;
+
+use {Audio, Sound};
+
+/// Where the mixed audio goes. Samples are interleaved stereo, left first.
+pub trait Sink {
+    fn rate(&self) -> u32;
+    fn write(&mut self, samples: &[f32]) -> IoResult<()>;
+}
+
+/// How a single source is heard by the listener.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Voice {
+    pub left: f32,
+    pub right: f32,
+    pub pitch: f32
+}
+
+fn translation(mat: &Matrix4<f32>) -> Vector3<f32> {
+    Vector3::new(mat.w.x, mat.w.y, mat.w.z)
+}
+
+/// Work out the gain of each channel and the doppler shift of a source.
+/// `listener` and `source` are world matrices, the velocities are in world
+/// units per second. The gain falls off with the inverse of the distance
+/// once the source is further than `reference` away.
+pub fn spatialize(listener: &Matrix4<f32>, listener_vel: &Vector3<f32>,
+                  source: &Matrix4<f32>, source_vel: &Vector3<f32>,
+                  gain: f32, reference: f32, speed_of_sound: f32) -> Voice {
+    let offset = translation(source).sub_v(&translation(listener));
+    let dist = offset.length();
+    let gain = gain * reference / dist.max(reference);
+
+    // a source on top of the listener is centered and not shifted
+    if dist < 1e-6 {
+        let g = gain * (PI / 4.).cos();
+        return Voice { left: g, right: g, pitch: 1. };
+    }
+    let dir = offset.div_s(dist);
+
+    // pan using the position of the source in listener space, -x is left
+    let pan = match listener.invert() {
+        Some(inv) => {
+            let local = inv.mul_v(&Vector4::new(offset.x, offset.y, offset.z, 0.));
+            local.x / dist
+        }
+        None => 0.
+    };
+    let angle = (pan.max(-1.).min(1.) + 1.) * PI / 4.;
+
+    // both speeds are positive when the two are closing on each other
+    let listener_speed = listener_vel.dot(&dir);
+    let source_speed = -source_vel.dot(&dir);
+    let limit = speed_of_sound * 0.99;
+    let pitch = (speed_of_sound + listener_speed.max(-limit).min(limit)) /
+                (speed_of_sound - source_speed.max(-limit).min(limit));
+
+    Voice {
+        left: gain * angle.cos(),
+        right: gain * angle.sin(),
+        pitch: pitch
+    }
+}
+
+struct Playback {
+    // the sound and `Source::plays` the cursor belongs to
+    sound: ObjectKey,
+    plays: u32,
+    cursor: f64,
+    last_pos: Option<Vector3<f32>>,
+    finished: bool
+}
+
+fn sample(sound: &Sound, cursor: f64) -> f32 {
+    let idx = cursor as uint;
+    let frac = (cursor - idx as f64) as f32;
+    let a = *sound.samples.get(idx);
+    let b = if idx + 1 < sound.samples.len() { *sound.samples.get(idx + 1) } else { a };
+    a + (b - a) * frac
+}
+
+fn velocity(last: Option<Vector3<f32>>, now: &Vector3<f32>, delta: f64) -> Vector3<f32> {
+    match last {
+        Some(last) if delta > 0. => now.sub_v(&last).div_s(delta as f32),
+        _ => Vector3::new(0f32, 0., 0.)
+    }
+}
+
+/// Mixes every playing source into a `Sink`. Play cursors live here rather
+/// than in the database so that the audio manager is passive.
+pub struct AudioManager<S> {
+    sink: S,
+    pub reference_distance: f32,
+    pub speed_of_sound: f32,
+    voices: TrieMap<Playback>,
+    listener_last: Option<Vector3<f32>>,
+    // fraction of a frame left over from the last mix
+    pending: f64,
+    buffer: Vec<f32>
+}
+
+impl<S: Sink> AudioManager<S> {
+    pub fn new(sink: S) -> AudioManager<S> {
+        AudioManager {
+            sink: sink,
+            reference_distance: 1.,
+            speed_of_sound: 343.,
+            voices: TrieMap::new(),
+            listener_last: None,
+            pending: 0.,
+            buffer: Vec::new()
+        }
+    }
+
+    /// Mix `delta` seconds of audio from the state of `gd`.
+    pub fn mix<A: Audio>(&mut self, gd: &A, delta: f64) -> IoResult<()> {
+        let rate = self.sink.rate();
+        let total = self.pending + delta * rate as f64;
+        let frames = total as uint;
+        self.pending = total - frames as f64;
+
+        self.buffer.truncate(0);
+        self.buffer.grow(frames * 2, &0f32);
+
+        let listener = gd.listener().map(|key| gd.position(key));
+        let listener_vel = match listener {
+            Some(ref mat) => {
+                let pos = translation(mat);
+                let vel = velocity(self.listener_last, &pos, delta);
+                self.listener_last = Some(pos);
+                vel
+            }
+            None => {
+                self.listener_last = None;
+                Vector3::new(0f32, 0., 0.)
+            }
+        };
+
+        // forget the sources that are gone
+        let stale: Vec<uint> = self.voices.iter()
+            .map(|(key, _)| key)
+            .filter(|key| gd.sound_source(*key as ObjectKey).is_none())
+            .collect();
+        for key in stale.iter() {
+            self.voices.remove(key);
+        }
+
+        for (key, source) in gd.sound_source_iter() {
+            // a stopped source starts over the next time it plays
+            if !source.playing {
+                self.voices.remove(&(*key as uint));
+                continue;
+            }
+            let sound = match gd.sound(source.sound) {
+                Some(sound) if sound.samples.len() > 0 => sound,
+                _ => continue
+            };
+
+            let mat = gd.position(*key);
+            let pos = translation(&mat);
+            let (start, last_pos) = match self.voices.find(&(*key as uint)) {
+                Some(v) => (v.sound != source.sound || v.plays != source.plays, v.last_pos),
+                None => (true, None)
+            };
+            if start {
+                self.voices.insert(*key as uint, Playback {
+                    sound: source.sound,
+                    plays: source.plays,
+                    cursor: 0.,
+                    last_pos: last_pos,
+                    finished: false
+                });
+            }
+            let voice = self.voices.find_mut(&(*key as uint)).unwrap();
+            let source_vel = velocity(voice.last_pos, &pos, delta);
+            voice.last_pos = Some(pos);
+            if voice.finished {
+                continue;
+            }
+
+            let v = match listener {
+                Some(ref l) => spatialize(l, &listener_vel, &mat, &source_vel, source.gain,
+                                          self.reference_distance, self.speed_of_sound),
+                None => {
+                    let g = source.gain * (PI / 4.).cos();
+                    Voice { left: g, right: g, pitch: 1. }
+                }
+            };
+
+            let step = v.pitch as f64 * sound.rate as f64 / rate as f64;
+            let len = sound.samples.len() as f64;
+            for i in range(0, frames) {
+                if voice.cursor >= len {
+                    if source.looping {
+                        voice.cursor %= len;
+                    } else {
+                        voice.finished = true;
+                        break;
+                    }
+                }
+                let s = sample(sound, voice.cursor);
+                *self.buffer.get_mut(i * 2) += s * v.left;
+                *self.buffer.get_mut(i * 2 + 1) += s * v.right;
+                voice.cursor += step;
+            }
+        }
+
+        self.sink.write(self.buffer.as_slice())
+    }
+
+    pub fn sink<'a>(&'a self) -> &'a S { &self.sink }
+
+    pub fn unwrap(self) -> S { self.sink }
+}
+
+impl<GD: Audio, S: Sink + Send> Manager<GD> for AudioManager<S> {
+    fn step(&mut self, gd: GD, frame: &FrameInfo) -> Option<GD> {
+        self.mix(&gd, frame.delta).ok().expect("failed to write audio");
+        None
+    }
+}
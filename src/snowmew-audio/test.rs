@@ -0,0 +1,173 @@
+extern crate snowmew;
+extern crate cgmath;
+extern crate position = "snowmew-position";
+extern crate audio = "snowmew-audio";
+
+use std::io::{IoResult, MemWriter, MemReader};
+
+use snowmew::common::{Common, CommonData};
+use position::{Positions, PositionData};
+use audio::{Audio, AudioData, AudioManager, Sink, Sound, Source, WavSink, spatialize};
+
+use cgmath::matrix::{Matrix4, Matrix};
+use cgmath::vector::Vector3;
+
+#[deriving(Clone)]
+struct TestData {
+    common: CommonData,
+    position: PositionData,
+    audio: AudioData
+}
+
+impl TestData {
+    fn new() -> TestData {
+        TestData {
+            common: CommonData::new(),
+            position: PositionData::new(),
+            audio: AudioData::new()
+        }
+    }
+}
+
+impl Common for TestData {
+    fn get_common<'a>(&'a self) -> &'a CommonData { &self.common }
+    fn get_common_mut<'a>(&'a mut self) -> &'a mut CommonData { &mut self.common }
+}
+
+impl Positions for TestData {
+    fn get_position<'a>(&'a self) -> &'a PositionData { &self.position }
+    fn get_position_mut<'a>(&'a mut self) -> &'a mut PositionData { &mut self.position }
+}
+
+impl Audio for TestData {
+    fn get_audio<'a>(&'a self) -> &'a AudioData { &self.audio }
+    fn get_audio_mut<'a>(&'a mut self) -> &'a mut AudioData { &mut self.audio }
+}
+
+fn still() -> Vector3<f32> { Vector3::new(0f32, 0., 0.) }
+
+#[test]
+fn spatialize_pan() {
+    let listener = Matrix4::identity();
+    let right = Matrix4::translate(&Vector3::new(2f32, 0., 0.));
+    let left = Matrix4::translate(&Vector3::new(-2f32, 0., 0.));
+
+    let r = spatialize(&listener, &still(), &right, &still(), 1., 1., 343.);
+    let l = spatialize(&listener, &still(), &left, &still(), 1., 1., 343.);
+    assert!(r.right > r.left);
+    assert!(l.left > l.right);
+    assert!(r.pitch == 1.);
+}
+
+#[test]
+fn spatialize_distance() {
+    let listener = Matrix4::identity();
+    let near = Matrix4::translate(&Vector3::new(0f32, 0., -2.));
+    let far = Matrix4::translate(&Vector3::new(0f32, 0., -8.));
+
+    let n = spatialize(&listener, &still(), &near, &still(), 1., 1., 343.);
+    let f = spatialize(&listener, &still(), &far, &still(), 1., 1., 343.);
+    assert!(n.left > f.left);
+    assert!((n.left - f.left * 4.).abs() < 1e-5);
+}
+
+#[test]
+fn spatialize_doppler() {
+    let listener = Matrix4::identity();
+    let source = Matrix4::translate(&Vector3::new(0f32, 0., -10.));
+
+    let closing = spatialize(&listener, &still(), &source, &Vector3::new(0f32, 0., 20.), 1., 1., 343.);
+    let leaving = spatialize(&listener, &still(), &source, &Vector3::new(0f32, 0., -20.), 1., 1., 343.);
+    assert!(closing.pitch > 1.);
+    assert!(leaving.pitch < 1.);
+}
+
+#[test]
+fn mix_to_wav() {
+    let mut db = TestData::new();
+    let sounds = db.new_object(None, "sounds");
+    let tone = db.new_sound(sounds, "tone", Sound::new(100, Vec::from_elem(100, 0.5f32)));
+
+    let listener = db.new_object(None, "listener");
+    db.set_listener(Some(listener));
+    let speaker = db.new_object(None, "speaker");
+    db.set_displacement(speaker, Vector3::new(1f32, 0., -1.));
+    db.set_sound_source(speaker, Source::new(tone));
+
+    let sink = WavSink::new(MemWriter::new(), 100).unwrap();
+    let mut mixer = AudioManager::new(sink);
+    mixer.mix(&db, 0.5).unwrap();
+    mixer.mix(&db, 0.5).unwrap();
+    // the clip has ended, the rest is silence
+    mixer.mix(&db, 0.5).unwrap();
+
+    let sink = mixer.unwrap();
+    assert!(sink.frames() == 150);
+    let bytes = sink.finish().unwrap().unwrap();
+    assert!(bytes.len() == 44 + 150 * 4);
+
+    let mut r = MemReader::new(bytes);
+    assert!(r.read_exact(4).unwrap().as_slice() == "RIFF".as_bytes());
+    assert!(r.read_le_u32().unwrap() == 36 + 150 * 4);
+    assert!(r.read_exact(4).unwrap().as_slice() == "WAVE".as_bytes());
+
+    // skip to the first sample, the source is to the right
+    r.read_exact(32).unwrap();
+    let left = r.read_le_i16().unwrap();
+    let right = r.read_le_i16().unwrap();
+    assert!(right > left && left > 0);
+
+    // last frame is silent
+    let rest = r.read_to_end().unwrap();
+    let n = rest.len();
+    assert!(rest.slice_from(n - 4).iter().all(|b| *b == 0));
+}
+
+struct Capture {
+    samples: Vec<f32>
+}
+
+impl Sink for Capture {
+    fn rate(&self) -> u32 { 100 }
+
+    fn write(&mut self, samples: &[f32]) -> IoResult<()> {
+        self.samples.push_all(samples);
+        Ok(())
+    }
+}
+
+fn last_frame_silent(mixer: &AudioManager<Capture>) -> bool {
+    let samples = &mixer.sink().samples;
+    let n = samples.len();
+    samples.slice_from(n - 2).iter().all(|s| *s == 0.)
+}
+
+#[test]
+fn retrigger_source() {
+    let mut db = TestData::new();
+    let sounds = db.new_object(None, "sounds");
+    let tone = db.new_sound(sounds, "tone", Sound::new(100, Vec::from_elem(50, 0.5f32)));
+    let speaker = db.new_object(None, "speaker");
+    db.set_sound_source(speaker, Source::new(tone));
+
+    let mut mixer = AudioManager::new(Capture { samples: Vec::new() });
+    mixer.mix(&db, 1.).unwrap();
+    assert!(last_frame_silent(&mixer));
+
+    // started over after it ran to the end
+    db.restart_sound(speaker);
+    mixer.mix(&db, 0.1).unwrap();
+    assert!(!last_frame_silent(&mixer));
+    mixer.mix(&db, 1.).unwrap();
+    assert!(last_frame_silent(&mixer));
+
+    // stopping and playing again also starts over
+    let mut source = db.sound_source(speaker).unwrap().clone();
+    source.playing = false;
+    db.set_sound_source(speaker, source.clone());
+    mixer.mix(&db, 0.1).unwrap();
+    source.playing = true;
+    db.set_sound_source(speaker, source);
+    mixer.mix(&db, 0.1).unwrap();
+    assert!(!last_frame_silent(&mixer));
+}
@@ -0,0 +1,70 @@
+use std::io::{IoResult, Seek, SeekSet, SeekEnd};
+
+use mixer::Sink;
+
+static HEADER_SIZE: u32 = 44;
+
+/// A `Sink` that writes 16 bit stereo PCM to a wav file. The sizes in the
+/// header are filled in by `finish`.
+pub struct WavSink<W> {
+    writer: W,
+    rate: u32,
+    frames: u32
+}
+
+impl<W: Writer + Seek> WavSink<W> {
+    pub fn new(writer: W, rate: u32) -> IoResult<WavSink<W>> {
+        let mut sink = WavSink {
+            writer: writer,
+            rate: rate,
+            frames: 0
+        };
+        try!(sink.write_header());
+        Ok(sink)
+    }
+
+    fn write_header(&mut self) -> IoResult<()> {
+        let data = self.frames * 4;
+        let w = &mut self.writer;
+        try!(w.write_str("RIFF"));
+        try!(w.write_le_u32(HEADER_SIZE - 8 + data));
+        try!(w.write_str("WAVE"));
+
+        try!(w.write_str("fmt "));
+        try!(w.write_le_u32(16));
+        try!(w.write_le_u16(1));             // PCM
+        try!(w.write_le_u16(2));             // channels
+        try!(w.write_le_u32(self.rate));
+        try!(w.write_le_u32(self.rate * 4)); // bytes per second
+        try!(w.write_le_u16(4));             // bytes per frame
+        try!(w.write_le_u16(16));            // bits per sample
+
+        try!(w.write_str("data"));
+        w.write_le_u32(data)
+    }
+
+    /// Number of stereo frames written so far.
+    pub fn frames(&self) -> u32 { self.frames }
+
+    /// Patch the header with the final sizes and return the writer.
+    pub fn finish(self) -> IoResult<W> {
+        let mut sink = self;
+        try!(sink.writer.seek(0, SeekSet));
+        try!(sink.write_header());
+        try!(sink.writer.seek(0, SeekEnd));
+        Ok(sink.writer)
+    }
+}
+
+impl<W: Writer + Seek> Sink for WavSink<W> {
+    fn rate(&self) -> u32 { self.rate }
+
+    fn write(&mut self, samples: &[f32]) -> IoResult<()> {
+        for s in samples.iter() {
+            let s = s.max(-1.).min(1.);
+            try!(self.writer.write_le_i16((s * 32767.) as i16));
+        }
+        self.frames += (samples.len() / 2) as u32;
+        Ok(())
+    }
+}
@@ -1,8 +1,11 @@
 
 use std::default::Default;
-use cgmath::vector::{Vector2, Vector3};
+use std::io::IoResult;
+use cgmath::vector::{Vector2, Vector3, Vector4};
 
 use snowmew::common::ObjectKey;
+use snowmew::snapshot;
+use snowmew::snapshot::Snapshot;
 
 #[deriving(Clone)]
 pub enum Primative {
@@ -44,13 +47,25 @@ pub struct VertexGeoTexNormTan {
     pub tangent: Vector3<f32>,
 }
 
+/// A vertex deformed by up to four joints of a `Skin`. `joints` index the
+/// joints of the skin, the matching `weights` should add up to one.
+#[deriving(Clone)]
+pub struct VertexGeoTexNormSkin {
+    pub position: Vector3<f32>,
+    pub texture: Vector2<f32>,
+    pub normal: Vector3<f32>,
+    pub joints: [u32, ..4],
+    pub weights: Vector4<f32>
+}
+
 #[deriving(Clone)]
 pub enum Vertex {
     Geo(Vec<VertexGeo>),
     GeoTex(Vec<VertexGeoTex>),
     GeoNorm(Vec<VertexGeoNorm>),
     GeoTexNorm(Vec<VertexGeoTexNorm>),
-    GeoTexNormTan(Vec<VertexGeoTexNormTan>)
+    GeoTexNormTan(Vec<VertexGeoTexNormTan>),
+    GeoTexNormSkin(Vec<VertexGeoTexNormSkin>)
 }
 
 impl Default for Vertex {
@@ -197,4 +212,192 @@ impl VertexBuffer {
             index: idx
         }
     }
+
+    pub fn new_position_texture_normal_skin(vert: Vec<VertexGeoTexNormSkin>, idx: Vec<u32>) -> VertexBuffer {
+        VertexBuffer {
+            vertex: GeoTexNormSkin(vert),
+            index: idx
+        }
+    }
+}
+
+impl Snapshot for Primative {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()> {
+        w.write_u8(match *self {
+            Point => 0,
+            Line => 1,
+            Triangle => 2,
+            TriangleAdjacency => 3
+        })
+    }
+
+    fn read_from(r: &mut Reader) -> IoResult<Primative> {
+        match try!(r.read_u8()) {
+            0 => Ok(Point),
+            1 => Ok(Line),
+            2 => Ok(Triangle),
+            3 => Ok(TriangleAdjacency),
+            _ => Err(snapshot::invalid("unknown primative"))
+        }
+    }
+}
+
+impl Snapshot for Geometry {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()> {
+        try!(w.write_le_u32(self.vb));
+        try!(w.write_le_u32(self.count as u32));
+        try!(w.write_le_u32(self.offset as u32));
+        self.prim.write_to(w)
+    }
+
+    fn read_from(r: &mut Reader) -> IoResult<Geometry> {
+        Ok(Geometry {
+            vb: try!(r.read_le_u32()),
+            count: try!(r.read_le_u32()) as uint,
+            offset: try!(r.read_le_u32()) as uint,
+            prim: try!(Snapshot::read_from(r))
+        })
+    }
+}
+
+impl Snapshot for VertexGeo {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()> {
+        self.position.write_to(w)
+    }
+
+    fn read_from(r: &mut Reader) -> IoResult<VertexGeo> {
+        Ok(VertexGeo {
+            position: try!(Snapshot::read_from(r))
+        })
+    }
+}
+
+impl Snapshot for VertexGeoTex {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()> {
+        try!(self.position.write_to(w));
+        self.texture.write_to(w)
+    }
+
+    fn read_from(r: &mut Reader) -> IoResult<VertexGeoTex> {
+        Ok(VertexGeoTex {
+            position: try!(Snapshot::read_from(r)),
+            texture: try!(Snapshot::read_from(r))
+        })
+    }
+}
+
+impl Snapshot for VertexGeoNorm {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()> {
+        try!(self.position.write_to(w));
+        self.normal.write_to(w)
+    }
+
+    fn read_from(r: &mut Reader) -> IoResult<VertexGeoNorm> {
+        Ok(VertexGeoNorm {
+            position: try!(Snapshot::read_from(r)),
+            normal: try!(Snapshot::read_from(r))
+        })
+    }
+}
+
+impl Snapshot for VertexGeoTexNorm {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()> {
+        try!(self.position.write_to(w));
+        try!(self.texture.write_to(w));
+        self.normal.write_to(w)
+    }
+
+    fn read_from(r: &mut Reader) -> IoResult<VertexGeoTexNorm> {
+        Ok(VertexGeoTexNorm {
+            position: try!(Snapshot::read_from(r)),
+            texture: try!(Snapshot::read_from(r)),
+            normal: try!(Snapshot::read_from(r))
+        })
+    }
+}
+
+impl Snapshot for VertexGeoTexNormTan {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()> {
+        try!(self.position.write_to(w));
+        try!(self.texture.write_to(w));
+        try!(self.normal.write_to(w));
+        self.tangent.write_to(w)
+    }
+
+    fn read_from(r: &mut Reader) -> IoResult<VertexGeoTexNormTan> {
+        Ok(VertexGeoTexNormTan {
+            position: try!(Snapshot::read_from(r)),
+            texture: try!(Snapshot::read_from(r)),
+            normal: try!(Snapshot::read_from(r)),
+            tangent: try!(Snapshot::read_from(r))
+        })
+    }
+}
+
+impl Snapshot for VertexGeoTexNormSkin {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()> {
+        try!(self.position.write_to(w));
+        try!(self.texture.write_to(w));
+        try!(self.normal.write_to(w));
+        for j in self.joints.iter() {
+            try!(w.write_le_u32(*j));
+        }
+        self.weights.write_to(w)
+    }
+
+    fn read_from(r: &mut Reader) -> IoResult<VertexGeoTexNormSkin> {
+        let position = try!(Snapshot::read_from(r));
+        let texture = try!(Snapshot::read_from(r));
+        let normal = try!(Snapshot::read_from(r));
+        let mut joints = [0u32, ..4];
+        for j in joints.mut_iter() {
+            *j = try!(r.read_le_u32());
+        }
+        Ok(VertexGeoTexNormSkin {
+            position: position,
+            texture: texture,
+            normal: normal,
+            joints: joints,
+            weights: try!(Snapshot::read_from(r))
+        })
+    }
+}
+
+impl Snapshot for Vertex {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()> {
+        match *self {
+            Geo(ref v) => { try!(w.write_u8(0)); v.write_to(w) }
+            GeoTex(ref v) => { try!(w.write_u8(1)); v.write_to(w) }
+            GeoNorm(ref v) => { try!(w.write_u8(2)); v.write_to(w) }
+            GeoTexNorm(ref v) => { try!(w.write_u8(3)); v.write_to(w) }
+            GeoTexNormTan(ref v) => { try!(w.write_u8(4)); v.write_to(w) }
+            GeoTexNormSkin(ref v) => { try!(w.write_u8(5)); v.write_to(w) }
+        }
+    }
+
+    fn read_from(r: &mut Reader) -> IoResult<Vertex> {
+        match try!(r.read_u8()) {
+            0 => Ok(Geo(try!(Snapshot::read_from(r)))),
+            1 => Ok(GeoTex(try!(Snapshot::read_from(r)))),
+            2 => Ok(GeoNorm(try!(Snapshot::read_from(r)))),
+            3 => Ok(GeoTexNorm(try!(Snapshot::read_from(r)))),
+            4 => Ok(GeoTexNormTan(try!(Snapshot::read_from(r)))),
+            5 => Ok(GeoTexNormSkin(try!(Snapshot::read_from(r)))),
+            _ => Err(snapshot::invalid("unknown vertex format"))
+        }
+    }
+}
+
+impl Snapshot for VertexBuffer {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()> {
+        try!(self.vertex.write_to(w));
+        self.index.write_to(w)
+    }
+
+    fn read_from(r: &mut Reader) -> IoResult<VertexBuffer> {
+        Ok(VertexBuffer {
+            vertex: try!(Snapshot::read_from(r)),
+            index: try!(Snapshot::read_from(r))
+        })
+    }
 }
@@ -10,18 +10,24 @@ extern crate collision;
 extern crate image = "stb_image";
 
 use std::slice;
+use std::io::IoResult;
 
 use cgmath::vector::{Vector3, Vector2};
 use cgmath::point::Point3;
 use collision::sphere::Sphere;
 
 use cow::btree::{BTreeMapIterator, BTreeMap};
-use snowmew::common::{Common, ObjectKey};
+use snowmew::common::{Common, ObjectKey, Remap};
+use snowmew::snapshot;
+use snowmew::snapshot::{Snapshot, write_map, read_map};
+use snowmew::diff::{Diff, diff_maps, diff_keys};
+use snowmew::event::{DrawableSet, SkinSet, VertexBufferAdded, GeometryAdded, MaterialAdded, TextureAdded, LightChanged};
 
 pub use geometry::{Geometry, VertexBuffer};
 pub use material::Material;
 pub use texture::Texture;
 pub use light::Light;
+pub use skin::Skin;
 
 pub mod geometry;
 pub mod material;
@@ -29,6 +35,7 @@ pub mod default;
 pub mod texture;
 pub mod texture_atlas;
 pub mod light;
+pub mod skin;
 
 #[deriving(Clone, Default, Eq, PartialEq)]
 pub struct Drawable {
@@ -70,7 +77,12 @@ pub struct GraphicsData {
     texture:            BTreeMap<ObjectKey, Texture>,
     texture_to_atlas:   BTreeMap<ObjectKey, (uint, uint)>,
     atlases:            Vec<texture_atlas::Atlas>,
-    lights:             BTreeMap<ObjectKey, light::Light>
+    lights:             BTreeMap<ObjectKey, light::Light>,
+    skins:              BTreeMap<ObjectKey, Skin>
+}
+
+impl std::default::Default for GraphicsData {
+    fn default() -> GraphicsData { GraphicsData::new() }
 }
 
 impl GraphicsData {
@@ -86,11 +98,142 @@ impl GraphicsData {
             atlases: Vec::new(),
             texture_to_atlas: BTreeMap::new(),
             material_idx_last: 0,
-            sphere: BTreeMap::new()
+            sphere: BTreeMap::new(),
+            skins: BTreeMap::new()
+        }
+    }
+
+    pub fn diff(old: &GraphicsData, new: &GraphicsData) -> GraphicsDiff {
+        GraphicsDiff {
+            drawable: diff_maps(old.draw.iter(), new.draw.iter(), |a, b| a == b),
+            geometry: diff_keys(old.geometry.iter(), new.geometry.iter()),
+            vertex: diff_keys(old.vertex.iter(), new.vertex.iter()),
+            material: diff_maps(old.material.iter(), new.material.iter(), |a, b| a == b),
+            texture: diff_keys(old.texture.iter(), new.texture.iter()),
+            light: diff_maps(old.lights.iter(), new.lights.iter(), |a, b| a == b),
+            skin: diff_maps(old.skins.iter(), new.skins.iter(), |a, b| a == b)
         }
     }
 }
 
+/// The changes to each of the maps in `GraphicsData` between two generations.
+/// Vertex buffers, geometry and textures can not be modified in place so
+/// only additions and removals are reported for them.
+#[deriving(Clone, Default, Show)]
+pub struct GraphicsDiff {
+    pub drawable: Diff,
+    pub geometry: Diff,
+    pub vertex: Diff,
+    pub material: Diff,
+    pub texture: Diff,
+    pub light: Diff,
+    pub skin: Diff
+}
+
+impl GraphicsDiff {
+    pub fn is_empty(&self) -> bool {
+        self.drawable.is_empty() &&
+        self.geometry.is_empty() &&
+        self.vertex.is_empty() &&
+        self.material.is_empty() &&
+        self.texture.is_empty() &&
+        self.light.is_empty() &&
+        self.skin.is_empty()
+    }
+}
+
+impl Snapshot for Drawable {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()> {
+        try!(w.write_le_u32(self.geometry));
+        w.write_le_u32(self.material)
+    }
+
+    fn read_from(r: &mut Reader) -> IoResult<Drawable> {
+        Ok(Drawable {
+            geometry: try!(r.read_le_u32()),
+            material: try!(r.read_le_u32())
+        })
+    }
+}
+
+impl Snapshot for GraphicsData {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()> {
+        try!(snapshot::write_tag(w, snapshot::TAG_GRAPHICS));
+        try!(write_map(w, &self.draw));
+        try!(write_map(w, &self.geometry));
+        try!(write_map(w, &self.vertex));
+
+        try!(w.write_le_u32(self.sphere.len() as u32));
+        for (oid, sphere) in self.sphere.iter() {
+            try!(w.write_le_u32(*oid));
+            try!(sphere.center.write_to(w));
+            try!(w.write_le_f32(sphere.radius));
+        }
+
+        try!(write_map(w, &self.material));
+        try!(w.write_le_i32(self.material_idx_last));
+        try!(w.write_le_u32(self.material_index.len() as u32));
+        for (oid, idx) in self.material_index.iter() {
+            try!(w.write_le_u32(*oid));
+            try!(w.write_le_i32(*idx));
+        }
+
+        try!(write_map(w, &self.texture));
+        try!(w.write_le_u32(self.texture_to_atlas.len() as u32));
+        for (oid, &(atlas, layer)) in self.texture_to_atlas.iter() {
+            try!(w.write_le_u32(*oid));
+            try!(w.write_le_u32(atlas as u32));
+            try!(w.write_le_u32(layer as u32));
+        }
+        try!(self.atlases.write_to(w));
+
+        try!(write_map(w, &self.lights));
+        write_map(w, &self.skins)
+    }
+
+    fn read_from(r: &mut Reader) -> IoResult<GraphicsData> {
+        Snapshot::read_version(r, snapshot::VERSION)
+    }
+
+    fn read_version(r: &mut Reader, version: u32) -> IoResult<GraphicsData> {
+        try!(snapshot::expect_tag(r, snapshot::TAG_GRAPHICS));
+        let mut gd = GraphicsData::new();
+        gd.draw = try!(read_map(r));
+        gd.geometry = try!(read_map(r));
+        gd.vertex = try!(read_map(r));
+
+        for _ in range(0, try!(r.read_le_u32())) {
+            let oid = try!(r.read_le_u32());
+            let center = try!(Snapshot::read_from(r));
+            let radius = try!(r.read_le_f32());
+            gd.sphere.insert(oid, Sphere::new(center, radius));
+        }
+
+        gd.material = try!(read_map(r));
+        gd.material_idx_last = try!(r.read_le_i32());
+        for _ in range(0, try!(r.read_le_u32())) {
+            let oid = try!(r.read_le_u32());
+            let idx = try!(r.read_le_i32());
+            gd.material_index.insert(oid, idx);
+        }
+
+        gd.texture = try!(read_map(r));
+        for _ in range(0, try!(r.read_le_u32())) {
+            let oid = try!(r.read_le_u32());
+            let atlas = try!(r.read_le_u32()) as uint;
+            let layer = try!(r.read_le_u32()) as uint;
+            gd.texture_to_atlas.insert(oid, (atlas, layer));
+        }
+        gd.atlases = try!(Snapshot::read_from(r));
+
+        gd.lights = try!(read_map(r));
+        if version >= 4 {
+            gd.skins = try!(read_map(r));
+        }
+        Ok(gd)
+    }
+}
+
 pub trait Graphics: Common {
     fn get_graphics<'a>(&'a self) -> &'a GraphicsData;
     fn get_graphics_mut<'a>(&'a mut self) -> &'a mut GraphicsData;
@@ -102,6 +245,7 @@ pub trait Graphics: Common {
     fn new_vertex_buffer(&mut self, parent: ObjectKey, name: &str, vb: VertexBuffer) -> ObjectKey {
         let oid = self.new_object(Some(parent), name);
         self.get_graphics_mut().vertex.insert(oid, vb);
+        self.log_event(VertexBufferAdded(oid));
         oid
     }
 
@@ -116,6 +260,7 @@ pub trait Graphics: Common {
             .expect("Could not create sphere collider");
         println!("sphere: {}", sphere);
         self.get_graphics_mut().sphere.insert(oid, sphere);
+        self.log_event(GeometryAdded(oid));
         oid
     }
 
@@ -143,6 +288,7 @@ pub trait Graphics: Common {
         let idx = self.get_graphics().material_idx_last;
         self.get_graphics_mut().material_idx_last += 1;
         self.get_graphics_mut().material_index.insert(obj, idx);
+        self.log_event(MaterialAdded(obj));
         obj
     }
 
@@ -150,13 +296,27 @@ pub trait Graphics: Common {
         self.get_graphics().material.iter()
     }
 
+    /// Draw `oid` with `geo` and `material`. If `oid` is skinned and the
+    /// new geometry is weighted to joints the skin does not have, the skin
+    /// is dropped and the drawable is drawn unskinned.
     fn set_draw(&mut self, oid: ObjectKey, geo: ObjectKey, material: ObjectKey) {
         let draw = Drawable {
             geometry: geo,
             material: material
         };
 
+        let fits = match self.skin(oid) {
+            Some(skin) => skin_fits(self, geo, skin),
+            None => true
+        };
+
         self.get_graphics_mut().draw.insert(oid, draw.clone());
+        self.log_event(DrawableSet(oid));
+
+        if !fits {
+            self.get_graphics_mut().skins.remove(&oid);
+            self.log_event(SkinSet(oid));
+        }
     }
 
     fn get_draw(&self, oid: ObjectKey) -> Option<Drawable> {
@@ -174,6 +334,10 @@ pub trait Graphics: Common {
         self.get_graphics().draw.iter()
     }
 
+    fn vertex_buffer<'a>(&'a self, oid: ObjectKey) -> Option<&'a VertexBuffer> {
+        self.get_graphics().vertex.find(&oid)
+    }
+
     fn vertex_buffer_iter<'a>(&'a self) -> BTreeMapIterator<'a, ObjectKey, VertexBuffer> {
         self.get_graphics().vertex.iter()
     }
@@ -208,23 +372,8 @@ pub trait Graphics: Common {
 
     fn new_texture(&mut self, parent: ObjectKey, name: &str, texture: Texture) -> ObjectKey {
         let oid = self.new_object(Some(parent), name);
-        let mut found = None;
-        for (idx, atlas) in self.get_graphics_mut().atlases.mut_iter().enumerate() {
-            if atlas.check_texture(&texture) {
-                found = Some((idx, atlas.add_texture(oid, &texture)));
-                break;
-            }
-        }
-        if found.is_none() {
-            let mut atlas = texture_atlas::Atlas::new(texture.width(), texture.height(), texture.depth());
-            let idx = atlas.add_texture(oid, &texture);
-            let idx_atlas = self.get_graphics().atlases.len();
-            self.get_graphics_mut().atlases.push(atlas);
-            found = Some((idx_atlas, idx))
-        }
-
-        self.get_graphics_mut().texture.insert(oid, texture);
-        self.get_graphics_mut().texture_to_atlas.insert(oid, found.unwrap());
+        insert_texture(self.get_graphics_mut(), oid, texture);
+        self.log_event(TextureAdded(oid));
         oid
     }
 
@@ -247,6 +396,7 @@ pub trait Graphics: Common {
     fn new_light(&mut self, parent: ObjectKey, name: &str, light: Light) -> ObjectKey {
         let oid = self.new_object(Some(parent), name);
         self.get_graphics_mut().lights.insert(oid, light);
+        self.log_event(LightChanged(oid));
         oid
     }
 
@@ -257,6 +407,155 @@ pub trait Graphics: Common {
     fn light_iter<'a>(&'a self) -> BTreeMapIterator<'a, ObjectKey, Light> {
         self.get_graphics().lights.iter()
     }
+
+    /// Deform the drawable `oid` by the joints of `skin`, its vertex buffer
+    /// should be `GeoTexNormSkin`. Fails if a vertex is weighted to a joint
+    /// that the skin does not have.
+    fn set_skin(&mut self, oid: ObjectKey, skin: Skin) -> bool {
+        let fits = match self.get_draw(oid) {
+            Some(draw) => skin_fits(self, draw.geometry, &skin),
+            None => true
+        };
+        if !fits {
+            return false;
+        }
+
+        self.get_graphics_mut().skins.insert(oid, skin);
+        self.log_event(SkinSet(oid));
+        true
+    }
+
+    fn skin<'a>(&'a self, oid: ObjectKey) -> Option<&'a Skin> {
+        self.get_graphics().skins.find(&oid)
+    }
+
+    fn skin_iter<'a>(&'a self) -> BTreeMapIterator<'a, ObjectKey, Skin> {
+        self.get_graphics().skins.iter()
+    }
+
+    /// Copy the graphics rows of a prefab to the instance made by
+    /// `Common::instantiate`. Drawables and geometry that point at objects
+    /// inside the prefab are pointed at the copies, anything outside of it
+    /// is shared.
+    fn instantiate_graphics(&mut self, remap: &Remap) {
+        for (old, new) in remap.iter() {
+            let (old, new) = (*old, *new);
+
+            let draw = self.get_draw(old);
+            match draw {
+                Some(d) => self.set_draw(new, remap.remap(d.geometry), remap.remap(d.material)),
+                None => ()
+            }
+
+            let vb = self.vertex_buffer(old).map(|vb| vb.clone());
+            match vb {
+                Some(vb) => {
+                    self.get_graphics_mut().vertex.insert(new, vb);
+                    self.log_event(VertexBufferAdded(new));
+                }
+                None => ()
+            }
+
+            let geo = self.geometry(old).map(|g| g.clone());
+            match geo {
+                Some(mut geo) => {
+                    geo.vb = remap.remap(geo.vb);
+                    let sphere = self.sphere(old);
+                    self.get_graphics_mut().geometry.insert(new, geo);
+                    self.get_graphics_mut().sphere.insert(new, sphere);
+                    self.log_event(GeometryAdded(new));
+                }
+                None => ()
+            }
+
+            let material = self.material(old).map(|m| m.clone());
+            match material {
+                Some(material) => {
+                    let idx = self.get_graphics().material_idx_last;
+                    self.get_graphics_mut().material_idx_last += 1;
+                    self.get_graphics_mut().material.insert(new, material);
+                    self.get_graphics_mut().material_index.insert(new, idx);
+                    self.log_event(MaterialAdded(new));
+                }
+                None => ()
+            }
+
+            let texture = self.get_texture(old).map(|t| t.clone());
+            match texture {
+                Some(texture) => {
+                    insert_texture(self.get_graphics_mut(), new, texture);
+                    self.log_event(TextureAdded(new));
+                }
+                None => ()
+            }
+
+            let light = self.get_light(old).map(|l| l.clone());
+            match light {
+                Some(light) => {
+                    self.get_graphics_mut().lights.insert(new, light);
+                    self.log_event(LightChanged(new));
+                }
+                None => ()
+            }
+
+            let skin = self.skin(old).map(|s| s.clone());
+            match skin {
+                Some(mut skin) => {
+                    skin.remap(remap);
+                    self.set_skin(new, skin);
+                }
+                None => ()
+            }
+        }
+    }
+
+    /// Drop every graphics row owned by an object that was removed from `Common`.
+    fn delete_graphics(&mut self, oid: ObjectKey) {
+        let gd = self.get_graphics_mut();
+        gd.draw.remove(&oid);
+        gd.geometry.remove(&oid);
+        gd.sphere.remove(&oid);
+        gd.vertex.remove(&oid);
+        gd.material.remove(&oid);
+        gd.material_index.remove(&oid);
+        gd.texture.remove(&oid);
+        match gd.texture_to_atlas.pop(&oid) {
+            Some((atlas, _)) => gd.atlases.get_mut(atlas).remove_texture(oid),
+            None => ()
+        }
+        gd.lights.remove(&oid);
+        gd.skins.remove(&oid);
+    }
+}
+
+// True if the vertices of `geo` are only weighted to joints of `skin`.
+fn skin_fits<G: Graphics>(g: &G, geo: ObjectKey, skin: &Skin) -> bool {
+    let vb = g.geometry(geo).and_then(|geo| g.vertex_buffer(geo.vb));
+    match vb {
+        Some(&VertexBuffer{vertex: geometry::GeoTexNormSkin(ref v), ..}) => skin.fits(v.as_slice()),
+        _ => true
+    }
+}
+
+// Add a texture to the first atlas it fits in, or to a new atlas.
+fn insert_texture(gd: &mut GraphicsData, oid: ObjectKey, texture: Texture) {
+    let mut found = None;
+    for (idx, atlas) in gd.atlases.mut_iter().enumerate() {
+        if atlas.check_texture(&texture) {
+            found = Some((idx, atlas.add_texture(oid, &texture)));
+            break;
+        }
+    }
+    if found.is_none() {
+        let mut atlas = texture_atlas::Atlas::new(texture.width(), texture.height(), texture.depth());
+        let idx = atlas.add_texture(oid, &texture);
+        let idx_atlas = gd.atlases.len();
+        gd.atlases.push(atlas);
+        found = Some((idx_atlas, idx))
+    }
+
+    gd.texture.insert(oid, texture);
+    gd.texture_to_atlas.insert(oid, found.unwrap());
 }
 
 pub struct VertexBufferIter<'a> {
@@ -292,6 +591,10 @@ impl<'a> Iterator<(u32, &'a Vector3<f32>, Option<&'a Vector2<f32>>, Option<&'a V
                 let v = v.get(*idx as uint);
                 Some((*idx, &v.position, Some(&v.texture), Some(&v.normal)))
             }
+            geometry::GeoTexNormSkin(ref v) => {
+                let v = v.get(*idx as uint);
+                Some((*idx, &v.position, Some(&v.texture), Some(&v.normal)))
+            }
         }
     }
 }
\ No newline at end of file
@@ -1,9 +1,13 @@
 
 use std::default::Default;
+use std::io::IoResult;
 use cgmath::vector::Vector3;
 
+use snowmew::snapshot;
+use snowmew::snapshot::Snapshot;
 
-#[deriving(Clone)]
+
+#[deriving(Clone, PartialEq)]
 pub struct Point {
     color: Vector3<f32>,
     intensity: f32
@@ -24,7 +28,7 @@ impl Point {
     pub fn intensity(&self) -> f32 {self.intensity.clone()}
 }
 
-#[deriving(Clone)]
+#[deriving(Clone, PartialEq)]
 pub struct Directional {
     normal: Vector3<f32>,
     color: Vector3<f32>,
@@ -48,7 +52,7 @@ impl Directional {
     pub fn intensity(&self) -> f32 {self.intensity.clone()}
 }
 
-#[deriving(Clone)]
+#[deriving(Clone, PartialEq)]
 pub enum Light {
     Directional(Directional),
     Point(Point)
@@ -61,4 +65,39 @@ impl Default for Light {
             intensity: 0.
         })
     }
-}
\ No newline at end of file
+}
+
+impl Snapshot for Light {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()> {
+        match *self {
+            Directional(ref d) => {
+                try!(w.write_u8(0));
+                try!(d.normal.write_to(w));
+                try!(d.color.write_to(w));
+                w.write_le_f32(d.intensity)
+            }
+            Point(ref p) => {
+                try!(w.write_u8(1));
+                try!(p.color.write_to(w));
+                w.write_le_f32(p.intensity)
+            }
+        }
+    }
+
+    fn read_from(r: &mut Reader) -> IoResult<Light> {
+        match try!(r.read_u8()) {
+            0 => {
+                let normal = try!(Snapshot::read_from(r));
+                let color = try!(Snapshot::read_from(r));
+                let intensity = try!(r.read_le_f32());
+                Ok(Directional(Directional::new(normal, color, intensity)))
+            }
+            1 => {
+                let color = try!(Snapshot::read_from(r));
+                let intensity = try!(r.read_le_f32());
+                Ok(Point(Point::new(color, intensity)))
+            }
+            _ => Err(snapshot::invalid("unknown light type"))
+        }
+    }
+}
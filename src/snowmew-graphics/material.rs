@@ -1,10 +1,12 @@
 use std::default::Default;
+use std::io::IoResult;
 
 use cgmath::vector::Vector3;
 
 use snowmew::ObjectKey;
+use snowmew::snapshot::Snapshot;
 
-#[deriving(Clone)]
+#[deriving(Clone, PartialEq)]
 pub struct Material {
     ka: Vector3<f32>,
     kd: Vector3<f32>,
@@ -110,4 +112,50 @@ impl Material {
     pub fn ni(&self) -> f32 {self.ni}
     pub fn set_ni(&mut self, v: f32) {self.ni = v}
 
-}
\ No newline at end of file
+}
+
+impl Snapshot for Material {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()> {
+        try!(self.ka.write_to(w));
+        try!(self.kd.write_to(w));
+        try!(self.ks.write_to(w));
+        try!(self.ke.write_to(w));
+        try!(self.tf.write_to(w));
+        try!(w.write_le_f32(self.ns));
+        try!(w.write_le_f32(self.ni));
+        try!(w.write_le_f32(self.tr));
+        try!(w.write_le_f32(self.d));
+        try!(w.write_le_i32(self.illum as i32));
+        try!(self.map_ka.write_to(w));
+        try!(self.map_kd.write_to(w));
+        try!(self.map_ks.write_to(w));
+        try!(self.map_ke.write_to(w));
+        try!(self.map_ns.write_to(w));
+        try!(self.map_d.write_to(w));
+        try!(self.map_bump.write_to(w));
+        self.map_refl.write_to(w)
+    }
+
+    fn read_from(r: &mut Reader) -> IoResult<Material> {
+        Ok(Material {
+            ka: try!(Snapshot::read_from(r)),
+            kd: try!(Snapshot::read_from(r)),
+            ks: try!(Snapshot::read_from(r)),
+            ke: try!(Snapshot::read_from(r)),
+            tf: try!(Snapshot::read_from(r)),
+            ns: try!(r.read_le_f32()),
+            ni: try!(r.read_le_f32()),
+            tr: try!(r.read_le_f32()),
+            d: try!(r.read_le_f32()),
+            illum: try!(r.read_le_i32()) as int,
+            map_ka: try!(Snapshot::read_from(r)),
+            map_kd: try!(Snapshot::read_from(r)),
+            map_ks: try!(Snapshot::read_from(r)),
+            map_ke: try!(Snapshot::read_from(r)),
+            map_ns: try!(Snapshot::read_from(r)),
+            map_d: try!(Snapshot::read_from(r)),
+            map_bump: try!(Snapshot::read_from(r)),
+            map_refl: try!(Snapshot::read_from(r))
+        })
+    }
+}
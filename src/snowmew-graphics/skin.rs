@@ -0,0 +1,98 @@
+use std::io::IoResult;
+
+use cgmath::matrix::{Matrix, Matrix4};
+use cgmath::vector::{Vector, EuclideanVector, Vector3, Vector4};
+
+use snowmew::common::{ObjectKey, Remap};
+use snowmew::snapshot;
+use snowmew::snapshot::Snapshot;
+
+use geometry::{VertexGeoTexNorm, VertexGeoTexNormSkin};
+
+/// Binds a drawable to the joints that deform it. `inverse_bind` holds the
+/// inverse of the world matrix of each joint in the pose the mesh was
+/// modelled in, so a joint that has not moved leaves its vertices where
+/// they are. A skinned mesh is placed by its joints alone, the world
+/// matrix of the drawable itself is not used.
+#[deriving(Clone, PartialEq)]
+pub struct Skin {
+    pub joints: Vec<ObjectKey>,
+    pub inverse_bind: Vec<Matrix4<f32>>
+}
+
+impl Skin {
+    pub fn new(joints: Vec<ObjectKey>, inverse_bind: Vec<Matrix4<f32>>) -> Skin {
+        assert!(joints.len() == inverse_bind.len());
+        Skin {
+            joints: joints,
+            inverse_bind: inverse_bind
+        }
+    }
+
+    pub fn len(&self) -> uint { self.joints.len() }
+
+    /// True if every joint that `verts` are weighted to is part of the skin.
+    pub fn fits(&self, verts: &[VertexGeoTexNormSkin]) -> bool {
+        verts.iter().all(|v| {
+            let weights = [v.weights.x, v.weights.y, v.weights.z, v.weights.w];
+            v.joints.iter().zip(weights.iter()).all(|(j, w)| *w == 0. || (*j as uint) < self.len())
+        })
+    }
+
+    /// Point the joints that are inside a prefab at the copies.
+    pub fn remap(&mut self, remap: &Remap) {
+        for j in self.joints.mut_iter() {
+            *j = remap.remap(*j);
+        }
+    }
+}
+
+/// Deform a vertex by the palette of its skin, this is the same sum the
+/// geometry pass does on the gpu. Each palette matrix is the world matrix
+/// of a joint times its inverse bind matrix, joints past the end of the
+/// palette are skipped.
+pub fn skin_vertex(v: &VertexGeoTexNormSkin, palette: &[Matrix4<f32>]) -> VertexGeoTexNorm {
+    let pos = Vector4::new(v.position.x, v.position.y, v.position.z, 1f32);
+    let norm = Vector4::new(v.normal.x, v.normal.y, v.normal.z, 0f32);
+    let weights = [v.weights.x, v.weights.y, v.weights.z, v.weights.w];
+
+    let mut out_pos = Vector4::new(0f32, 0., 0., 0.);
+    let mut out_norm = Vector4::new(0f32, 0., 0., 0.);
+    for (joint, weight) in v.joints.iter().zip(weights.iter()) {
+        if *weight == 0. || *joint as uint >= palette.len() {
+            continue;
+        }
+        let mat = &palette[*joint as uint];
+        out_pos = out_pos.add_v(&mat.mul_v(&pos).mul_s(*weight));
+        out_norm = out_norm.add_v(&mat.mul_v(&norm).mul_s(*weight));
+    }
+
+    let normal = Vector3::new(out_norm.x, out_norm.y, out_norm.z);
+    VertexGeoTexNorm {
+        position: Vector3::new(out_pos.x, out_pos.y, out_pos.z),
+        texture: v.texture,
+        normal: if normal.length() > 0. { normal.normalize() } else { normal }
+    }
+}
+
+/// Skin a whole vertex buffer on the cpu, used as a reference for the gpu
+/// path and by tools that need the deformed mesh.
+pub fn skin_vertices(verts: &[VertexGeoTexNormSkin], palette: &[Matrix4<f32>]) -> Vec<VertexGeoTexNorm> {
+    verts.iter().map(|v| skin_vertex(v, palette)).collect()
+}
+
+impl Snapshot for Skin {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()> {
+        try!(self.joints.write_to(w));
+        self.inverse_bind.write_to(w)
+    }
+
+    fn read_from(r: &mut Reader) -> IoResult<Skin> {
+        let joints: Vec<ObjectKey> = try!(Snapshot::read_from(r));
+        let inverse_bind: Vec<Matrix4<f32>> = try!(Snapshot::read_from(r));
+        if joints.len() != inverse_bind.len() {
+            return Err(snapshot::invalid("skin has a different number of joints and bind matrices"));
+        }
+        Ok(Skin::new(joints, inverse_bind))
+    }
+}
@@ -1,4 +1,7 @@
 use std::default;
+use std::io::IoResult;
+
+use snowmew::snapshot::Snapshot;
 
 #[deriving(Clone)]
 pub struct Texture {
@@ -54,4 +57,23 @@ impl Texture {
     pub fn flip(&mut self) {
         flip(&mut self.data, self.height, self.width, self.depth);
     }
-}
\ No newline at end of file
+}
+
+impl Snapshot for Texture {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()> {
+        try!(w.write_le_u32(self.width as u32));
+        try!(w.write_le_u32(self.height as u32));
+        try!(w.write_le_u32(self.depth as u32));
+        try!(w.write_le_u32(self.data.len() as u32));
+        w.write(self.data.as_slice())
+    }
+
+    fn read_from(r: &mut Reader) -> IoResult<Texture> {
+        let width = try!(r.read_le_u32()) as uint;
+        let height = try!(r.read_le_u32()) as uint;
+        let depth = try!(r.read_le_u32()) as uint;
+        let len = try!(r.read_le_u32()) as uint;
+        let data = try!(r.read_exact(len));
+        Ok(Texture::new(width, height, depth, data))
+    }
+}
@@ -1,7 +1,11 @@
 
+use std::io::IoResult;
+
 use cow::btree::{BTreeMap, BTreeMapIterator};
 
 use snowmew::common::ObjectKey;
+use snowmew::snapshot;
+use snowmew::snapshot::Snapshot;
 
 use Texture;
 
@@ -49,9 +53,49 @@ impl Atlas {
         layer
     }
 
+    pub fn remove_texture(&mut self, id: ObjectKey) {
+        match self.layers.pop(&id) {
+            Some(layer) => self.free_layers.push(layer),
+            None => ()
+        }
+    }
+
     pub fn texture_iter<'a>(&'a self) -> BTreeMapIterator<'a, ObjectKey, uint> {
         self.layers.iter()
     }
 
     pub fn max_layers(&self) -> uint {self.max_layers}
-}
\ No newline at end of file
+}
+
+impl Snapshot for Atlas {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()> {
+        try!(w.write_le_u32(self.width as u32));
+        try!(w.write_le_u32(self.height as u32));
+        try!(w.write_le_u32(self.depth as u32));
+        try!(w.write_le_u32(self.layers.len() as u32));
+        for (id, layer) in self.layers.iter() {
+            try!(w.write_le_u32(*id));
+            try!(w.write_le_u32(*layer as u32));
+        }
+        Ok(())
+    }
+
+    // the free list is not stored, it is rebuilt from the used layers
+    fn read_from(r: &mut Reader) -> IoResult<Atlas> {
+        let width = try!(r.read_le_u32()) as uint;
+        let height = try!(r.read_le_u32()) as uint;
+        let depth = try!(r.read_le_u32()) as uint;
+        let mut atlas = Atlas::new(width, height, depth);
+
+        for _ in range(0, try!(r.read_le_u32())) {
+            let id = try!(r.read_le_u32());
+            let layer = try!(r.read_le_u32()) as uint;
+            match atlas.free_layers.iter().position(|l| *l == layer) {
+                Some(idx) => { atlas.free_layers.swap_remove(idx); },
+                None => return Err(snapshot::invalid("texture atlas layer used twice"))
+            }
+            atlas.layers.insert(id, layer);
+        }
+        Ok(atlas)
+    }
+}
@@ -0,0 +1,336 @@
+#![crate_id = "github.com/csherratt/snowmew#snowmew-net:0.1"]
+#![license = "ASL2"]
+#![crate_type = "lib"]
+#![comment = "A network replication manager for snowmew"]
+
+extern crate snowmew;
+extern crate cow;
+extern crate cgmath;
+extern crate position = "snowmew-position";
+
+use std::io::IoResult;
+
+use cgmath::transform::Decomposed;
+use cgmath::quaternion::Quaternion;
+use cgmath::vector::Vector3;
+
+use cow::btree::BTreeMap;
+
+use snowmew::common::{Common, CommonData, ObjectKey};
+use snowmew::snapshot;
+use snowmew::snapshot::Snapshot;
+use position::{Positions, PositionData};
+
+/// A single change to the replicated subtree. Keys are always the keys of
+/// the peer that owns the subtree, the key `0` refers to the root of the
+/// subtree.
+#[deriving(Clone)]
+pub enum Message {
+    Create(ObjectKey, ObjectKey, String),
+    Update(ObjectKey, ObjectKey, String),
+    Delete(ObjectKey),
+    Location(ObjectKey, Decomposed<f32, Vector3<f32>, Quaternion<f32>>),
+    Commit(u32)
+}
+
+impl Snapshot for Message {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()> {
+        match *self {
+            Create(key, parent, ref name) => {
+                try!(w.write_u8(0));
+                try!(w.write_le_u32(key));
+                try!(w.write_le_u32(parent));
+                snapshot::write_string(w, name.as_slice())
+            }
+            Update(key, parent, ref name) => {
+                try!(w.write_u8(1));
+                try!(w.write_le_u32(key));
+                try!(w.write_le_u32(parent));
+                snapshot::write_string(w, name.as_slice())
+            }
+            Delete(key) => {
+                try!(w.write_u8(2));
+                w.write_le_u32(key)
+            }
+            Location(key, ref delta) => {
+                try!(w.write_u8(3));
+                try!(w.write_le_u32(key));
+                delta.write_to(w)
+            }
+            Commit(gen) => {
+                try!(w.write_u8(4));
+                w.write_le_u32(gen)
+            }
+        }
+    }
+
+    fn read_from(r: &mut Reader) -> IoResult<Message> {
+        match try!(r.read_u8()) {
+            0 => {
+                let key = try!(r.read_le_u32());
+                let parent = try!(r.read_le_u32());
+                Ok(Create(key, parent, try!(snapshot::read_string(r))))
+            }
+            1 => {
+                let key = try!(r.read_le_u32());
+                let parent = try!(r.read_le_u32());
+                Ok(Update(key, parent, try!(snapshot::read_string(r))))
+            }
+            2 => Ok(Delete(try!(r.read_le_u32()))),
+            3 => {
+                let key = try!(r.read_le_u32());
+                Ok(Location(key, try!(Snapshot::read_from(r))))
+            }
+            4 => Ok(Commit(try!(r.read_le_u32()))),
+            _ => Err(snapshot::invalid("unknown replication message"))
+        }
+    }
+}
+
+fn in_subtree(common: &CommonData, root: ObjectKey, key: ObjectKey) -> bool {
+    let mut key = key;
+    while key != 0 {
+        key = match common.object(key) {
+            Some(obj) => obj.parent,
+            None => return false
+        };
+        if key == root {
+            return true;
+        }
+    }
+    false
+}
+
+fn depth(common: &CommonData, key: ObjectKey) -> uint {
+    let mut key = key;
+    let mut depth = 0;
+    while key != 0 {
+        key = match common.object(key) {
+            Some(obj) => obj.parent,
+            None => 0
+        };
+        depth += 1;
+    }
+    depth
+}
+
+/// The authoritative side of a replicated subtree. Each call to `deltas`
+/// compares the current generation against the last one that was sent and
+/// produces the messages needed to bring a `Subscriber` up to date.
+pub struct Publisher {
+    root: ObjectKey,
+    common: CommonData,
+    position: PositionData,
+    generation: u32
+}
+
+impl Publisher {
+    pub fn new(root: ObjectKey) -> Publisher {
+        Publisher {
+            root: root,
+            common: CommonData::new(),
+            position: PositionData::new(),
+            generation: 0
+        }
+    }
+
+    fn describe(&self, common: &CommonData, key: ObjectKey) -> (ObjectKey, String) {
+        let parent = common.object(key).unwrap().parent;
+        let name = common.object_name(key).unwrap().to_string();
+        (if parent == self.root { 0 } else { parent }, name)
+    }
+
+    pub fn deltas<GD: Positions>(&mut self, gd: &GD) -> Vec<Message> {
+        let common = gd.get_common();
+        let mut out = Vec::new();
+
+        let diff = CommonData::diff(&self.common, common);
+
+        for key in diff.removed.iter() {
+            if in_subtree(&self.common, self.root, *key) {
+                out.push(Delete(*key));
+            }
+        }
+
+        let mut created: Vec<ObjectKey> = diff.added.iter()
+            .map(|k| *k)
+            .filter(|k| in_subtree(common, self.root, *k))
+            .collect();
+
+        // an object that moved into the subtree brings its children with
+        // it, none of them were sent before so all of them need a location
+        let mut moved_in = Vec::new();
+        let mut updated = Vec::new();
+        for key in diff.modified.iter() {
+            let was = in_subtree(&self.common, self.root, *key);
+            let is = in_subtree(common, self.root, *key);
+            if was && is {
+                updated.push(*key);
+            } else if was {
+                out.push(Delete(*key));
+            } else if is {
+                moved_in.push_all(gd.subtree(*key).as_slice());
+            }
+        }
+        moved_in.sort();
+        moved_in.dedup();
+        created.push_all(moved_in.as_slice());
+        created.sort();
+        created.dedup();
+
+        // renames go out before the creates so a new object can take the
+        // old name of another, unless the new parent is created here too
+        let (late, early) = updated.partition(|k| {
+            let parent = common.object(*k).unwrap().parent;
+            created.contains(&parent)
+        });
+        for key in early.iter() {
+            let (parent, name) = self.describe(common, *key);
+            out.push(Update(*key, parent, name));
+        }
+
+        // parents must be created before their children
+        created.sort_by(|a, b| depth(common, *a).cmp(&depth(common, *b)));
+        for key in created.iter() {
+            let (parent, name) = self.describe(common, *key);
+            out.push(Create(*key, parent, name));
+        }
+
+        for key in late.iter() {
+            let (parent, name) = self.describe(common, *key);
+            out.push(Update(*key, parent, name));
+        }
+
+        let diff = PositionData::diff(&self.position, gd.get_position());
+        let mut located: Vec<ObjectKey> = diff.changed().iter()
+            .map(|k| *k)
+            .filter(|k| in_subtree(common, self.root, *k))
+            .collect();
+        located.push_all(moved_in.as_slice());
+        located.sort();
+        located.dedup();
+        for key in located.iter() {
+            match gd.location(*key) {
+                Some(loc) => out.push(Location(*key, loc)),
+                None => ()
+            }
+        }
+
+        self.generation += 1;
+        out.push(Commit(self.generation));
+
+        self.common = common.clone();
+        self.position = gd.get_position().clone();
+        out
+    }
+
+    pub fn send<GD: Positions>(&mut self, gd: &GD, w: &mut Writer) -> IoResult<()> {
+        for msg in self.deltas(gd).iter() {
+            try!(msg.write_to(w));
+        }
+        w.flush()
+    }
+}
+
+/// The receiving side of a replicated subtree. The remote subtree is mounted
+/// under a local object, the remote peer is the authority for everything
+/// under that mount.
+pub struct Subscriber {
+    mount: ObjectKey,
+    remote_to_local: BTreeMap<ObjectKey, ObjectKey>,
+    generation: u32
+}
+
+impl Subscriber {
+    pub fn new(mount: ObjectKey) -> Subscriber {
+        let mut remote_to_local = BTreeMap::new();
+        remote_to_local.insert(0, mount);
+
+        Subscriber {
+            mount: mount,
+            remote_to_local: remote_to_local,
+            generation: 0
+        }
+    }
+
+    /// The local key of an object that was replicated from the remote peer.
+    pub fn local(&self, remote: ObjectKey) -> Option<ObjectKey> {
+        match self.remote_to_local.find(&remote) {
+            Some(key) => Some(*key),
+            None => None
+        }
+    }
+
+    pub fn generation(&self) -> u32 { self.generation }
+
+    /// Apply a single message, messages for objects that are not part of the
+    /// replicated subtree are ignored and `false` is returned.
+    pub fn apply<GD: Positions>(&mut self, gd: &mut GD, msg: &Message) -> bool {
+        match *msg {
+            Create(key, parent, ref name) => {
+                if key == 0 || self.local(key).is_some() {
+                    return false;
+                }
+                let parent = match self.local(parent) {
+                    Some(parent) => parent,
+                    None => return false
+                };
+                let local = gd.new_object(Some(parent), name.as_slice());
+                self.remote_to_local.insert(key, local);
+                true
+            }
+            Update(key, parent, ref name) => {
+                let (local, parent) = match (self.local(key), self.local(parent)) {
+                    (Some(local), Some(parent)) if key != 0 => (local, parent),
+                    _ => return false
+                };
+                gd.move_object(local, Some(parent)) && gd.rename(local, name.as_slice())
+            }
+            Delete(key) => {
+                let local = match self.local(key) {
+                    Some(local) if key != 0 => local,
+                    _ => return false
+                };
+                for k in gd.delete_subtree(local).iter() {
+                    gd.delete_position(*k);
+                }
+
+                // drop the mappings of anything that went with it
+                let stale: Vec<ObjectKey> = self.remote_to_local.iter()
+                    .filter(|&(_, l)| gd.object(*l).is_none() && *l != self.mount)
+                    .map(|(r, _)| *r)
+                    .collect();
+                for r in stale.iter() {
+                    self.remote_to_local.remove(r);
+                }
+                true
+            }
+            Location(key, delta) => {
+                match self.local(key) {
+                    Some(local) if key != 0 => {
+                        gd.update_location(local, delta);
+                        true
+                    }
+                    _ => false
+                }
+            }
+            Commit(gen) => {
+                self.generation = gen;
+                true
+            }
+        }
+    }
+
+    /// Read and apply messages until the end of the next generation,
+    /// returns the remote generation number.
+    pub fn recv<GD: Positions>(&mut self, gd: &mut GD, r: &mut Reader) -> IoResult<u32> {
+        loop {
+            let msg: Message = try!(Snapshot::read_from(r));
+            self.apply(gd, &msg);
+            match msg {
+                Commit(gen) => return Ok(gen),
+                _ => ()
+            }
+        }
+    }
+}
@@ -0,0 +1,149 @@
+extern crate snowmew;
+extern crate cgmath;
+extern crate net = "snowmew-net";
+extern crate position = "snowmew-position";
+
+use std::io::{Acceptor, Listener};
+use std::io::net::tcp::{TcpListener, TcpStream};
+use std::io::{BufferedReader, BufferedWriter};
+
+use cgmath::vector::Vector3;
+
+use snowmew::common::{Common, CommonData};
+use position::{Positions, PositionData};
+use net::{Publisher, Subscriber};
+
+#[deriving(Clone)]
+struct TestData {
+    common: CommonData,
+    position: PositionData
+}
+
+impl TestData {
+    fn new() -> TestData {
+        TestData {
+            common: CommonData::new(),
+            position: PositionData::new()
+        }
+    }
+}
+
+impl Common for TestData {
+    fn get_common<'a>(&'a self) -> &'a CommonData { &self.common }
+    fn get_common_mut<'a>(&'a mut self) -> &'a mut CommonData { &mut self.common }
+}
+
+impl Positions for TestData {
+    fn get_position<'a>(&'a self) -> &'a PositionData { &self.position }
+    fn get_position_mut<'a>(&'a mut self) -> &'a mut PositionData { &mut self.position }
+}
+
+#[test]
+fn replicate_local() {
+    let mut server = TestData::new();
+    let mut client = TestData::new();
+
+    // keys are remapped, the client already has objects of its own
+    client.new_object(None, "local");
+    let mount = client.new_object(None, "remote");
+
+    let root = server.new_object(None, "world");
+    let a = server.new_object(Some(root), "a");
+    let b = server.new_object(Some(a), "b");
+    server.new_object(None, "not_replicated");
+    server.set_displacement(b, Vector3::new(1f32, 2f32, 3f32));
+
+    let mut publisher = Publisher::new(root);
+    let mut subscriber = Subscriber::new(mount);
+
+    for msg in publisher.deltas(&server).iter() {
+        subscriber.apply(&mut client, msg);
+    }
+
+    let cb = client.find("remote/a/b").expect("b was not replicated");
+    assert!(subscriber.local(b) == Some(cb));
+    assert!(client.find("remote/not_replicated").is_none());
+    assert!(client.location(cb).unwrap().disp == Vector3::new(1f32, 2f32, 3f32));
+
+    server.rename(a, "c");
+    server.delete_object(b);
+    for msg in publisher.deltas(&server).iter() {
+        subscriber.apply(&mut client, msg);
+    }
+
+    assert!(client.find("remote/c").is_some());
+    assert!(client.find("remote/c/b").is_none());
+    assert!(subscriber.local(b).is_none());
+    assert!(subscriber.generation() == 2);
+}
+
+#[test]
+fn replicate_loopback() {
+    let addr = "127.0.0.1";
+    let mut listener = TcpListener::bind(addr, 0).unwrap();
+    let port = listener.socket_name().unwrap().port;
+    let mut acceptor = listener.listen().unwrap();
+
+    spawn(proc() {
+        let mut server = TestData::new();
+        let root = server.new_object(None, "world");
+        let mut publisher = Publisher::new(root);
+        let mut w = BufferedWriter::new(TcpStream::connect(addr, port).unwrap());
+
+        for i in range(0u, 10) {
+            let obj = server.new_object(Some(root), format!("obj_{}", i).as_slice());
+            server.set_displacement(obj, Vector3::new(i as f32, 0., 0.));
+            publisher.send(&server, &mut w).unwrap();
+        }
+    });
+
+    let mut client = TestData::new();
+    let mount = client.new_object(None, "remote");
+    let mut subscriber = Subscriber::new(mount);
+    let mut r = BufferedReader::new(acceptor.accept().unwrap());
+
+    for i in range(1u32, 11) {
+        assert!(subscriber.recv(&mut client, &mut r).unwrap() == i);
+    }
+
+    for i in range(0u, 10) {
+        let obj = client.find(format!("remote/obj_{}", i).as_slice()).unwrap();
+        assert!(client.location(obj).unwrap().disp == Vector3::new(i as f32, 0., 0.));
+    }
+}
+
+#[test]
+fn replicate_moved_subtree() {
+    let mut server = TestData::new();
+    let mut client = TestData::new();
+    let mount = client.new_object(None, "remote");
+
+    let root = server.new_object(None, "world");
+    let old = server.new_object(Some(root), "x");
+    let stash = server.new_object(None, "stash");
+    let a = server.new_object(Some(stash), "a");
+    let b = server.new_object(Some(a), "b");
+    server.set_displacement(b, Vector3::new(1f32, 2f32, 3f32));
+
+    let mut publisher = Publisher::new(root);
+    let mut subscriber = Subscriber::new(mount);
+    for msg in publisher.deltas(&server).iter() {
+        subscriber.apply(&mut client, msg);
+    }
+    assert!(client.find("remote/a").is_none());
+
+    // b did not change, it has to be sent because its parent moved in
+    server.move_object(a, Some(root));
+    server.rename(old, "y");
+    let new = server.new_object(Some(root), "x");
+    for msg in publisher.deltas(&server).iter() {
+        subscriber.apply(&mut client, msg);
+    }
+
+    let cb = client.find("remote/a/b").expect("b was not replicated");
+    assert!(subscriber.local(b) == Some(cb));
+    assert!(client.location(cb).unwrap().disp == Vector3::new(1f32, 2f32, 3f32));
+    assert!(client.find("remote/y") == subscriber.local(old));
+    assert!(client.find("remote/x") == subscriber.local(new));
+    assert!(subscriber.local(new).is_some());
+}
@@ -2,14 +2,22 @@
 #![license = "ASL2"]
 #![crate_type = "lib"]
 #![comment = "A collison detection manager for snowmew"]
+#![feature(phase)]
 
+#[phase(plugin, link)]
 extern crate snowmew;
 extern crate cow;
 extern crate cgmath;
 extern crate position = "snowmew-position";
 extern crate collision;
 
-use snowmew::common::{ObjectKey, Common};
+use std::io::IoResult;
+
+use snowmew::common::{ObjectKey, Common, Remap};
+use snowmew::snapshot;
+use snowmew::snapshot::{Snapshot, write_map, read_map};
+use snowmew::diff::{Diff, diff_maps};
+use snowmew::event::ColliderChanged;
 use position::Positions;
 
 use collision::aabb::{Aabb3};
@@ -40,6 +48,31 @@ impl std::default::Default for Velocity {
     }
 }
 
+impl Snapshot for Collider {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()> {
+        let &Collider(ref aabb) = self;
+        try!(aabb.min.write_to(w));
+        aabb.max.write_to(w)
+    }
+
+    fn read_from(r: &mut Reader) -> IoResult<Collider> {
+        let min = try!(Snapshot::read_from(r));
+        let max = try!(Snapshot::read_from(r));
+        Ok(Collider(Aabb3::new(min, max)))
+    }
+}
+
+impl Snapshot for Velocity {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()> {
+        let &Velocity(ref v) = self;
+        v.write_to(w)
+    }
+
+    fn read_from(r: &mut Reader) -> IoResult<Velocity> {
+        Ok(Velocity(try!(Snapshot::read_from(r))))
+    }
+}
+
 #[deriving(Clone)]
 pub struct PhysicsData {
     static_colliders: BTreeMap<ObjectKey, Collider>,
@@ -48,6 +81,10 @@ pub struct PhysicsData {
     static_version: uint
 }
 
+impl std::default::Default for PhysicsData {
+    fn default() -> PhysicsData { PhysicsData::new() }
+}
+
 impl PhysicsData {
     pub fn new() -> PhysicsData {
         PhysicsData {
@@ -57,6 +94,44 @@ impl PhysicsData {
             static_version: 0
         }
     }
+
+    pub fn diff(old: &PhysicsData, new: &PhysicsData) -> PhysicsDiff {
+        PhysicsDiff {
+            static_colliders: diff_maps(old.static_colliders.iter(), new.static_colliders.iter(),
+                                        |&Collider(ref a), &Collider(ref b)| a.min == b.min && a.max == b.max),
+            colliders: diff_maps(old.colliders.iter(), new.colliders.iter(),
+                                 |&Collider(ref a), &Collider(ref b)| a.min == b.min && a.max == b.max),
+            velocity: diff_maps(old.velocity.iter(), new.velocity.iter(),
+                                |&Velocity(ref a), &Velocity(ref b)| a == b)
+        }
+    }
+}
+
+/// The changes to the physics maps between two generations.
+#[deriving(Clone, Default, Show)]
+pub struct PhysicsDiff {
+    pub static_colliders: Diff,
+    pub colliders: Diff,
+    pub velocity: Diff
+}
+
+impl Snapshot for PhysicsData {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()> {
+        try!(snapshot::write_tag(w, snapshot::TAG_PHYSICS));
+        try!(write_map(w, &self.static_colliders));
+        try!(write_map(w, &self.colliders));
+        write_map(w, &self.velocity)
+    }
+
+    fn read_from(r: &mut Reader) -> IoResult<PhysicsData> {
+        try!(snapshot::expect_tag(r, snapshot::TAG_PHYSICS));
+        Ok(PhysicsData {
+            static_colliders: try!(read_map(r)),
+            colliders: try!(read_map(r)),
+            velocity: try!(read_map(r)),
+            static_version: 0
+        })
+    }
 }
 
 pub trait Physics: Common + Positions {
@@ -66,6 +141,7 @@ pub trait Physics: Common + Positions {
     fn add_static_collider(&mut self, key: ObjectKey, collider: Aabb3<f32>) {
         self.get_physics_mut().static_version += 1;
         self.get_physics_mut().static_colliders.insert(key, Collider(collider));   
+        self.log_event(ColliderChanged(key));
     }
 
     fn get_static_collider<'a>(&'a self, key: ObjectKey) -> Option<&'a Aabb3<f32>> {
@@ -77,6 +153,7 @@ pub trait Physics: Common + Positions {
 
     fn add_collider(&mut self, key: ObjectKey, collider: Aabb3<f32>) {
         self.get_physics_mut().colliders.insert(key, Collider(collider));   
+        self.log_event(ColliderChanged(key));
     }
 
     fn get_collider<'a>(&'a self, key: ObjectKey) -> Option<&'a Aabb3<f32>> {
@@ -96,5 +173,37 @@ pub trait Physics: Common + Positions {
             None => None
         }
     }
+
+    /// Copy the colliders and velocity of a prefab to the instance made by
+    /// `Common::instantiate`.
+    fn instantiate_physics(&mut self, remap: &Remap) {
+        for (old, new) in remap.iter() {
+            let collider = self.get_physics().static_colliders.find(old).map(|c| c.clone());
+            match collider {
+                Some(Collider(c)) => self.add_static_collider(*new, c),
+                None => ()
+            }
+
+            let collider = self.get_physics().colliders.find(old).map(|c| c.clone());
+            match collider {
+                Some(Collider(c)) => self.add_collider(*new, c),
+                None => ()
+            }
+
+            match self.get_velocity(*old) {
+                Some(v) => self.set_velocity(*new, v),
+                None => ()
+            }
+        }
+    }
+
+    /// Drop the colliders and velocity of an object that was removed from `Common`.
+    fn delete_physics(&mut self, key: ObjectKey) {
+        if self.get_physics_mut().static_colliders.remove(&key) {
+            self.get_physics_mut().static_version += 1;
+        }
+        self.get_physics_mut().colliders.remove(&key);
+        self.get_physics_mut().velocity.remove(&key);
+    }
 }
 
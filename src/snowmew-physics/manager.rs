@@ -6,9 +6,9 @@ use cgmath::vector::{Vector, Vector4, Vector3};
 use cgmath::matrix::{Matrix, Matrix4};
 use cgmath::transform::Decomposed;
 
-use cow::join::join_maps;
-
-use snowmew::common::{ObjectKey, CommonData, Common};
+use snowmew::common::{ObjectKey, CommonData, Common, Layers, ALL_LAYERS};
+use snowmew::query::query;
+use snowmew::event::{EventSeq, PositionChanged, ObjectMoved, LayersChanged};
 use collision::bvh::{BvhBuilder, Bvh};
 use collision::aabb::{Aabb3};
 use collision::Merge;
@@ -17,12 +17,11 @@ use position::{Positions, ComputedPosition, PositionData};
 
 use {Physics, Velocity, Collider, PhysicsData};
 
-#[deriving(Clone)]
-struct PhysicsTemp {
-    common: CommonData,
-    position: PositionData,
-    physics: PhysicsData
-}
+game_data!(PhysicsTemp {
+    common: CommonData => Common { get_common, get_common_mut },
+    position: PositionData => Positions { get_position, get_position_mut },
+    physics: PhysicsData => Physics { get_physics, get_physics_mut }
+})
 
 impl PhysicsTemp {
     fn new<T: Physics>(t: &T) -> PhysicsTemp {
@@ -34,58 +33,92 @@ impl PhysicsTemp {
     }
 }
 
-impl Common for PhysicsTemp {
-    fn get_common<'a>(&'a self) -> &'a CommonData { &self.common }
-    fn get_common_mut<'a>(&'a mut self) -> &'a mut CommonData { &mut self.common }
-}
-
-impl Positions for PhysicsTemp {
-    fn get_position<'a>(&'a self) -> &'a PositionData { &self.position }
-    fn get_position_mut<'a>(&'a mut self) -> &'a mut PositionData { &mut self.position }
-}
-
-impl Physics for PhysicsTemp {
-    fn get_physics<'a>(&'a self) -> &'a PhysicsData { &self.physics }
-    fn get_physics_mut<'a>(&'a mut self) -> &'a mut PhysicsData { &mut self.physics }
-}
-
 pub struct PhysicsManager {
-    static_builder: Option<BvhBuilder<ObjectKey, Aabb3<f32>, Point3<f32>>>,
     static_bvh: Option<Bvh<ObjectKey, Aabb3<f32>>>,
+    // the generation the static bvh was built from, and the end of its
+    // event log when the bvh was last checked against it
+    built: PhysicsData,
+    seq: Option<EventSeq>,
     matrix: Vec<Matrix4<f32>>,
-    version: uint
+    version: uint,
+    layers: Layers,
+    built_layers: Layers
 }
 
 impl PhysicsManager {
     pub fn new() -> PhysicsManager { 
         PhysicsManager {
-            static_builder: Some(BvhBuilder::new()),
             static_bvh: None,
+            built: PhysicsData::new(),
+            seq: None,
             matrix: Vec::new(),
-            version: 0
+            version: 0,
+            layers: ALL_LAYERS,
+            built_layers: ALL_LAYERS
         }
     }
 
-    fn build_static_bvh<P: Physics>(&mut self, pos: &ComputedPosition, data: &P) {
-        if self.static_bvh.is_some() && data.get_physics().static_version == self.version {
-            return;
+    /// Only objects on one of the layers in `mask` collide or move,
+    /// everything takes part by default.
+    pub fn set_layers(&mut self, mask: Layers) {
+        self.layers = mask;
+    }
+
+    pub fn layers(&self) -> Layers { self.layers }
+
+    // true if a static collider, or one of its parents, was moved or had
+    // its layers changed since the bvh was last checked
+    fn statics_moved<P: Physics>(&self, data: &P) -> bool {
+        let events = match self.seq.clone().and_then(|seq| data.events_since(seq)) {
+            Some(events) => events,
+            None => return true
+        };
+
+        let statics = &data.get_physics().static_colliders;
+        for (_, ev) in events {
+            match *ev {
+                PositionChanged(key) | ObjectMoved(key) => {
+                    if data.subtree(key).iter().any(|k| statics.find(k).is_some()) {
+                        return true;
+                    }
+                }
+                LayersChanged(key) if statics.find(&key).is_some() => return true,
+                _ => ()
+            }
         }
+        false
+    }
 
-        let mut bvh = match self.static_builder.take() {
-            Some(bvh) => bvh,
-            None => match self.static_bvh.take() {
-                Some(bvh_builder) => bvh_builder.to_builder(),
-                None => fail!("no bvh or builder")
+    // The bvh has no way to remove a single collider, so it is rebuilt
+    // whenever the static colliders really changed, were moved or changed
+    // layers. A version bump that left them as they were, such as a
+    // collider that was added and removed again, keeps the old one.
+    fn build_static_bvh<P: Physics>(&mut self, pos: &ComputedPosition, data: &P) {
+        if self.static_bvh.is_some() && self.built_layers == self.layers && !self.statics_moved(data) {
+            if data.get_physics().static_version == self.version ||
+               PhysicsData::diff(&self.built, data.get_physics()).static_colliders.is_empty() {
+                self.version = data.get_physics().static_version;
+                self.seq = Some(data.event_seq());
+                return;
             }
-        };
+        }
+
+        // start from an empty builder, the old bvh still holds the
+        // colliders where they were
+        let mut bvh: BvhBuilder<ObjectKey, Aabb3<f32>, Point3<f32>> = BvhBuilder::new();
 
-        for (key, (loc, &Collider(ref coll))) in join_maps(data.location_iter(), data.get_physics().static_colliders.iter()) {
+        for (key, (loc, &Collider(ref coll))) in
+                query(data.location_iter()).with(data.get_physics().static_colliders.iter())
+                                           .in_layers(data.get_common(), self.layers) {
             let aabb = recalc_aabb(coll, self.matrix.get(pos.get_loc(*loc)));
             bvh.add(aabb, *key);
         }
 
         self.static_bvh = Some(bvh.build());
+        self.built = data.get_physics().clone();
         self.version = data.get_physics().static_version;
+        self.seq = Some(data.event_seq());
+        self.built_layers = self.layers;
     }
 
     pub fn step<P: Physics>(&mut self, data: &mut P, time: f32) {
@@ -102,9 +135,10 @@ impl PhysicsManager {
         match self.static_bvh {
             None => fail!("Could not unwrap bvh"),
             Some(ref bvh) => {
-                for (key, ((loc, &Velocity(ref vel)), &Collider(ref coll))) in 
-                        join_maps(join_maps(old.location_iter(), old.get_physics().velocity.iter()),
-                                            old.get_physics().colliders.iter()) {
+                for (key, ((loc, &Velocity(ref vel)), &Collider(ref coll))) in
+                        query(old.location_iter()).with(old.get_physics().velocity.iter())
+                                                  .with(old.get_physics().colliders.iter())
+                                                  .in_layers(old.get_common(), self.layers) {
                     let vel = vel.mul_s(time);
                     let aabb = recalc_aabb_with_vec(coll, self.matrix.get(pos.get_loc(*loc)), &vel);
                     let mut collided = false;
@@ -0,0 +1,119 @@
+extern crate snowmew;
+extern crate cgmath;
+extern crate collision;
+extern crate position = "snowmew-position";
+extern crate physics = "snowmew-physics";
+
+use snowmew::common::{Common, CommonData, ObjectKey, DEFAULT_LAYER};
+use position::{Positions, PositionData};
+use physics::{Physics, PhysicsData};
+use physics::manager::PhysicsManager;
+
+use collision::aabb::Aabb3;
+use cgmath::point::Point3;
+use cgmath::vector::Vector3;
+
+#[deriving(Clone)]
+struct TestData {
+    common: CommonData,
+    position: PositionData,
+    physics: PhysicsData
+}
+
+impl TestData {
+    fn new() -> TestData {
+        TestData {
+            common: CommonData::new(),
+            position: PositionData::new(),
+            physics: PhysicsData::new()
+        }
+    }
+}
+
+impl Common for TestData {
+    fn get_common<'a>(&'a self) -> &'a CommonData { &self.common }
+    fn get_common_mut<'a>(&'a mut self) -> &'a mut CommonData { &mut self.common }
+}
+
+impl Positions for TestData {
+    fn get_position<'a>(&'a self) -> &'a PositionData { &self.position }
+    fn get_position_mut<'a>(&'a mut self) -> &'a mut PositionData { &mut self.position }
+}
+
+impl Physics for TestData {
+    fn get_physics<'a>(&'a self) -> &'a PhysicsData { &self.physics }
+    fn get_physics_mut<'a>(&'a mut self) -> &'a mut PhysicsData { &mut self.physics }
+}
+
+fn unit_box() -> Aabb3<f32> {
+    Aabb3::new(Point3::new(-0.5f32, -0.5, -0.5), Point3::new(0.5f32, 0.5, 0.5))
+}
+
+// a wall far down the x axis and a box at the origin moving towards it
+fn setup() -> (TestData, ObjectKey, ObjectKey) {
+    let mut db = TestData::new();
+    let wall = db.new_object(None, "wall");
+    let mover = db.new_object(None, "mover");
+    db.set_displacement(wall, Vector3::new(10f32, 0., 0.));
+    db.set_displacement(mover, Vector3::new(0f32, 0., 0.));
+    db.add_static_collider(wall, unit_box());
+    db.add_collider(mover, unit_box());
+    db.set_velocity(mover, Vector3::new(1f32, 0., 0.));
+    (db, wall, mover)
+}
+
+fn x(db: &TestData, key: ObjectKey) -> f32 {
+    db.location(key).unwrap().disp.x
+}
+
+#[test]
+fn static_collider_moved() {
+    let (mut db, wall, mover) = setup();
+    let mut pm = PhysicsManager::new();
+
+    pm.step(&mut db, 1.);
+    assert!(x(&db, mover) == 1.);
+
+    // the wall is now in the way
+    db.set_displacement(wall, Vector3::new(2.5f32, 0., 0.));
+    pm.step(&mut db, 1.);
+    assert!(x(&db, mover) == 1.);
+
+    // and out of the way again
+    db.set_displacement(wall, Vector3::new(10f32, 0., 0.));
+    pm.step(&mut db, 1.);
+    assert!(x(&db, mover) == 2.);
+}
+
+#[test]
+fn static_parent_moved() {
+    let (mut db, wall, mover) = setup();
+    let room = db.new_object(None, "room");
+    assert!(db.move_object(wall, Some(room)));
+    let mut pm = PhysicsManager::new();
+
+    pm.step(&mut db, 1.);
+    assert!(x(&db, mover) == 1.);
+
+    // moving the parent brings the wall along
+    db.set_displacement(room, Vector3::new(-7.5f32, 0., 0.));
+    pm.step(&mut db, 1.);
+    assert!(x(&db, mover) == 1.);
+}
+
+#[test]
+fn static_collider_layers_changed() {
+    let (mut db, wall, mover) = setup();
+    db.set_displacement(wall, Vector3::new(2.5f32, 0., 0.));
+    let mut pm = PhysicsManager::new();
+    pm.set_layers(DEFAULT_LAYER);
+
+    pm.step(&mut db, 1.);
+    pm.step(&mut db, 1.);
+    assert!(x(&db, mover) == 1.);
+
+    // the wall left the layers the manager looks at
+    db.set_layers(wall, 2);
+    pm.step(&mut db, 1.);
+    assert!(x(&db, mover) == 2.);
+}
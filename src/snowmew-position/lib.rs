@@ -8,12 +8,17 @@ extern crate cgmath;
 extern crate OpenCL;
 extern crate cow;
 extern crate time;
+extern crate sync;
 
+use std::cmp;
 use std::default::Default;
+use std::io::IoResult;
+use std::sync::TaskPool;
+use sync::Arc;
 
 use cgmath::transform::{Transform, Decomposed};
 use cgmath::quaternion::Quaternion;
-use cgmath::vector::{Vector3, Vector4};
+use cgmath::vector::{Vector, Vector3, Vector4};
 use cgmath::matrix::{Matrix4, ToMatrix4, Matrix};
 
 use OpenCL::hl::{Device, Context, CommandQueue, Kernel, Event};
@@ -22,19 +27,34 @@ use OpenCL::CL::{CL_MEM_READ_ONLY};
 
 use cow::btree::{BTreeMap, BTreeMapIterator};
 
-use snowmew::common::{ObjectKey, Common};
+use snowmew::common::{ObjectKey, Common, Remap};
+use snowmew::snapshot;
+use snowmew::snapshot::Snapshot;
+use snowmew::diff::{Diff, diff_maps};
+use snowmew::event::{EventSeq, PositionChanged, ObjectMoved};
 
 static opencl_program: &'static str = include_str!("position.c");
 
+/// The number of slots of a generation handed to each worker by
+/// `Deltas::queue_positions`.
+pub static PARALLEL_CHUNK: uint = 1024;
+
+// the number of chunks a generation of `len` slots is split into
+fn chunk_count(len: u32) -> uint {
+    cmp::max(1, (len as uint + PARALLEL_CHUNK - 1) / PARALLEL_CHUNK)
+}
+
 pub struct Delta {
     delta : Decomposed<f32, Vector3<f32>, Quaternion<f32>>,
     parent: u32,
+    children: u32
 }
 
 impl Default for Delta {
     fn default() -> Delta {
         Delta {
             parent: 0,
+            children: 0,
             delta: Transform::identity()
         }
     }
@@ -44,6 +64,7 @@ impl Clone for Delta {
     fn clone(&self) -> Delta {
         Delta {
             parent: self.parent.clone(),
+            children: self.children,
             delta: Decomposed{scale: self.delta.scale.clone(),
                               rot:   self.delta.rot.clone(),
                               disp:  self.delta.disp.clone()}
@@ -65,11 +86,75 @@ impl<'r> MatrixManager for &'r mut [Matrix4<f32>] {
 pub struct Deltas {
     gen: Vec<(u32, u32)>,
     delta: BTreeMap<u32, BTreeMap<u32, Delta>>,
+    // slots left empty by `remove`, until the next `compact`
+    holes: uint
 }
 
+/// The slot of a location in `Deltas`. Slots move when the deltas are
+/// compacted, hold on to a `Handle` instead.
 #[deriving(Clone, Default, Eq, PartialOrd, PartialEq, Ord)]
 pub struct Id(u32, u32);
 
+/// A handle to the location of an object that stays valid across
+/// `compact`. It is resolved to the current `Id` each time it is used.
+#[deriving(Clone, Eq, PartialOrd, PartialEq, Ord, Show)]
+pub struct Handle(ObjectKey);
+
+impl Handle {
+    pub fn key(&self) -> ObjectKey {
+        let Handle(key) = *self;
+        key
+    }
+}
+
+// the `Id` of an object's location, a location is added under its parent
+// if it does not have one yet
+fn location_id<P: Positions>(p: &mut P, key: ObjectKey) -> Id {
+    if key == 0 {
+        Deltas::root()
+    } else {
+        match p.get_position().location.find(&key) {
+            Some(id) =>  return *id,
+            None => ()
+        }
+
+        let poid = p.object(key).unwrap().parent;
+        let pid = location_id(p, poid);
+        let id = p.get_position_mut().deltas_mut().insert(pid, Transform::identity());
+        p.get_position_mut().location.insert(key, id);
+        id
+    }
+}
+
+// drop the cached world matrices of the subtrees moved with
+// `Common::set_parent` since the cache was last synced, the whole cache
+// is dropped if the log no longer reaches back that far
+fn sync_world<P: Positions>(p: &mut P) {
+    let moves = p.moves();
+    if p.get_position().moves == moves {
+        return;
+    }
+
+    let moved: Option<Vec<ObjectKey>> = p.events_since(p.get_position().synced.clone()).map(|events| {
+        events.filter_map(|(_, ev)| match *ev {
+            ObjectMoved(key) => Some(key),
+            _ => None
+        }).collect()
+    });
+    match moved {
+        Some(keys) => {
+            for key in keys.iter() {
+                p.invalidate_world(*key);
+            }
+        }
+        None => p.get_position_mut().world = BTreeMap::new()
+    }
+
+    let seq = p.event_seq();
+    p.get_position_mut().moves = moves;
+    p.get_position_mut().synced = seq;
+}
+
 impl Deltas {
     pub fn new() -> Deltas {
         let mut b = BTreeMap::new();
@@ -79,11 +164,18 @@ impl Deltas {
         Deltas {
             gen: vec!((0, 1)),
             delta: b,
+            holes: 0
         }
     }
 
     pub fn root() -> Id { Id(0, 0) }
 
+    /// The number of matrices written by `write_positions`, holes included.
+    pub fn len(&self) -> uint {
+        let (s, l) = *self.gen.get(self.gen.len()-1);
+        (s + l) as uint
+    }
+
     pub fn get_loc(&self, id: Id) -> uint {
         let Id(gen, offset) = id;
         let (gen_offset, _) = *self.gen.get(gen as uint);
@@ -124,15 +216,88 @@ impl Deltas {
             Some(d) => {
                 d.insert(id, Delta {
                     parent: pid,
+                    children: 0,
                     delta: delta,
                 })
             }
             None => fail!("there was no delta! {}", gen)
         };
+        self.delta.find_mut(&gen).unwrap().find_mut(&pid).unwrap().children += 1;
 
         Id(gen+1, id)
     }
 
+    /// Remove a location, its slot is left empty until `compact` is called.
+    /// Fails for the root and for locations that still have children.
+    pub fn remove(&mut self, id: Id) -> bool {
+        let Id(gen, off) = id;
+        if gen == 0 {
+            return false;
+        }
+
+        let parent = match self.delta.find(&gen).and_then(|g| g.find(&off)) {
+            Some(d) if d.children == 0 => d.parent,
+            _ => return false
+        };
+
+        self.delta.find_mut(&gen).unwrap().remove(&off);
+        self.delta.find_mut(&(gen-1)).unwrap().find_mut(&parent).unwrap().children -= 1;
+        self.holes += 1;
+        true
+    }
+
+    /// Number of empty slots left by `remove`.
+    pub fn holes(&self) -> uint { self.holes }
+
+    /// Close the slots left by `remove` so that the locations are packed at
+    /// the start of the matrix buffer again. Every location that was moved
+    /// is returned as a pair of its old and new `Id`.
+    pub fn compact(&mut self) -> Vec<(Id, Id)> {
+        let mut moved = Vec::new();
+        if self.holes == 0 {
+            return moved;
+        }
+
+        let mut gens = Vec::new();
+        let mut delta = BTreeMap::new();
+        // the new offset of each location of the last generation
+        let mut last: BTreeMap<u32, u32> = BTreeMap::new();
+        let mut start = 0;
+
+        for (gen, locations) in self.delta.iter() {
+            // every location deeper than this is a child of a removed location
+            if locations.len() == 0 {
+                break;
+            }
+
+            let mut out = BTreeMap::new();
+            let mut offsets = BTreeMap::new();
+            for (new, (old, d)) in locations.iter().enumerate() {
+                let new = new as u32;
+                let mut d = d.clone();
+                if *gen != 0 {
+                    d.parent = *last.find(&d.parent).unwrap();
+                }
+                out.insert(new, d);
+                offsets.insert(*old, new);
+                if *old != new {
+                    moved.push((Id(*gen, *old), Id(*gen, new)));
+                }
+            }
+
+            let len = out.len() as u32;
+            gens.push((start, len));
+            start += len;
+            delta.insert(*gen, out);
+            last = offsets;
+        }
+
+        self.gen = gens;
+        self.delta = delta;
+        self.holes = 0;
+        moved
+    }
+
     pub fn get_mut<'a>(&'a mut self, id: Id) -> &'a mut Decomposed<f32, Vector3<f32>, Quaternion<f32>> {
         let Id(gen, id) = id;
         &mut self.delta.find_mut(&gen).unwrap().find_mut(&id).unwrap().delta
@@ -177,26 +342,116 @@ impl Deltas {
         }
     }
 
-    pub fn write_positions_cl_vec4x4(&self, cq: &CommandQueue, ctx: &mut CalcPositionsCl,
-                           out: &[CLBuffer<Vector4<f32>>, ..4]) -> Event {
+    /// Same result as `write_positions`, but worked out by the workers of
+    /// `tp` with `queue_positions`. This waits for the pool, so it must not
+    /// be called from one of its workers.
+    pub fn write_positions_parallel<T, MM: MatrixManager>(deltas: &Arc<Deltas>, tp: &mut TaskPool<T>, mm: &mut MM) {
+        Deltas::queue_positions(deltas, tp).write(mm)
+    }
 
-        let last = self.gen.len();
-        let (s, l) = *self.gen.get(last-1);
-        let size = s + l;
+    /// Queue the world matrices of every location on the workers of `tp`
+    /// without waiting for them. Each generation is split into chunks of
+    /// `PARALLEL_CHUNK` slots that wait for the matrices of the generation
+    /// before it, a task only ever waits on tasks queued ahead of it. The
+    /// workers share `deltas`.
+    pub fn queue_positions<T>(deltas: &Arc<Deltas>, tp: &mut TaskPool<T>) -> PositionJob {
+        let this = deltas.deref();
+        let (done, finished) = channel();
+
+        // the root generation has no parent
+        let (sender, receiver) = channel();
+        sender.send(Arc::new(vec!(Matrix4::identity())));
+        let mut waiting = vec!(receiver);
+
+        for (idx, &(gen_off, len)) in this.gen.iter().enumerate() {
+            let gen = idx as u32;
+            let chunks = waiting.len();
+            let (result, results) = channel();
+            for (i, parents) in waiting.move_iter().enumerate() {
+                let start = (i * PARALLEL_CHUNK) as u32;
+                let end = cmp::min(start + PARALLEL_CHUNK as u32, len);
+                let (deltas, result) = (deltas.clone(), result.clone());
+                tp.execute(proc(_) {
+                    let parents = parents.recv();
+                    result.send(deltas.deref().write_chunk(gen, start, end, parents.as_slice()));
+                });
+            }
+
+            // one channel for each chunk of the next generation
+            let next = if idx + 1 < this.gen.len() {
+                let (_, len) = *this.gen.get(idx + 1);
+                chunk_count(len)
+            } else {
+                0
+            };
+            let mut senders = Vec::new();
+            waiting = Vec::new();
+            for _ in range(0, next) {
+                let (sender, receiver) = channel();
+                senders.push(sender);
+                waiting.push(receiver);
+            }
 
-        unsafe {
-            ctx.parent_buffer.reserve(size as uint);
-            ctx.parent_buffer.set_len(size as uint);
-            ctx.input_buffer.reserve(size as uint);
-            ctx.input_buffer.set_len(size as uint);
+            let done = done.clone();
+            tp.execute(proc(_) {
+                let mut mats = Vec::from_elem(len as uint, Matrix4::identity());
+                for _ in range(0, chunks) {
+                    for &(off, mat) in results.recv().iter() {
+                        *mats.get_mut(off as uint) = mat;
+                    }
+                }
+
+                let mats = Arc::new(mats);
+                for s in senders.iter() {
+                    s.send(mats.clone());
+                }
+                done.send((gen_off, mats));
+            });
+        }
+
+        PositionJob {
+            gens: this.gen.len(),
+            finished: finished
+        }
+    }
+
+    // world matrices of the locations of generation `gen` in the slots from
+    // `start` up to `end`, `parents` holds the matrices of the generation
+    // before it
+    fn write_chunk(&self, gen: u32, start: u32, end: u32,
+                   parents: &[Matrix4<f32>]) -> Vec<(u32, Matrix4<f32>)> {
+        let gen = self.delta.find(&gen).unwrap();
+        range(start, end).filter_map(|off| {
+            gen.find(&off).map(|delta| {
+                (off, parents[delta.parent as uint].mul_m(&delta.delta.to_matrix4()))
+            })
+        }).collect()
+    }
+
+    // copy every slot into the upload buffers of `ctx`, the slots left
+    // empty by `remove` get an identity under slot 0 of the generation above
+    // so that the kernel never follows a stale parent index
+    fn fill_cl_buffers(&self, ctx: &mut CalcPositionsCl) {
+        let size = self.len();
+        ctx.input_buffer.truncate(0);
+        ctx.parent_buffer.truncate(0);
+        for _ in range(0, size) {
+            ctx.input_buffer.push(Transform::identity());
+            ctx.parent_buffer.push(0);
         }
 
         for (&(gen_off, _), (_, gen)) in self.gen.iter().zip(self.delta.iter()) {
             for (off, delta) in gen.iter() {
                 *ctx.input_buffer.get_mut((off + gen_off) as uint) = delta.delta;
-                *ctx.parent_buffer.get_mut((off + gen_off) as uint) = delta.parent.clone();
+                *ctx.parent_buffer.get_mut((off + gen_off) as uint) = delta.parent;
             }
         }
+    }
+
+    pub fn write_positions_cl_vec4x4(&self, cq: &CommandQueue, ctx: &mut CalcPositionsCl,
+                           out: &[CLBuffer<Vector4<f32>>, ..4]) -> Event {
+
+        self.fill_cl_buffers(ctx);
 
         let events = &[cq.write_async(&ctx.input, &ctx.input_buffer.as_slice(), ()),
                        cq.write_async(&ctx.parent, &ctx.parent_buffer.as_slice(), ())];
@@ -229,23 +484,7 @@ impl Deltas {
     pub fn write_positions_cl_mat4(&self, cq: &CommandQueue, ctx: &mut CalcPositionsCl,
                            out: &[CLBuffer<Matrix4<f32>>]) -> Event {
 
-        let last = self.gen.len();
-        let (s, l) = *self.gen.get(last-1);
-        let size = s + l;
-
-        unsafe {
-            ctx.parent_buffer.reserve(size as uint);
-            ctx.parent_buffer.set_len(size as uint);
-            ctx.input_buffer.reserve(size as uint);
-            ctx.input_buffer.set_len(size as uint);
-        }
-
-        for (&(gen_off, _), (_, gen)) in self.gen.iter().zip(self.delta.iter()) {
-            for (off, delta) in gen.iter() {
-                *ctx.input_buffer.get_mut((off + gen_off) as uint) = delta.delta;
-                *ctx.parent_buffer.get_mut((off + gen_off) as uint) = delta.parent.clone();
-            }
-        }
+        self.fill_cl_buffers(ctx);
 
         let events = &[cq.write_async(&ctx.input, &ctx.input_buffer.as_slice(), ()),
                        cq.write_async(&ctx.parent, &ctx.parent_buffer.as_slice(), ())];
@@ -269,7 +508,13 @@ impl Deltas {
         event
     }
 
+    /// Copy every slot into `out_delta` for the GL compute pass. The slots
+    /// left empty by `remove` are written as an identity under slot 0 of the
+    /// generation above, like the OpenCL path does.
     pub fn to_positions_gl(&self, out_delta: &mut [Delta]) -> ComputedPositionGL {
+        for slot in out_delta.mut_slice_to(self.len()).mut_iter() {
+            *slot = Default::default();
+        }
         for (&(gen_off, _), (_, gen)) in self.gen.iter().zip(self.delta.iter()) {
             for (off, delta) in gen.iter() {
                 out_delta[(off + gen_off) as uint] = delta.clone();
@@ -288,6 +533,25 @@ impl Deltas {
     }
 }
 
+/// The world matrices queued by `Deltas::queue_positions`.
+pub struct PositionJob {
+    gens: uint,
+    finished: Receiver<(u32, Arc<Vec<Matrix4<f32>>>)>
+}
+
+impl PositionJob {
+    /// Wait for each generation and copy its matrices into `mm`. A worker of
+    /// the pool may call this if its task was queued after the job.
+    pub fn write<MM: MatrixManager>(&self, mm: &mut MM) {
+        for _ in range(0, self.gens) {
+            let (gen_off, mats) = self.finished.recv();
+            for (off, mat) in mats.iter().enumerate() {
+                mm.set(gen_off as uint + off, *mat);
+            }
+        }
+    }
+}
+
 pub struct ComputedPositionGL {
     pub gen: Vec<(u32, u32)>
 }
@@ -372,15 +636,186 @@ impl CalcPositionsCl {
 #[deriving(Clone)]
 pub struct PositionData {
     location: BTreeMap<ObjectKey, Id>,
-    position: Deltas
+    // shared between generations until one of them changes a location
+    position: Arc<Deltas>,
+    // world matrices that are known to be current, an object is only
+    // cached if its parent is
+    world: BTreeMap<ObjectKey, Matrix4<f32>>,
+    // `Common::moves` and the end of the event log when the cache was last
+    // checked for objects moved with `Common::set_parent`
+    moves: u64,
+    synced: EventSeq
+}
+
+impl Default for PositionData {
+    fn default() -> PositionData { PositionData::new() }
 }
 
 impl PositionData {
     pub fn new() -> PositionData {
         PositionData {
             location: BTreeMap::new(),
-            position: Deltas::new()
+            position: Arc::new(Deltas::new()),
+            world: BTreeMap::new(),
+            moves: 0,
+            synced: EventSeq::start()
+        }
+    }
+
+    fn deltas_mut<'a>(&'a mut self) -> &'a mut Deltas {
+        self.position.make_unique()
+    }
+
+    /// Objects whose local transform was added, removed or changed
+    /// between two generations.
+    pub fn diff(old: &PositionData, new: &PositionData) -> Diff {
+        diff_maps(old.location.iter(), new.location.iter(), |a, b| {
+            let a = old.position.get_delta(*a);
+            let b = new.position.get_delta(*b);
+            a.scale == b.scale && a.rot == b.rot && a.disp == b.disp
+        })
+    }
+
+    /// Pack the locations after objects were removed, the `Id`s of the
+    /// moved locations are updated to match.
+    pub fn compact(&mut self) {
+        let moved = self.deltas_mut().compact();
+        if moved.len() == 0 {
+            return;
+        }
+
+        let mut map = BTreeMap::new();
+        for &(old, new) in moved.iter() {
+            map.insert(old, new);
+        }
+
+        let updated: Vec<(ObjectKey, Id)> = self.location.iter()
+            .filter_map(|(key, id)| map.find(id).map(|new| (*key, *new)))
+            .collect();
+        for &(key, id) in updated.iter() {
+            self.location.insert(key, id);
+        }
+    }
+
+    /// Blend two generations, `alpha` of 0. is `old` and 1. is `new`. The
+    /// result has the layout of `new`, objects that are not in `old` are
+    /// left where `new` put them.
+    pub fn interpolate(old: &PositionData, new: &PositionData, alpha: f32) -> PositionData {
+        let mut out = new.clone();
+        out.world = BTreeMap::new();
+        for (key, id) in new.location.iter() {
+            let a = match old.location.find(key) {
+                Some(id) => old.position.get_delta(*id),
+                None => continue
+            };
+            let b = new.position.get_delta(*id);
+
+            // leave the unchanged nodes shared with `new`
+            if a.scale == b.scale && a.rot == b.rot && a.disp == b.disp {
+                continue;
+            }
+
+            out.deltas_mut().update(*id, Decomposed {
+                scale: a.scale + (b.scale - a.scale) * alpha,
+                rot: a.rot.nlerp(&b.rot, alpha),
+                disp: a.disp.add_v(&b.disp.sub_v(&a.disp).mul_s(alpha))
+            });
+        }
+        out
+    }
+}
+
+impl Snapshot for Deltas {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()> {
+        try!(w.write_le_u32(self.gen.len() as u32));
+        for (&(off, len), (_, gen)) in self.gen.iter().zip(self.delta.iter()) {
+            try!(w.write_le_u32(off));
+            try!(w.write_le_u32(len));
+            try!(w.write_le_u32(gen.len() as u32));
+            for (id, delta) in gen.iter() {
+                try!(w.write_le_u32(*id));
+                try!(w.write_le_u32(delta.parent));
+                try!(delta.delta.write_to(w));
+            }
+        }
+        Ok(())
+    }
+
+    fn read_from(r: &mut Reader) -> IoResult<Deltas> {
+        let mut deltas = Deltas {
+            gen: Vec::new(),
+            delta: BTreeMap::new(),
+            holes: 0
+        };
+
+        for idx in range(0, try!(r.read_le_u32())) {
+            let off = try!(r.read_le_u32());
+            let len = try!(r.read_le_u32());
+            deltas.gen.push((off, len));
+
+            let mut gen = BTreeMap::new();
+            for _ in range(0, try!(r.read_le_u32())) {
+                let id = try!(r.read_le_u32());
+                let parent = try!(r.read_le_u32());
+                let delta = try!(Snapshot::read_from(r));
+                gen.insert(id, Delta {
+                    parent: parent,
+                    children: 0,
+                    delta: delta
+                });
+            }
+            deltas.holes += len as uint - gen.len();
+            deltas.delta.insert(idx, gen);
+        }
+
+        if deltas.gen.len() == 0 {
+            return Err(snapshot::invalid("position block is missing the root"));
+        }
+
+        // the child counts are not stored, count them again
+        for gen in range(1, deltas.gen.len() as u32) {
+            let parents: Vec<u32> = deltas.delta.find(&gen).unwrap().iter().map(|(_, d)| d.parent).collect();
+            let last = deltas.delta.find_mut(&(gen-1)).unwrap();
+            for p in parents.iter() {
+                match last.find_mut(p) {
+                    Some(d) => d.children += 1,
+                    None => return Err(snapshot::invalid("position has no parent"))
+                }
+            }
         }
+        Ok(deltas)
+    }
+}
+
+impl Snapshot for PositionData {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()> {
+        try!(snapshot::write_tag(w, snapshot::TAG_POSITION));
+        try!(w.write_le_u32(self.location.len() as u32));
+        for (oid, &Id(gen, off)) in self.location.iter() {
+            try!(w.write_le_u32(*oid));
+            try!(w.write_le_u32(gen));
+            try!(w.write_le_u32(off));
+        }
+        self.position.write_to(w)
+    }
+
+    fn read_from(r: &mut Reader) -> IoResult<PositionData> {
+        try!(snapshot::expect_tag(r, snapshot::TAG_POSITION));
+        let mut location = BTreeMap::new();
+        for _ in range(0, try!(r.read_le_u32())) {
+            let oid = try!(r.read_le_u32());
+            let gen = try!(r.read_le_u32());
+            let off = try!(r.read_le_u32());
+            location.insert(oid, Id(gen, off));
+        }
+
+        Ok(PositionData {
+            location: location,
+            position: Arc::new(try!(Snapshot::read_from(r))),
+            world: BTreeMap::new(),
+            moves: 0,
+            synced: EventSeq::start()
+        })
     }
 }
 
@@ -388,46 +823,129 @@ pub trait Positions: Common {
     fn get_position<'a>(&'a self) -> &'a PositionData;
     fn get_position_mut<'a>(&'a mut self) -> &'a mut PositionData;
 
-    fn position_id(&mut self, key: ObjectKey) -> Id {
-        if key == 0 {
-            Deltas::root()
-        } else {
-            match self.get_position().location.find(&key) {
-                Some(id) =>  return *id,
-                None => ()
-            }
+    /// A handle to the location of `key`, the object is given an identity
+    /// location if it does not have one.
+    fn position_handle(&mut self, key: ObjectKey) -> Handle {
+        location_id(self, key);
+        Handle(key)
+    }
 
-            let poid = self.object(key).unwrap().parent;
-            let pid = self.position_id(poid);
-            let id = self.get_position_mut().position.insert(pid, Transform::identity());
-            self.get_position_mut().location.insert(key, id);
-            id
+    /// The current `Id` of a handle, or `None` if its location was
+    /// deleted. The `Id` is only good until the next `delete_position`.
+    fn handle_id(&self, handle: Handle) -> Option<Id> {
+        if handle.key() == 0 {
+            Some(Deltas::root())
+        } else {
+            self.get_position().location.find(&handle.key()).map(|id| *id)
         }
     }
 
     fn update_location(&mut self, key: ObjectKey, location: Decomposed<f32, Vector3<f32>, Quaternion<f32>>) {
-        let id = self.position_id(key);
-        self.get_position_mut().position.update(id, location);
+        let id = location_id(self, key);
+        self.get_position_mut().deltas_mut().update(id, location);
+        self.invalidate_world(key);
+        self.log_event(PositionChanged(key));
     }
 
     fn set_to_identity(&mut self, key: ObjectKey) {
-        let id = self.position_id(key);
-        self.get_position_mut().position.update(id, Transform::identity());
+        let id = location_id(self, key);
+        self.get_position_mut().deltas_mut().update(id, Transform::identity());
+        self.invalidate_world(key);
+        self.log_event(PositionChanged(key));
     }
 
     fn set_scale(&mut self, key: ObjectKey, scale: f32) {
-        let id = self.position_id(key);
-        self.get_position_mut().position.get_mut(id).scale = scale;
+        let id = location_id(self, key);
+        self.get_position_mut().deltas_mut().get_mut(id).scale = scale;
+        self.invalidate_world(key);
+        self.log_event(PositionChanged(key));
     }
 
     fn set_displacement(&mut self, key: ObjectKey, disp: Vector3<f32>) {
-        let id = self.position_id(key);
-        self.get_position_mut().position.get_mut(id).disp = disp;
+        let id = location_id(self, key);
+        self.get_position_mut().deltas_mut().get_mut(id).disp = disp;
+        self.invalidate_world(key);
+        self.log_event(PositionChanged(key));
     }
 
     fn set_rotation(&mut self, key: ObjectKey, rot: Quaternion<f32>) {
-        let id = self.position_id(key);
-        self.get_position_mut().position.get_mut(id).rot = rot;
+        let id = location_id(self, key);
+        self.get_position_mut().deltas_mut().get_mut(id).rot = rot;
+        self.invalidate_world(key);
+        self.log_event(PositionChanged(key));
+    }
+
+    /// Move an object and its children under a new parent with
+    /// `Common::set_parent`, and move their locations along with them. Each
+    /// moved location is reinserted one generation below its new parent,
+    /// keeping its local transform, and the cached world matrices of the
+    /// subtree are dropped. Returns false if `set_parent` refused the move.
+    fn move_object(&mut self, key: ObjectKey, parent: Option<ObjectKey>) -> bool {
+        let old_parent = match self.object(key) {
+            Some(obj) => obj.parent,
+            None => return false
+        };
+        if !self.set_parent(key, parent) {
+            return false;
+        }
+        if self.object(key).unwrap().parent == old_parent {
+            return true;
+        }
+
+        let subtree = self.subtree(key);
+        let old: Vec<Id> = subtree.iter()
+            .filter_map(|k| self.get_position().location.find(k).map(|id| *id))
+            .collect();
+
+        for k in subtree.iter().rev() {
+            let delta = match self.location(*k) {
+                Some(delta) => delta,
+                None => continue
+            };
+
+            let poid = self.object(*k).unwrap().parent;
+            let pid = location_id(self, poid);
+            let id = self.get_position_mut().deltas_mut().insert(pid, delta);
+            self.get_position_mut().location.insert(*k, id);
+            self.log_event(PositionChanged(*k));
+        }
+
+        // children first, so that each old location is empty when it is removed
+        for id in old.iter() {
+            self.get_position_mut().deltas_mut().remove(*id);
+        }
+        sync_world(self);
+        true
+    }
+
+    /// Give each copy made by `Common::instantiate` the local transform of
+    /// the object it was copied from.
+    fn instantiate_position(&mut self, remap: &Remap) {
+        for (old, new) in remap.iter() {
+            let delta = self.location(*old);
+            match delta {
+                Some(delta) => self.update_location(*new, delta),
+                None => ()
+            }
+        }
+    }
+
+    /// Drop the location of an object that was removed from `Common`. The
+    /// children of an object must be dropped before it, in the order
+    /// returned by `Common::delete_subtree`. The locations are compacted
+    /// once half of the slots are empty, which moves their `Id`s, a
+    /// `Handle` is still good afterwards.
+    fn delete_position(&mut self, key: ObjectKey) {
+        self.get_position_mut().world.remove(&key);
+        let id = match self.get_position_mut().location.pop(&key) {
+            Some(id) => id,
+            None => return
+        };
+        self.get_position_mut().deltas_mut().remove(id);
+
+        if self.get_position().position.holes() * 2 > self.position_count() {
+            self.get_position_mut().compact();
+        }
     }
 
     fn location(&self, key: ObjectKey) -> Option<Decomposed<f32, Vector3<f32>, Quaternion<f32>>> {
@@ -437,7 +955,18 @@ pub trait Positions: Common {
         }
     }
 
+    /// The world matrix of an object. Cached matrices are used as is, the
+    /// rest are worked out from the closest cached ancestor. A generation
+    /// that is only borrowed can not fill its cache, so anything left
+    /// uncached still costs a walk up to that ancestor on every call.
+    /// The cache is not used at all while an object moved with
+    /// `Common::set_parent` has not been synced by `cache_position`.
     fn position(&self, oid: ObjectKey) -> Matrix4<f32> {
+        match self.cached_world(oid) {
+            Some(mat) => return mat,
+            None => ()
+        }
+
         let obj = self.object(oid);
         let p_mat = match obj {
             Some(obj) => self.position(obj.parent),
@@ -451,10 +980,100 @@ pub trait Positions: Common {
         p_mat.mul_m(&loc)
     }
 
+    /// The skinning palette of a set of joints, the world matrix of each
+    /// joint times its inverse bind matrix. World matrices that are not
+    /// cached are worked out from the `Deltas` of this generation.
+    fn palette(&self, joints: &[ObjectKey], inverse_bind: &[Matrix4<f32>]) -> Vec<Matrix4<f32>> {
+        joints.iter().zip(inverse_bind.iter()).map(|(joint, inv)| {
+            let pos = self.get_position();
+            let world = match (self.cached_world(*joint), pos.location.find(joint)) {
+                (Some(mat), _) => mat,
+                (None, Some(id)) => pos.position.get_mat(*id),
+                (None, None) => self.position(*joint)
+            };
+            world.mul_m(inv)
+        }).collect()
+    }
+
+    /// Like `position`, but caches the matrix of the object and its
+    /// ancestors. The cache is part of the generation, older generations
+    /// keep their own. The subtrees moved with `Common::set_parent` since
+    /// the last call are dropped from the cache first.
+    fn cache_position(&mut self, oid: ObjectKey) -> Matrix4<f32> {
+        sync_world(self);
+        match self.get_position().world.find(&oid) {
+            Some(mat) => return *mat,
+            None => ()
+        }
+
+        let parent = match self.object(oid) {
+            Some(obj) => obj.parent,
+            None => return Matrix4::identity()
+        };
+        let p_mat = if parent == 0 {
+            Matrix4::identity()
+        } else {
+            self.cache_position(parent)
+        };
+
+        let loc = match self.location(oid) {
+            Some(t) => {t.to_matrix4()},
+            None => Matrix4::identity()
+        };
+        let mat = p_mat.mul_m(&loc);
+        self.get_position_mut().world.insert(oid, mat);
+        mat
+    }
+
+    /// Cache the world matrix of every object that has a location, this
+    /// is best done once a step after the game has moved things around and
+    /// before the generation is handed to the renderer or committed to a
+    /// `History`. Readers of that generation then get every lookup cached.
+    fn refresh_positions(&mut self) {
+        let keys: Vec<ObjectKey> = self.location_iter().map(|(k, _)| *k).collect();
+        for k in keys.iter() {
+            self.cache_position(*k);
+        }
+    }
+
+    fn world_cached(&self, oid: ObjectKey) -> bool {
+        self.cached_world(oid).is_some()
+    }
+
+    /// The cached world matrix of an object, `None` if it is not cached or
+    /// if the cache has not been synced since an object was moved.
+    fn cached_world(&self, oid: ObjectKey) -> Option<Matrix4<f32>> {
+        let pos = self.get_position();
+        if pos.moves != self.moves() {
+            return None;
+        }
+        pos.world.find(&oid).map(|mat| *mat)
+    }
+
+    /// Drop the cached world matrix of `key` and of everything below it.
+    /// Nothing below an uncached object is cached, so the walk stops there.
+    /// The location setters call this, objects moved with
+    /// `Common::set_parent` are dropped by `cache_position`.
+    fn invalidate_world(&mut self, key: ObjectKey) {
+        if self.get_position_mut().world.remove(&key) {
+            for child in self.children(key).iter() {
+                self.invalidate_world(*child);
+            }
+        }
+    }
+
     fn write_positions<MM: MatrixManager>(&self, mm: &mut MM) {
         self.get_position().position.write_positions(mm)
     }
 
+    fn write_positions_parallel<T, MM: MatrixManager>(&self, tp: &mut TaskPool<T>, mm: &mut MM) {
+        Deltas::write_positions_parallel(&self.get_position().position, tp, mm)
+    }
+
+    fn queue_positions<T>(&self, tp: &mut TaskPool<T>) -> PositionJob {
+        Deltas::queue_positions(&self.get_position().position, tp)
+    }
+
     fn write_positions_cl_vec4x4(&self, cq: &CommandQueue,
                         ctx: &mut CalcPositionsCl, out: &[CLBuffer<Vector4<f32>>, ..4]) -> Event {
         self.get_position().position.write_positions_cl_vec4x4(cq, ctx, out)
@@ -474,8 +1093,6 @@ pub trait Positions: Common {
     }
 
     fn position_count(&self) -> uint {
-        let last = self.get_position().position.gen.len();
-        let (s, l) = *self.get_position().position.gen.get(last-1);
-        (s + l) as uint
+        self.get_position().position.len()
     }
 }
@@ -6,18 +6,51 @@ extern crate snowmew;
 extern crate cgmath;
 extern crate OpenCL;
 extern crate cow;
+extern crate sync;
+extern crate test;
 extern crate position = "snowmew-position";
 
-use position::Deltas;
+use std::sync::TaskPool;
+use sync::Arc;
+use test::Bencher;
+
+use position::{Deltas, Positions, PositionData, PARALLEL_CHUNK};
 use position::CalcPositionsCl;
 
+use snowmew::common::{Common, CommonData};
+
 use cgmath::matrix::{Matrix4, Matrix};
-use cgmath::transform::Decomposed;
+use cgmath::transform::{Transform, Decomposed};
 use cgmath::quaternion::Quaternion;
 use cgmath::vector::{Vector3, Vector4};
 
 use OpenCL::hl::EventList;
 
+#[deriving(Clone)]
+struct TestData {
+    common: CommonData,
+    position: PositionData
+}
+
+impl TestData {
+    fn new() -> TestData {
+        TestData {
+            common: CommonData::new(),
+            position: PositionData::new()
+        }
+    }
+}
+
+impl Common for TestData {
+    fn get_common<'a>(&'a self) -> &'a CommonData { &self.common }
+    fn get_common_mut<'a>(&'a mut self) -> &'a mut CommonData { &mut self.common }
+}
+
+impl Positions for TestData {
+    fn get_position<'a>(&'a self) -> &'a PositionData { &self.position }
+    fn get_position_mut<'a>(&'a mut self) -> &'a mut PositionData { &mut self.position }
+}
+
 #[test]
 fn insert_children() {
     let mut pos = Deltas::new();
@@ -219,3 +252,370 @@ fn calc_positions_opencl_tree() {
     assert!(mat2.mul_v(&vec) == Vector4::new(0f32, 0f32, 0f32, 1f32));
     assert!(mat3.mul_v(&vec) == Vector4::new(-2f32, -2f32, -2f32, 1f32));
 }
+
+#[test]
+fn calc_positions_opencl_holes() {
+    let (device, context, queue) = match OpenCL::util::create_compute_context_prefer(OpenCL::util::GPUPrefered) {
+        Ok(cl) => cl,
+        Err(_) => return
+    };
+    let mut ctx = CalcPositionsCl::new(&context, &device);
+
+    let mut pos = Deltas::new();
+    let d = Decomposed{scale: 1f32, rot: Quaternion::identity(), disp: Vector3::new(1f32, 0f32, 0f32)};
+    let a = pos.insert(Deltas::root(), d);
+    let b = pos.insert(Deltas::root(), d);
+    let c = pos.insert(Deltas::root(), d);
+    let a_child = pos.insert(a, d);
+    let c_child = pos.insert(c, d);
+    let b_child = pos.insert(b, d);
+    let buffers: [OpenCL::mem::CLBuffer<Matrix4<f32>>, ..1]
+                = [context.create_buffer(pos.len(), OpenCL::CL::CL_MEM_READ_WRITE)];
+
+    // fill the upload buffers once, then leave holes in both generations
+    pos.write_positions_cl_mat4(&queue, &mut ctx, buffers.as_slice()).wait();
+    assert!(pos.remove(b_child));
+    assert!(pos.remove(b));
+    assert!(pos.holes() == 2);
+
+    let size = pos.len();
+    let mut serial = Vec::from_elem(size, Matrix4::identity());
+    pos.write_positions(&mut serial.as_mut_slice());
+    pos.write_positions_cl_mat4(&queue, &mut ctx, buffers.as_slice()).wait();
+    let cl: Vec<Matrix4<f32>> = queue.get(&buffers[0], ());
+
+    for id in [a, c, a_child, c_child].iter() {
+        let loc = pos.get_loc(*id);
+        assert!(serial.get(loc) == cl.get(loc));
+    }
+    // the hole in generation 1 is an identity under the root
+    assert!(*cl.get(pos.get_loc(b)) == Matrix4::identity());
+}
+
+#[test]
+fn move_object() {
+    let mut db = TestData::new();
+
+    let a = db.new_object(None, "a");
+    let b = db.new_object(None, "b");
+    let child = db.new_object(Some(a), "child");
+
+    db.set_displacement(a, Vector3::new(1f32, 1f32, 1f32));
+    db.set_displacement(b, Vector3::new(-1f32, -1f32, -1f32));
+    db.set_displacement(child, Vector3::new(1f32, 1f32, 1f32));
+
+    assert!(db.move_object(child, Some(b)));
+
+    let vec = Vector4::new(0f32, 0f32, 0f32, 1f32);
+    let mut mats: &mut [Matrix4<f32>] = &mut [Matrix4::identity(), Matrix4::identity(), Matrix4::identity(), Matrix4::identity(),
+                                              Matrix4::identity(), Matrix4::identity(), Matrix4::identity(), Matrix4::identity()];
+    db.write_positions(&mut mats);
+    let pos = db.compute_positions();
+    let id = db.handle_id(db.position_handle(child)).unwrap();
+
+    assert!(db.position(child).mul_v(&vec) == Vector4::new(0f32, 0f32, 0f32, 1f32));
+    assert!(mats[pos.get_loc(id)].mul_v(&vec) == Vector4::new(0f32, 0f32, 0f32, 1f32));
+}
+
+#[test]
+fn interpolate_positions() {
+    let mut db = TestData::new();
+
+    let a = db.new_object(None, "a");
+    let b = db.new_object(None, "b");
+    db.set_displacement(a, Vector3::new(0f32, 0f32, 0f32));
+    db.set_displacement(b, Vector3::new(1f32, 1f32, 1f32));
+    let old = db.clone();
+
+    db.set_displacement(a, Vector3::new(2f32, 4f32, 8f32));
+    let c = db.new_object(None, "c");
+    db.set_displacement(c, Vector3::new(3f32, 3f32, 3f32));
+
+    let mut mid = db.clone();
+    mid.position = PositionData::interpolate(old.get_position(), db.get_position(), 0.5);
+
+    assert!(mid.location(a).unwrap().disp == Vector3::new(1f32, 2f32, 4f32));
+    assert!(mid.location(b).unwrap().disp == Vector3::new(1f32, 1f32, 1f32));
+    assert!(mid.location(c).unwrap().disp == Vector3::new(3f32, 3f32, 3f32));
+
+    let end = PositionData::interpolate(old.get_position(), db.get_position(), 1.);
+    assert!(PositionData::diff(&end, db.get_position()).is_empty());
+}
+
+#[test]
+fn instantiate_positions() {
+    let mut db = TestData::new();
+
+    let prefab = db.new_object(None, "prefab");
+    let child = db.new_object(Some(prefab), "child");
+    let other = db.new_object(None, "other");
+    db.set_displacement(prefab, Vector3::new(1f32, 0f32, 0f32));
+    db.set_displacement(child, Vector3::new(0f32, 1f32, 0f32));
+    db.set_displacement(other, Vector3::new(0f32, 0f32, 5f32));
+
+    let remap = db.instantiate(prefab, Some(other), "copy").unwrap();
+    db.instantiate_position(&remap);
+    let child2 = remap.get(child).unwrap();
+
+    let vec = Vector4::new(0f32, 0f32, 0f32, 1f32);
+    assert!(db.location(child2).unwrap().disp == Vector3::new(0f32, 1f32, 0f32));
+    assert!(db.position(child2).mul_v(&vec) == Vector4::new(1f32, 1f32, 5f32, 1f32));
+    assert!(db.position(child).mul_v(&vec) == Vector4::new(1f32, 1f32, 0f32, 1f32));
+}
+
+#[test]
+fn remove_and_compact() {
+    let mut pos = Deltas::new();
+    let d = Decomposed{scale: 1f32, rot: Quaternion::identity(), disp: Vector3::new(1f32, 0f32, 0f32)};
+
+    let a = pos.insert(Deltas::root(), d);
+    let b = pos.insert(Deltas::root(), d);
+    let b_child = pos.insert(b, d);
+
+    // locations with children can not be removed
+    assert!(!pos.remove(b));
+    assert!(!pos.remove(Deltas::root()));
+    assert!(pos.remove(a));
+    assert!(!pos.remove(a));
+    assert!(pos.holes() == 1);
+
+    let moved = pos.compact();
+    assert!(pos.holes() == 0);
+    assert!(moved.len() == 1);
+    let &(old, new) = moved.get(0);
+    assert!(old == b);
+    assert!(pos.get_loc(new) == 1);
+
+    let mut mats: &mut [Matrix4<f32>] = &mut [Matrix4::identity(), Matrix4::identity(), Matrix4::identity()];
+    pos.write_positions(&mut mats);
+    let vec = Vector4::new(0f32, 0f32, 0f32, 1f32);
+    assert!(mats[pos.get_loc(new)].mul_v(&vec) == Vector4::new(1f32, 0f32, 0f32, 1f32));
+    assert!(mats[2].mul_v(&vec) == Vector4::new(2f32, 0f32, 0f32, 1f32));
+    assert!(pos.get_loc(b_child) == 2);
+}
+
+#[test]
+fn delete_positions_compacts() {
+    let mut db = TestData::new();
+
+    let keys: Vec<u32> = range(0u, 4).map(|i| {
+        let key = db.new_object(None, format!("obj_{}", i).as_slice());
+        db.set_displacement(key, Vector3::new(i as f32, 0f32, 0f32));
+        key
+    }).collect();
+    assert!(db.position_count() == 5);
+
+    for k in keys.slice(0, 3).iter() {
+        db.delete_object(*k);
+        db.delete_position(*k);
+    }
+    // three of the five slots were empty, so the locations were packed
+    assert!(db.position_count() == 2);
+
+    let last = *keys.get(3);
+    let vec = Vector4::new(0f32, 0f32, 0f32, 1f32);
+    let mut mats: &mut [Matrix4<f32>] = &mut [Matrix4::identity(), Matrix4::identity()];
+    db.write_positions(&mut mats);
+    let pos = db.compute_positions();
+    let id = db.handle_id(db.position_handle(last)).unwrap();
+    assert!(mats[pos.get_loc(id)].mul_v(&vec) == Vector4::new(3f32, 0f32, 0f32, 1f32));
+}
+
+#[test]
+fn handle_survives_compaction() {
+    let mut db = TestData::new();
+
+    let keys: Vec<u32> = range(0u, 4).map(|i| {
+        let key = db.new_object(None, format!("obj_{}", i).as_slice());
+        db.set_displacement(key, Vector3::new(i as f32, 0f32, 0f32));
+        key
+    }).collect();
+    let handle = db.position_handle(*keys.get(3));
+    let before = db.handle_id(handle).unwrap();
+
+    for k in keys.slice(0, 3).iter() {
+        db.delete_object(*k);
+        db.delete_position(*k);
+    }
+    assert!(db.position_count() == 2);
+
+    // the slot moved, the handle follows it
+    let after = db.handle_id(handle).unwrap();
+    assert!(after != before);
+    let vec = Vector4::new(0f32, 0f32, 0f32, 1f32);
+    let mut mats: &mut [Matrix4<f32>] = &mut [Matrix4::identity(), Matrix4::identity()];
+    db.write_positions(&mut mats);
+    let pos = db.compute_positions();
+    assert!(mats[pos.get_loc(after)].mul_v(&vec) == Vector4::new(3f32, 0f32, 0f32, 1f32));
+
+    db.delete_object(*keys.get(3));
+    db.delete_position(*keys.get(3));
+    assert!(db.handle_id(handle).is_none());
+}
+
+#[test]
+fn world_cache_invalidates_subtree() {
+    let mut db = TestData::new();
+
+    let a = db.new_object(None, "a");
+    let a_child = db.new_object(Some(a), "child");
+    let b = db.new_object(None, "b");
+    db.set_displacement(a, Vector3::new(1f32, 0f32, 0f32));
+    db.set_displacement(a_child, Vector3::new(0f32, 1f32, 0f32));
+    db.set_displacement(b, Vector3::new(0f32, 0f32, 1f32));
+
+    db.refresh_positions();
+    assert!(db.world_cached(a) && db.world_cached(a_child) && db.world_cached(b));
+    let old = db.clone();
+
+    db.set_displacement(a, Vector3::new(2f32, 0f32, 0f32));
+    assert!(!db.world_cached(a) && !db.world_cached(a_child));
+    assert!(db.world_cached(b));
+
+    let vec = Vector4::new(0f32, 0f32, 0f32, 1f32);
+    assert!(db.position(a_child).mul_v(&vec) == Vector4::new(2f32, 1f32, 0f32, 1f32));
+    assert!(db.cache_position(a_child).mul_v(&vec) == Vector4::new(2f32, 1f32, 0f32, 1f32));
+    assert!(db.world_cached(a));
+
+    // the older generation kept its own cache
+    assert!(old.world_cached(a_child));
+    assert!(old.position(a_child).mul_v(&vec) == Vector4::new(1f32, 1f32, 0f32, 1f32));
+}
+
+#[test]
+fn world_cache_follows_move() {
+    let mut db = TestData::new();
+
+    let a = db.new_object(None, "a");
+    let b = db.new_object(None, "b");
+    let child = db.new_object(Some(a), "child");
+    let leaf = db.new_object(Some(child), "leaf");
+    db.set_displacement(a, Vector3::new(1f32, 0f32, 0f32));
+    db.set_displacement(b, Vector3::new(0f32, 0f32, 1f32));
+    db.set_displacement(leaf, Vector3::new(0f32, 1f32, 0f32));
+
+    db.refresh_positions();
+    assert!(db.world_cached(child) && db.world_cached(leaf));
+
+    assert!(db.move_object(child, Some(b)));
+    assert!(!db.world_cached(child) && !db.world_cached(leaf));
+    assert!(db.world_cached(a) && db.world_cached(b));
+
+    let vec = Vector4::new(0f32, 0f32, 0f32, 1f32);
+    assert!(db.cache_position(leaf).mul_v(&vec) == Vector4::new(0f32, 1f32, 1f32, 1f32));
+}
+
+#[test]
+fn world_cache_follows_set_parent() {
+    let mut db = TestData::new();
+
+    let a = db.new_object(None, "a");
+    let b = db.new_object(None, "b");
+    let child = db.new_object(Some(a), "child");
+    let leaf = db.new_object(Some(child), "leaf");
+    db.set_displacement(a, Vector3::new(1f32, 0f32, 0f32));
+    db.set_displacement(b, Vector3::new(0f32, 0f32, 1f32));
+    db.set_displacement(leaf, Vector3::new(0f32, 1f32, 0f32));
+
+    db.refresh_positions();
+    assert!(db.world_cached(leaf));
+
+    // moved without `move_object`, the stale matrices are not used
+    assert!(db.set_parent(child, Some(b)));
+    assert!(!db.world_cached(leaf));
+    let vec = Vector4::new(0f32, 0f32, 0f32, 1f32);
+    assert!(db.position(leaf).mul_v(&vec) == Vector4::new(0f32, 1f32, 1f32, 1f32));
+
+    // caching drops the moved subtree and keeps the rest
+    assert!(db.cache_position(leaf).mul_v(&vec) == Vector4::new(0f32, 1f32, 1f32, 1f32));
+    assert!(db.world_cached(a) && db.world_cached(leaf));
+    assert!(db.position(child).mul_v(&vec) == Vector4::new(0f32, 0f32, 1f32, 1f32));
+}
+
+// a wide tree, each generation is several chunks long
+fn wide_tree(gens: uint, width: uint) -> Deltas {
+    let mut pos = Deltas::new();
+    let d = Decomposed{scale: 1f32, rot: Quaternion::identity(), disp: Vector3::new(1f32, 0f32, 0f32)};
+    let mut parents = vec!(Deltas::root());
+    for _ in range(0, gens) {
+        parents = range(0, width).map(|i| {
+            pos.insert(*parents.get(i % parents.len()), d)
+        }).collect();
+    }
+    pos
+}
+
+#[test]
+fn write_positions_parallel() {
+    let mut tp = TaskPool::new(4, || { proc(_) { () } });
+    let mut pos = wide_tree(3, PARALLEL_CHUNK * 2 + 7);
+    // leave a hole at the end of generation 1
+    let hole = pos.insert(Deltas::root(), Transform::identity());
+    assert!(pos.remove(hole));
+    let pos = Arc::new(pos);
+
+    let size = pos.len();
+    let mut serial = Vec::from_elem(size, Matrix4::identity());
+    let mut parallel = Vec::from_elem(size, Matrix4::identity());
+    pos.write_positions(&mut serial.as_mut_slice());
+    Deltas::write_positions_parallel(&pos, &mut tp, &mut parallel.as_mut_slice());
+    assert!(serial == parallel);
+
+    let vec = Vector4::new(0f32, 0f32, 0f32, 1f32);
+    assert!(serial.get(size-1).mul_v(&vec) == Vector4::new(3f32, 0f32, 0f32, 1f32));
+}
+
+#[test]
+fn queue_positions_from_worker() {
+    let mut tp = TaskPool::new(2, || { proc(_) { () } });
+    let pos = Arc::new(wide_tree(2, PARALLEL_CHUNK * 3));
+    let size = pos.len();
+    let mut serial = Vec::from_elem(size, Matrix4::identity());
+    pos.write_positions(&mut serial.as_mut_slice());
+
+    // like the render pool, the task that copies the matrices is one of the
+    // workers and is queued after the job
+    let job = Deltas::queue_positions(&pos, &mut tp);
+    let (sender, receiver) = channel();
+    tp.execute(proc(_) {
+        let mut mats = Vec::from_elem(size, Matrix4::identity());
+        job.write(&mut mats.as_mut_slice());
+        sender.send(mats);
+    });
+    assert!(receiver.recv() == serial);
+}
+
+#[bench]
+fn bench_write_positions(b: &mut Bencher) {
+    let pos = wide_tree(4, 16 * 1024);
+    let mut mats = Vec::from_elem(pos.len(), Matrix4::identity());
+    b.iter(|| {
+        pos.write_positions(&mut mats.as_mut_slice());
+    });
+}
+
+#[bench]
+fn bench_write_positions_parallel(b: &mut Bencher) {
+    let mut tp = TaskPool::new(4, || { proc(_) { () } });
+    let pos = Arc::new(wide_tree(4, 16 * 1024));
+    let mut mats = Vec::from_elem(pos.len(), Matrix4::identity());
+    b.iter(|| {
+        Deltas::write_positions_parallel(&pos, &mut tp, &mut mats.as_mut_slice());
+    });
+}
+
+#[bench]
+fn bench_write_positions_cl_mat4(b: &mut Bencher) {
+    // nothing to measure without an OpenCL device
+    let (device, context, queue) = match OpenCL::util::create_compute_context_prefer(OpenCL::util::GPUPrefered) {
+        Ok(cl) => cl,
+        Err(_) => return
+    };
+    let mut ctx = CalcPositionsCl::new(&context, &device);
+    let pos = wide_tree(4, 16 * 1024);
+    let buffers: [OpenCL::mem::CLBuffer<Matrix4<f32>>, ..1]
+                = [context.create_buffer(pos.len(), OpenCL::CL::CL_MEM_READ_WRITE)];
+    b.iter(|| {
+        pos.write_positions_cl_mat4(&queue, &mut ctx, buffers.as_slice()).wait();
+    });
+}
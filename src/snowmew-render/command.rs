@@ -3,7 +3,7 @@ use std::ptr;
 use std::mem;
 use std::slice::raw::mut_buf_as_slice;
 
-use cow::join::{join_set_to_map};
+use snowmew::query::query;
 
 
 use libc::c_void;
@@ -17,7 +17,7 @@ use cgmath::vector::{Vector, EuclideanVector};
 
 use config::Config;
 use graphics::Graphics;
-use snowmew::common::ObjectKey;
+use snowmew::common::{ObjectKey, Common, Layers};
 
 
 use db::GlState;
@@ -103,7 +103,8 @@ impl CommandBufferIndirect {
         gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, 0);
     }
 
-    pub fn build<GD: Graphics>(&mut self, db: &GD, scene: ObjectKey, instanced_is_enabled: bool) {
+    /// Only drawables in `scene` that are on one of the `layers` are drawn.
+    pub fn build<GD: Graphics>(&mut self, db: &GD, scene: ObjectKey, layers: Layers, instanced_is_enabled: bool) {
         let mut batch = Batch {
             vbo: 0,
             offset: 0,
@@ -123,7 +124,8 @@ impl CommandBufferIndirect {
         unsafe {
             self.batches.truncate(0);
             mut_buf_as_slice(self.ptr, self.size, |b| {
-                for (count, (_, draw)) in join_set_to_map(db.scene_iter(scene), db.drawable_iter()).enumerate() {
+                for (count, (_, draw)) in query(db.drawable_iter()).in_scene(db.get_common(), scene)
+                                                                   .in_layers(db.get_common(), layers).enumerate() {
                     if idx == -1 {
                         let draw_geo = db.geometry(draw.geometry).expect("geometry not found");
                         last_geo = Some(draw.geometry);
@@ -238,7 +240,7 @@ impl CommandBufferEmulated {
     pub fn map(&mut self) {}
     pub fn unmap(&mut self) {}
 
-    pub fn build<GD: Graphics>(&mut self, db: &GD) {
+    pub fn build<GD: Graphics>(&mut self, db: &GD, scene: ObjectKey, layers: Layers) {
         let mut batch = Batch {
             vbo: 0,
             offset: 0,
@@ -247,7 +249,8 @@ impl CommandBufferEmulated {
 
         self.batches.truncate(0);
         self.commands.truncate(0);
-        for (count, (_, draw)) in db.drawable_iter().enumerate() {
+        for (count, (_, draw)) in query(db.drawable_iter()).in_scene(db.get_common(), scene)
+                                                           .in_layers(db.get_common(), layers).enumerate() {
             let draw_geo = db.geometry(draw.geometry).expect("geometry not found");
 
             self.commands.push(DrawElementsIndirectCommand {
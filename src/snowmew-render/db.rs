@@ -1,7 +1,8 @@
 use cow::btree::BTreeMap;
-use graphics::{Graphics};
+use graphics::Graphics;
 use snowmew::common::{Common};
 use snowmew::common::ObjectKey;
+use snowmew::event::{EventSeq, VertexBufferAdded, ObjectRemoved};
 use {RenderData};
 
 use vertex_buffer::VertexBuffer;
@@ -28,7 +29,8 @@ pub struct GlState {
     pub defered_shader_point_light: Option<Shader>,
     pub ovr_shader: Option<Shader>,
     pub compute_cull: Option<Shader>,
-    pub texture: TextureAtlas
+    pub texture: TextureAtlas,
+    event_seq: Option<EventSeq>
 }
 
 impl GlState {
@@ -41,8 +43,8 @@ impl GlState {
             defered_shader_point_light: None,
             ovr_shader: None,
             compute_cull: None,
-            texture: TextureAtlas::new()
-
+            texture: TextureAtlas::new(),
+            event_seq: None
         }
     }
 
@@ -59,17 +61,55 @@ impl GlState {
     fn load_vertex(&mut self, db: &RenderData, _: &Config) {
         let mut vertex = self.vertex.clone();
 
-        for (oid, vbo) in db.vertex_buffer_iter() {
-            match vertex.find(oid) {
-                Some(_) => (),
-                None => {
+        // only look at the buffers that changed since the last generation
+        let events = match self.event_seq {
+            Some(ref seq) => db.events_since(seq.clone()),
+            None => None
+        };
+
+        let added: Vec<ObjectKey> = match events {
+            Some(events) => {
+                let mut added = Vec::new();
+                for (_, ev) in events {
+                    match *ev {
+                        VertexBufferAdded(oid) => added.push(oid),
+                        ObjectRemoved(oid) => { vertex.remove(&oid); }
+                        _ => ()
+                    }
+                }
+                added
+            }
+            None => {
+                // the log does not reach back far enough or this generation
+                // is from another branch, compare what is loaded against
+                // every buffer
+                let removed: Vec<ObjectKey> = vertex.iter()
+                    .filter(|&(oid, _)| db.vertex_buffer(*oid).is_none())
+                    .map(|(oid, _)| *oid)
+                    .collect();
+                for oid in removed.iter() {
+                    vertex.remove(oid);
+                }
+                db.vertex_buffer_iter()
+                    .filter(|&(oid, _)| vertex.find(oid).is_none())
+                    .map(|(oid, _)| *oid)
+                    .collect()
+            }
+        };
+
+        for oid in added.iter() {
+            match db.vertex_buffer(*oid) {
+                Some(vbo) => {
                     let vb = VertexBuffer::new(&vbo.vertex, vbo.index.as_slice());
                     vertex.insert(*oid, vb);
                 }
+                // removed later in the same batch of events
+                None => ()
             }
         }
 
-        self.vertex = vertex; 
+        self.vertex = vertex;
+        self.event_seq = Some(db.event_seq());
     }
 
     fn load_shaders(&mut self, _: &RenderData, cfg: &Config) {
@@ -83,7 +123,8 @@ impl GlState {
         if self.geometry_no_ssbo.is_none() {
             self.geometry_no_ssbo = Some(
                 Shader::new(GEO_PASS_VERTEX, GEO_PASS_FRAG, 
-                    &[(0, "in_position"), (1, "in_texture"), (2, "in_normal")],
+                    &[(0, "in_position"), (1, "in_texture"), (2, "in_normal"),
+                      (4, "in_joints"), (5, "in_weights")],
                     &[(0, "out_uv"), (1, "out_normal"), (2, "out_material"), (3, "out_dxdt")],
                     Some(HEADER_410)
             )); 
@@ -91,7 +132,8 @@ impl GlState {
         if cfg.ssbo() && self.geometry_ssbo_drawid.is_none() {
             self.geometry_ssbo_drawid = Some(
                 Shader::new(GEO_PASS_VERTEX, GEO_PASS_FRAG, 
-                    &[(0, "in_position"), (1, "in_texture"), (2, "in_normal")],
+                    &[(0, "in_position"), (1, "in_texture"), (2, "in_normal"),
+                      (4, "in_joints"), (5, "in_weights")],
                     &[(0, "out_uv"), (1, "out_normal"), (2, "out_material"), (3, "out_dxdt")],
                     Some(HEADER_430)
             )); 
@@ -11,7 +11,7 @@ use gl;
 
 use position::{Positions, PositionData};
 use graphics::{Graphics, GraphicsData};
-use snowmew::common::{Common, CommonData};
+use snowmew::common::{Common, CommonData, Layers};
 use snowmew::ObjectKey;
 
 use db::GlState;
@@ -21,6 +21,7 @@ use light::LightsBuffer;
 use model::{ModelInfoTextureBuffer, ModelInfoSSBOBuffer};
 use matrix::{MatrixSSBOBuffer, MatrixTextureBuffer};
 use command::{CommandBufferIndirect, CommandBufferEmulated};
+use skin::PaletteBuffer;
 
 pub trait Drawlist: RenderData {
     // This is done on the OpenGL thread, this will map and setup
@@ -31,7 +32,8 @@ pub trait Drawlist: RenderData {
     // data from the scene graph into the any mapped buffers. This can also
     // spawn multiple workers. One of the threads must send the drawlist
     // back to the server
-    fn setup_compute(~self, db: &RenderData, tp: &mut TaskPool<Sender<Box<Drawlist+Send>>>, scene: ObjectKey);
+    fn setup_compute(~self, db: &RenderData, tp: &mut TaskPool<Sender<Box<Drawlist+Send>>>,
+                     scene: ObjectKey, layers: Layers);
 
     // setup on the OpenGL thread, this will unmap and sync anything that
     // is needed to be done
@@ -84,27 +86,11 @@ impl Positions for DrawlistNoSSBO {
 
 impl RenderData for DrawlistNoSSBO {}
 
-#[deriving(Clone)]
-struct DrawlistGraphicsData {
-    common: CommonData,
-    graphics: GraphicsData,
-    position: PositionData,    
-}
-
-impl Common for DrawlistGraphicsData {
-    fn get_common<'a>(&'a self) -> &'a CommonData { &self.common }
-    fn get_common_mut<'a>(&'a mut self) -> &'a mut CommonData { &mut self.common }
-}
-
-impl Graphics for DrawlistGraphicsData {
-    fn get_graphics<'a>(&'a self) -> &'a GraphicsData { &self.graphics }
-    fn get_graphics_mut<'a>(&'a mut self) -> &'a mut GraphicsData { &mut self.graphics }
-}
-
-impl Positions for DrawlistGraphicsData {
-    fn get_position<'a>(&'a self) -> &'a PositionData { &self.position }
-    fn get_position_mut<'a>(&'a mut self) -> &'a mut PositionData { &mut self.position }
-}
+game_data!(DrawlistGraphicsData {
+    common: CommonData => Common { get_common, get_common_mut },
+    graphics: GraphicsData => Graphics { get_graphics, get_graphics_mut },
+    position: PositionData => Positions { get_position, get_position_mut }
+})
 
 impl RenderData for DrawlistGraphicsData {}
 
@@ -116,6 +102,7 @@ pub struct DrawlistNoSSBO {
     model: ModelInfoTextureBuffer,
     matrix: MatrixTextureBuffer,
     command: CommandBufferEmulated,
+    palette: PaletteBuffer,
 
     size: uint,
     start: f64
@@ -126,17 +113,14 @@ impl DrawlistNoSSBO {
                        cl: Option<(Arc<Context>, Arc<CommandQueue>, Arc<Device>)>) -> DrawlistNoSSBO {
 
         DrawlistNoSSBO {
-            data: DrawlistGraphicsData {
-                common: CommonData::new(),
-                graphics: GraphicsData::new(),
-                position: PositionData::new()
-            },
+            data: DrawlistGraphicsData::empty(),
             size: cfg.max_size(),
             materials: MaterialBuffer::new(512),
             lights: LightsBuffer::new(),
             model: ModelInfoTextureBuffer::new(cfg),
             matrix: MatrixTextureBuffer::new(cfg, cl),
             command: CommandBufferEmulated::new(cfg),
+            palette: PaletteBuffer::new(cfg),
             start: 0.
         }
     }
@@ -149,9 +133,11 @@ impl Drawlist for DrawlistNoSSBO {
         self.model.map();
         self.matrix.map();
         self.command.map();
+        self.palette.map();
     }
 
-    fn setup_compute(~self, db: &RenderData, tp: &mut TaskPool<Sender<Box<Drawlist+Send>>>, scene: ObjectKey) {
+    fn setup_compute(~self, db: &RenderData, tp: &mut TaskPool<Sender<Box<Drawlist+Send>>>,
+                     scene: ObjectKey, layers: Layers) {
         let DrawlistNoSSBO {
             data: _,
             size: size,
@@ -160,6 +146,7 @@ impl Drawlist for DrawlistNoSSBO {
             model: model,
             matrix: matrix,
             command: command,
+            palette: palette,
             start: _
         } = *self;
 
@@ -170,6 +157,8 @@ impl Drawlist for DrawlistNoSSBO {
         };
 
         let start = precise_time_s();
+        let mut matrix = matrix;
+        matrix.queue(&data, tp);
         let db0 = data.clone();
         let (sender, receiver0) = channel();
         tp.execute(proc(_) {
@@ -184,7 +173,7 @@ impl Drawlist for DrawlistNoSSBO {
         tp.execute(proc(_) {
             let db = db1;
             let mut model = model;
-            model.build(&db, scene);
+            model.build(&db, scene, layers);
             sender.send(model);
         });
 
@@ -211,22 +200,32 @@ impl Drawlist for DrawlistNoSSBO {
         tp.execute(proc(_) {
             let db = db4;
             let mut command = command;
-            command.build(&db);
+            command.build(&db, scene, layers);
             sender.send(command);
         });
 
+        let db5 = data.clone();
+        let (sender, receiver5) = channel();
+        tp.execute(proc(_) {
+            let db = db5;
+            let mut palette = palette;
+            palette.build(&db, scene, layers);
+            sender.send(palette);
+        });
+
         tp.execute(proc(ch) {
             ch.send(
                 match (receiver0.recv(), receiver1.recv(),
                        receiver2.recv(), receiver3.recv(),
-                       receiver4.recv()) {
-                    (matrix, model, lights, materials, command) => {
+                       receiver4.recv(), receiver5.recv()) {
+                    (matrix, model, lights, materials, command, palette) => {
                         box DrawlistNoSSBO {
                             matrix: matrix,
                             materials: materials,
                             lights: lights,
                             model: model,
                             command: command,
+                            palette: palette,
 
                             // other
                             size: size,
@@ -247,6 +246,7 @@ impl Drawlist for DrawlistNoSSBO {
         self.model.unmap();
         self.matrix.unmap();
         self.command.unmap();
+        self.palette.unmap();
     }
 
     fn cull(&mut self, _: &GlState, _: &Matrix4<f32>, _: &Matrix4<f32>) {}
@@ -285,6 +285,10 @@ impl Drawlist for DrawlistNoSSBO {
             gl::ActiveTexture(gl::TEXTURE4);
             gl::BindTexture(gl::TEXTURE_BUFFER, self.model.id());
             gl::Uniform1i(shader.uniform("info_buffer"), 4);
+
+            gl::ActiveTexture(gl::TEXTURE5);
+            gl::BindTexture(gl::TEXTURE_BUFFER, self.palette.id());
+            gl::Uniform1i(shader.uniform("palette"), 5);
         }
         
         let cmds = self.command.commands();
@@ -321,6 +325,7 @@ pub struct DrawlistSSBOCompute {
     model: ModelInfoSSBOBuffer,
     matrix: MatrixSSBOBuffer,
     command: CommandBufferIndirect,
+    palette: PaletteBuffer,
 
     size: uint,
     start: f64,
@@ -334,17 +339,14 @@ impl DrawlistSSBOCompute {
                        cl: Option<(Arc<Context>, Arc<CommandQueue>, Arc<Device>)>) -> DrawlistSSBOCompute {
 
         DrawlistSSBOCompute {
-            data: DrawlistGraphicsData {
-                common: CommonData::new(),
-                graphics: GraphicsData::new(),
-                position: PositionData::new()
-            },
+            data: DrawlistGraphicsData::empty(),
             size: cfg.max_size(),
             materials: MaterialBuffer::new(512),
             lights: LightsBuffer::new(),
             model: ModelInfoSSBOBuffer::new(cfg),
             matrix: MatrixSSBOBuffer::new(cfg, cl),
             command: CommandBufferIndirect::new(cfg),
+            palette: PaletteBuffer::new(cfg),
             start: 0.,
             culling_is_enabled: cfg.culling(),
             instanced_is_enabled: cfg.instanced()
@@ -359,9 +361,11 @@ impl Drawlist for DrawlistSSBOCompute {
         self.model.map();
         self.matrix.map();
         self.command.map();
+        self.palette.map();
     }
 
-    fn setup_compute(~self, db: &RenderData, tp: &mut TaskPool<Sender<Box<Drawlist+Send>>>, scene: ObjectKey) {
+    fn setup_compute(~self, db: &RenderData, tp: &mut TaskPool<Sender<Box<Drawlist+Send>>>,
+                     scene: ObjectKey, layers: Layers) {
         let DrawlistSSBOCompute {
             data: _,
             size: size,
@@ -370,6 +374,7 @@ impl Drawlist for DrawlistSSBOCompute {
             model: model,
             matrix: matrix,
             command: command,
+            palette: palette,
             culling_is_enabled: culling_is_enabled,
             instanced_is_enabled: instanced_is_enabled,
             start: _
@@ -382,6 +387,8 @@ impl Drawlist for DrawlistSSBOCompute {
         };
 
         let start = precise_time_s();
+        let mut matrix = matrix;
+        matrix.queue(&data, tp);
         let db0 = data.clone();
         let (sender, receiver0) = channel();
         tp.execute(proc(_) {
@@ -396,7 +403,7 @@ impl Drawlist for DrawlistSSBOCompute {
         tp.execute(proc(_) {
             let db = db1;
             let mut model = model;
-            model.build(&db, scene);
+            model.build(&db, scene, layers);
             sender.send(model);
         });
 
@@ -423,22 +430,32 @@ impl Drawlist for DrawlistSSBOCompute {
         tp.execute(proc(_) {
             let db = db4;
             let mut command = command;
-            command.build(&db, scene, instanced_is_enabled);
+            command.build(&db, scene, layers, instanced_is_enabled);
             sender.send(command);
         });
 
+        let db5 = data.clone();
+        let (sender, receiver5) = channel();
+        tp.execute(proc(_) {
+            let db = db5;
+            let mut palette = palette;
+            palette.build(&db, scene, layers);
+            sender.send(palette);
+        });
+
         tp.execute(proc(ch) {
             ch.send(
                 match (receiver0.recv(), receiver1.recv(),
                        receiver2.recv(), receiver3.recv(),
-                       receiver4.recv()) {
-                    (matrix, model, lights, materials, command) => {
+                       receiver4.recv(), receiver5.recv()) {
+                    (matrix, model, lights, materials, command, palette) => {
                         box DrawlistSSBOCompute {
                             matrix: matrix,
                             materials: materials,
                             lights: lights,
                             model: model,
                             command: command,
+                            palette: palette,
 
                             // other
                             size: size,
@@ -461,6 +478,7 @@ impl Drawlist for DrawlistSSBOCompute {
         self.model.unmap();
         self.matrix.unmap();
         self.command.unmap();
+        self.palette.unmap();
     }
 
     fn cull(&mut self, db: &GlState, view: &Matrix4<f32>, projection: &Matrix4<f32>) {
@@ -481,6 +499,10 @@ impl Drawlist for DrawlistSSBOCompute {
             gl::UniformMatrix4fv(shader.uniform("mat_proj"), 1, gl::FALSE, projection.ptr());
             gl::UniformMatrix4fv(shader.uniform("mat_view"), 1, gl::FALSE, view.ptr());    
         }
+
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::BindTexture(gl::TEXTURE_BUFFER, self.palette.id());
+        gl::Uniform1i(shader.uniform("palette"), 0);
         
         gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 4, self.model.id());
         gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 5, self.matrix.id());
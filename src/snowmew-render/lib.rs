@@ -3,11 +3,13 @@
 #![crate_type = "lib"]
 #![comment = "A game engine in rust"]
 #![allow(dead_code)]
+#![feature(phase)]
 
 //extern crate debug;
 extern crate std;
 extern crate glfw;
 extern crate cgmath;
+#[phase(plugin, link)]
 extern crate snowmew;
 extern crate cow;
 extern crate gl;
@@ -34,10 +36,10 @@ use time::precise_time_s;
 use OpenCL::hl::{CommandQueue, Context, Device};
 use sync::Arc;
 
-use snowmew::common::ObjectKey;
+use snowmew::common::{ObjectKey, Common};
 use snowmew::camera::Camera;
 use snowmew::io::Window;
-use position::Positions;
+use position::{Positions, PositionData};
 use graphics::Graphics;
 
 pub use config::Config;
@@ -60,6 +62,7 @@ mod light;
 mod model;
 mod matrix;
 mod command;
+mod skin;
 
 pub trait RenderData : Graphics + Positions {}
 
@@ -187,6 +190,8 @@ fn render_server(command: Receiver<RenderCommand>,
             return;
         }
     };
+    // true until the newest generation has been handed to a drawlist
+    let mut pending = true;
 
     let select = std::comm::Select::new();
     let mut receiver_drawlist_ready_handle = select.handle(&receiver_drawlist_ready);
@@ -214,6 +219,7 @@ fn render_server(command: Receiver<RenderCommand>,
                     scene = s;
                     camera = c;
                     db = rd;
+                    pending = true;
                 }
                 Finish => {
                     break 'finished;
@@ -221,10 +227,13 @@ fn render_server(command: Receiver<RenderCommand>,
             }
         }
 
-        if drawlists_ready.len() > 0 && scene != 0 {
+        // a scene that was unloaded, or was never loaded, draws nothing
+        if drawlists_ready.len() > 0 && pending {
             let dl = drawlists_ready.pop().unwrap();
-            dl.setup_compute(db, &mut taskpool, scene);
-            scene = 0;           
+            // the layers of the camera select what it can see
+            let layers = db.layers(camera);
+            dl.setup_compute(db, &mut taskpool, scene, layers);
+            pending = false;
         }
     }
 }
@@ -295,6 +304,13 @@ impl<RD: RenderData+Send> snowmew::Render<RD> for RenderManager {
     fn update(&mut self, db: RD, scene: ObjectKey, camera: ObjectKey) {
         self.ch.send(Update(box db, scene, camera));
     }
+
+    fn update_interpolated(&mut self, last: RD, db: RD, alpha: f32, scene: ObjectKey, camera: ObjectKey) {
+        let mut db = db;
+        let position = PositionData::interpolate(last.get_position(), db.get_position(), alpha);
+        *db.get_position_mut() = position;
+        self.ch.send(Update(box db, scene, camera));
+    }
 }
 
 impl<RD: RenderData+Send> snowmew::RenderFactory<RD, RenderManager> for RenderFactory {
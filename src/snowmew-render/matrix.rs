@@ -1,5 +1,6 @@
 use std::mem;
 use std::ptr;
+use std::sync::TaskPool;
 use std::slice::raw::mut_buf_as_slice;
 use sync::Arc;
 
@@ -13,12 +14,21 @@ use gl::types::{GLuint, GLsizeiptr};
 use gl_cl;
 use gl_cl::AcquireRelease;
 
-use position::{CalcPositionsCl, MatrixManager};
+use position::{CalcPositionsCl, MatrixManager, PositionJob};
 
 use position::Positions;
 
 use {Config, RenderData};
 
+// where the matrices of a buffer are worked out
+enum Compute<B> {
+    // by OpenCL, straight into the shared GL buffers
+    OpenCl(CalcPositionsCl, Arc<CommandQueue>, B),
+    // on the render pool, the job queued by `queue` is copied into the
+    // mapped buffers by `build`
+    Cpu(Option<PositionJob>)
+}
+
 struct GLTextureMatrix<'r> {
     x: &'r mut [Vector4<f32>],
     y: &'r mut [Vector4<f32>],
@@ -70,7 +80,7 @@ pub struct MatrixSSBOBuffer {
     size: uint,
 
     event: Option<Event>,
-    cl: Option<(CalcPositionsCl, Arc<CommandQueue>, [CLBuffer<Matrix4<f32>>, ..1])>,
+    compute: Compute<[CLBuffer<Matrix4<f32>>, ..1]>
 }
 
 impl MatrixSSBOBuffer {
@@ -87,28 +97,28 @@ impl MatrixSSBOBuffer {
                            ptr::null(), gl::DYNAMIC_DRAW);
         }
 
-        let clpos = match cl {
+        let compute = match cl {
             Some((ctx, cq, dev)) => {
                 let calc = CalcPositionsCl::new(ctx.deref(), dev.deref());
                 let buffers = gl_cl::create_from_gl_buffer(ctx.deref(), buffer[0], CL_MEM_READ_WRITE);
 
-                Some((calc, cq, [buffers]))
+                OpenCl(calc, cq, [buffers])
             },
-            None => None
+            None => Cpu(None)
         };
 
         MatrixSSBOBuffer {
             model_matrix: buffer[0],
             ptr_model_matrix: ptr::mut_null(),
             size: cfg.max_size(),
-            cl: clpos,
+            compute: compute,
             event: None,
         }
     }
 
     pub fn map(&mut self) {
-        match self.cl {
-            None => {
+        match self.compute {
+            Cpu(_) => {
                 gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, self.model_matrix);
                 self.ptr_model_matrix = gl::MapBufferRange(gl::SHADER_STORAGE_BUFFER, 0, 
                         (mem::size_of::<Matrix4<f32>>()*self.size) as GLsizeiptr,
@@ -116,7 +126,7 @@ impl MatrixSSBOBuffer {
                 ) as *mut Matrix4<f32>;
                 assert!(0 == gl::GetError());           
             }
-            Some((_, ref cq, ref buf)) => {
+            OpenCl(_, ref cq, ref buf) => {
                 cq.acquire_gl_objects(buf.as_slice(), ()).wait()
             }
         }
@@ -124,33 +134,47 @@ impl MatrixSSBOBuffer {
 
     pub fn unmap(&mut self) {
         let event = self.event.take();
-        match (&self.cl, event) {
-            (&None, None) => {
+        match (&self.compute, event) {
+            (&Cpu(_), None) => {
                 gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, self.model_matrix);
                 gl::UnmapBuffer(gl::SHADER_STORAGE_BUFFER);
                 assert!(0 == gl::GetError());
                 self.ptr_model_matrix = ptr::mut_null();
             }
-            (&Some((_, ref cq, ref buf)), Some(ref event)) => {
+            (&OpenCl(_, ref cq, ref buf), Some(ref event)) => {
                 cq.release_gl_objects(buf.as_slice(), event).wait();
             }
             _ => fail!("expected both an event and a queue")
         }
     }
 
+    /// Queue the matrices of `db` on the render pool, `build` copies them
+    /// into the mapped buffer once they are done. OpenCL needs no queueing.
+    pub fn queue<RD: RenderData, T>(&mut self, db: &RD, tp: &mut TaskPool<T>) {
+        match self.compute {
+            Cpu(ref mut job) => *job = Some(db.queue_positions(tp)),
+            OpenCl(..) => ()
+        }
+    }
+
     pub fn build<RD: RenderData>(&mut self, db: &RD) {
         self.event = unsafe {
-            match self.cl {
-                None => {
-                    mut_buf_as_slice(self.ptr_model_matrix, self.size, |mat| {
-                        let mut mat = GLSSBOMatrix {
-                            mat: mat
-                        };
-                        db.write_positions(&mut mat);
-                        None
-                    })               
+            match self.compute {
+                Cpu(ref mut job) => {
+                    match job.take() {
+                        Some(job) => {
+                            mut_buf_as_slice(self.ptr_model_matrix, self.size, |mat| {
+                                let mut mat = GLSSBOMatrix {
+                                    mat: mat
+                                };
+                                job.write(&mut mat);
+                            })
+                        }
+                        None => ()
+                    }
+                    None
                 }
-                Some((ref mut ctx, ref cq, ref buf)) => {
+                OpenCl(ref mut ctx, ref cq, ref buf) => {
                     let evt = db.write_positions_cl_mat4(cq.deref(), ctx, buf.as_slice());
                     Some(evt)
                 }
@@ -168,7 +192,7 @@ pub struct MatrixTextureBuffer {
     size: uint,
 
     event: Option<Event>,
-    cl: Option<(CalcPositionsCl, Arc<CommandQueue>, [CLBuffer<Vector4<f32>>, ..4])>,
+    compute: Compute<[CLBuffer<Vector4<f32>>, ..4]>
 }
 
 impl MatrixTextureBuffer {
@@ -191,7 +215,7 @@ impl MatrixTextureBuffer {
             }
         }
 
-        let clpos = match cl {
+        let compute = match cl {
             Some((ctx, cq, dev)) => {
                 let calc = CalcPositionsCl::new(ctx.deref(), dev.deref());
                 let buffers = [gl_cl::create_from_gl_buffer(ctx.deref(), buffer[0], CL_MEM_READ_WRITE),
@@ -199,9 +223,9 @@ impl MatrixTextureBuffer {
                                gl_cl::create_from_gl_buffer(ctx.deref(), buffer[2], CL_MEM_READ_WRITE),
                                gl_cl::create_from_gl_buffer(ctx.deref(), buffer[3], CL_MEM_READ_WRITE)];
 
-                Some((calc, cq, buffers))
+                OpenCl(calc, cq, buffers)
             },
-            None => None
+            None => Cpu(None)
         };
 
         MatrixTextureBuffer {
@@ -209,14 +233,14 @@ impl MatrixTextureBuffer {
             texture_model_matrix: [texture[0], texture[1], texture[2], texture[3]],
             ptr_model_matrix: [ptr::mut_null(), ptr::mut_null(), ptr::mut_null(), ptr::mut_null()],
             size: cfg.max_size(),
-            cl: clpos,
+            compute: compute,
             event: None,
         }
     }
 
     pub fn map(&mut self) {
-        match self.cl {
-            None => {
+        match self.compute {
+            Cpu(_) => {
                 for i in range(0u, 4) {
                     gl::BindBuffer(gl::TEXTURE_BUFFER, self.model_matrix[i]);
                     self.ptr_model_matrix[i] = gl::MapBufferRange(
@@ -227,7 +251,7 @@ impl MatrixTextureBuffer {
                 }
                 assert!(0 == gl::GetError());
             }
-            Some((_, ref cq, ref buf)) => {
+            OpenCl(_, ref cq, ref buf) => {
                 cq.acquire_gl_objects(buf.as_slice(), ()).wait()
             }
         }
@@ -235,8 +259,8 @@ impl MatrixTextureBuffer {
 
     pub fn unmap(&mut self) {
         let event = self.event.take();
-        match (&self.cl, event) {
-            (&None, None) => {
+        match (&self.compute, event) {
+            (&Cpu(_), None) => {
                 for i in range(0u, 4) {
                     gl::BindBuffer(gl::TEXTURE_BUFFER, self.model_matrix[i]);
                     gl::UnmapBuffer(gl::TEXTURE_BUFFER);
@@ -244,29 +268,43 @@ impl MatrixTextureBuffer {
                     self.ptr_model_matrix[i] = ptr::mut_null();
                 }
             }
-            (&Some((_, ref cq, ref buf)), Some(ref event)) => {
+            (&OpenCl(_, ref cq, ref buf), Some(ref event)) => {
                 cq.release_gl_objects(buf.as_slice(), event).wait();
             }
             _ => fail!("expected both an event and a queue")
         }
     }
 
+    /// Queue the matrices of `db` on the render pool, `build` copies them
+    /// into the mapped buffer once they are done. OpenCL needs no queueing.
+    pub fn queue<RD: RenderData, T>(&mut self, db: &RD, tp: &mut TaskPool<T>) {
+        match self.compute {
+            Cpu(ref mut job) => *job = Some(db.queue_positions(tp)),
+            OpenCl(..) => ()
+        }
+    }
+
     pub fn build<RD: RenderData>(&mut self, db: &RD) {
         self.event = unsafe {
-            match self.cl {
-                None => {
-                    mut_buf_as_slice(self.ptr_model_matrix[0], self.size, |x| {
-                    mut_buf_as_slice(self.ptr_model_matrix[1], self.size, |y| {
-                    mut_buf_as_slice(self.ptr_model_matrix[2], self.size, |z| {
-                    mut_buf_as_slice(self.ptr_model_matrix[3], self.size, |w| {
-                        let mut mat = GLTextureMatrix {
-                            x: x, y: y, z: z, w: w
-                        };
-                        db.write_positions(&mut mat);
-                        None
-                    })})})})
+            match self.compute {
+                Cpu(ref mut job) => {
+                    match job.take() {
+                        Some(job) => {
+                            mut_buf_as_slice(self.ptr_model_matrix[0], self.size, |x| {
+                            mut_buf_as_slice(self.ptr_model_matrix[1], self.size, |y| {
+                            mut_buf_as_slice(self.ptr_model_matrix[2], self.size, |z| {
+                            mut_buf_as_slice(self.ptr_model_matrix[3], self.size, |w| {
+                                let mut mat = GLTextureMatrix {
+                                    x: x, y: y, z: z, w: w
+                                };
+                                job.write(&mut mat);
+                            })})})})
+                        }
+                        None => ()
+                    }
+                    None
                 }
-                Some((ref mut ctx, ref cq, ref buf)) => {
+                OpenCl(ref mut ctx, ref cq, ref buf) => {
                     let evt = db.write_positions_cl_vec4x4(cq.deref(), ctx, buf);
                     Some(evt)
                 }
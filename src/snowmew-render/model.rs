@@ -3,7 +3,7 @@ use std::ptr;
 use std::mem;
 use std::slice::raw::mut_buf_as_slice;
 
-use cow::join::{join_set_to_map, join_maps};
+use snowmew::query::query;
 
 use gl;
 use gl::types::{GLsizeiptr, GLuint};
@@ -12,14 +12,16 @@ use collision::sphere::Sphere;
 
 use Config;
 use RenderData;
+use skin::palette_slot;
 
 use snowmew::ObjectKey;
+use snowmew::common::{Common, Layers};
 
 struct ModelInfoSSBO {
     id: u32,
     matrix: u32,
     material: u32,
-    _padd: u32,
+    palette: u32,
     sphere: Sphere<f32>
 }
 
@@ -66,18 +68,21 @@ impl ModelInfoSSBOBuffer {
         self.ptr_model_info = ptr::mut_null();
     }
 
-    pub fn build(&mut self, db: &RenderData, scene: ObjectKey) {
+    pub fn build(&mut self, db: &RenderData, scene: ObjectKey, layers: Layers) {
         let position = db.compute_positions();
+        let size = self.size;
+        let mut next_palette = 0;
         unsafe {
-            mut_buf_as_slice(self.ptr_model_info, self.size, |info| {
-                for (idx, (id, (draw, pos))) in join_set_to_map(db.scene_iter(scene),
-                                                join_maps(db.drawable_iter(), db.location_iter())).enumerate() {
+            mut_buf_as_slice(self.ptr_model_info, size, |info| {
+                for (idx, (id, (draw, pos))) in query(db.drawable_iter()).with(db.location_iter())
+                                                                   .in_scene(db.get_common(), scene)
+                                                                   .in_layers(db.get_common(), layers).enumerate() {
                     info[idx] = ModelInfoSSBO {
                         id: id.clone(),
                         matrix: position.get_loc(*pos) as u32,
                         material: db.material_index(draw.material).unwrap() as u32,
                         sphere: db.sphere(draw.geometry),
-                        _padd: 0
+                        palette: palette_slot(db, *id, &mut next_palette, size)
                     };
                 }
             });
@@ -90,7 +95,8 @@ impl ModelInfoSSBOBuffer {
 struct ModelInfoTexture {
     id: u32,
     matrix: u32,
-    material: u32
+    material: u32,
+    palette: u32
 }
 
 pub struct ModelInfoTextureBuffer {
@@ -112,7 +118,7 @@ impl ModelInfoTextureBuffer {
 
         gl::BindBuffer(gl::TEXTURE_BUFFER, buffer[0]);
         gl::BindTexture(gl::TEXTURE_BUFFER, texture[0]);
-        gl::TexBuffer(gl::TEXTURE_BUFFER, gl::RGB32UI, buffer[0]);
+        gl::TexBuffer(gl::TEXTURE_BUFFER, gl::RGBA32UI, buffer[0]);
         unsafe {
             gl::BufferData(gl::TEXTURE_BUFFER,
                            (mem::size_of::<ModelInfoTexture>()*cfg.max_size()) as GLsizeiptr,
@@ -142,16 +148,20 @@ impl ModelInfoTextureBuffer {
         self.ptr_model_info = ptr::mut_null();
     }
 
-    pub fn build(&mut self, db: &RenderData, scene: ObjectKey) {
+    pub fn build(&mut self, db: &RenderData, scene: ObjectKey, layers: Layers) {
         let position = db.compute_positions();
+        let size = self.size;
+        let mut next_palette = 0;
         unsafe {
-            mut_buf_as_slice(self.ptr_model_info, self.size, |info| {
-                for (idx, (id, (draw, pos))) in join_set_to_map(db.scene_iter(scene),
-                                                join_maps(db.drawable_iter(), db.location_iter())).enumerate() {
+            mut_buf_as_slice(self.ptr_model_info, size, |info| {
+                for (idx, (id, (draw, pos))) in query(db.drawable_iter()).with(db.location_iter())
+                                                                   .in_scene(db.get_common(), scene)
+                                                                   .in_layers(db.get_common(), layers).enumerate() {
                     info[idx] = ModelInfoTexture {
                         id: id.clone(),
                         matrix: position.get_loc(*pos) as u32,
-                        material: db.material_index(draw.material).unwrap() as u32
+                        material: db.material_index(draw.material).unwrap() as u32,
+                        palette: palette_slot(db, *id, &mut next_palette, size)
                     };
                 }
             });
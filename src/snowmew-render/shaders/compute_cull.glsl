@@ -1,10 +1,12 @@
 #version 440
 
+#define NO_PALETTE -1
+
 struct DrawInfoStruct {
     int id;
     int matrix;
     int material;
-    int _padd;
+    int palette;
     vec4 sphere;
 };
 
@@ -44,13 +46,17 @@ void main() {
         }
         DrawInfoStruct info_id = info[id];
 
-        mat4 mat = model_matrix[info_id.matrix];
-        vec4 sphere_center = mat * vec4(info_id.sphere.xyz, 1.);
-        float sphere_radius = length(vec4(1/sqrt(3), 1/sqrt(3), 1/sqrt(3), 0.) * mat) * info_id.sphere.w;
+        // a skinned mesh is placed by its joints rather than by its own
+        // matrix, its sphere says nothing about where it is so it is always drawn
+        if (info_id.palette == NO_PALETTE) {
+            mat4 mat = model_matrix[info_id.matrix];
+            vec4 sphere_center = mat * vec4(info_id.sphere.xyz, 1.);
+            float sphere_radius = length(vec4(1/sqrt(3), 1/sqrt(3), 1/sqrt(3), 0.) * mat) * info_id.sphere.w;
 
-        for (int i=0; i<6; i++) {
-            if (dot(plane[i], sphere_center) + sphere_radius < 0.) {
-                accept = false;
+            for (int i=0; i<6; i++) {
+                if (dot(plane[i], sphere_center) + sphere_radius < 0.) {
+                    accept = false;
+                }
             }
         }
 
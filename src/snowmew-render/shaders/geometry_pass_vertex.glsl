@@ -3,17 +3,20 @@
     #extension GL_ARB_shader_draw_parameters: require
 #endif
 
+#define NO_PALETTE 0xffffffffu
+
 struct DrawInfoCore {
     uint id;
     uint matrix;
     uint material;
+    uint palette;
 };
 
 struct DrawInfoStruct {
     uint id;
     uint matrix;
     uint material;
-    uint _padd;
+    uint palette;
     vec4 sphere;
 };
 
@@ -34,7 +37,8 @@ struct DrawInfoStruct {
         DrawInfoStruct f_info = info[idx];
         return DrawInfoCore(f_info.id,
                             f_info.matrix,
-                            f_info.material);
+                            f_info.material,
+                            f_info.palette);
     }
 
     int get_index() {
@@ -57,10 +61,11 @@ struct DrawInfoStruct {
     }
 
     DrawInfoCore get_info(int idx) {
-        uvec3 f_info = texelFetch(info_buffer, idx).xyz;
+        uvec4 f_info = texelFetch(info_buffer, idx);
         return DrawInfoCore(f_info.x,
                             f_info.y,
-                            f_info.z);
+                            f_info.z,
+                            f_info.w);
     }
 
     int get_index() {
@@ -68,12 +73,25 @@ struct DrawInfoStruct {
     }
 #endif
 
+// skinning matrices, four texels to a matrix
+uniform samplerBuffer palette;
+
+mat4 get_palette(uint idx) {
+    int i = int(idx) * 4;
+    return mat4(texelFetch(palette, i),
+                texelFetch(palette, i+1),
+                texelFetch(palette, i+2),
+                texelFetch(palette, i+3));
+}
+
 uniform mat4 mat_view;
 uniform mat4 mat_proj;
 
 in vec3 in_position;
 in vec2 in_texture;
 in vec3 in_normal;
+in uvec4 in_joints;
+in vec4 in_weights;
 
 out vec2 fs_texture;
 out vec3 fs_normal;
@@ -83,7 +101,16 @@ flat out uint fs_material_id;
 void main() {
     int idx = get_index();
     DrawInfoCore info = get_info(idx);
-    mat4 mat_model = get_mat(int(info.matrix));
+    mat4 mat_model;
+    if (info.palette == NO_PALETTE) {
+        mat_model = get_mat(int(info.matrix));
+    } else {
+        // a skinned mesh is placed by its joints alone
+        mat_model = in_weights.x * get_palette(info.palette + in_joints.x) +
+                    in_weights.y * get_palette(info.palette + in_joints.y) +
+                    in_weights.z * get_palette(info.palette + in_joints.z) +
+                    in_weights.w * get_palette(info.palette + in_joints.w);
+    }
 
     vec4 normal = mat_model * vec4(in_normal, 0.);
     gl_Position = mat_proj * mat_view * mat_model * vec4(in_position, 1.);
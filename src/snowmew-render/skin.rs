@@ -0,0 +1,109 @@
+use std::ptr;
+use std::mem;
+use std::slice::raw::mut_buf_as_slice;
+
+use snowmew::query::query;
+
+use gl;
+use gl::types::{GLsizeiptr, GLuint};
+
+use cgmath::matrix::Matrix4;
+
+use Config;
+use RenderData;
+
+use snowmew::ObjectKey;
+use snowmew::common::{Common, Layers};
+
+/// The palette offset of a drawable that is not skinned.
+pub static NO_PALETTE: u32 = 0xffffffff;
+
+/// Reserve room for the palette of `key` starting at `next`. The model info
+/// and the palette buffer walk the drawables in the same order, so they
+/// agree on the offsets. Skins that do not fit are drawn unskinned.
+pub fn palette_slot(db: &RenderData, key: ObjectKey, next: &mut uint, size: uint) -> u32 {
+    match db.skin(key) {
+        Some(skin) if *next + skin.len() <= size => {
+            let offset = *next;
+            *next += skin.len();
+            offset as u32
+        }
+        _ => NO_PALETTE
+    }
+}
+
+/// The skinning matrices of every skinned drawable, stored as a texture
+/// buffer with one matrix every four texels.
+pub struct PaletteBuffer {
+    ptr_palette: *mut Matrix4<f32>,
+    palette: GLuint,
+    texture_palette: GLuint,
+    size: uint
+}
+
+impl PaletteBuffer {
+    pub fn new(cfg: &Config) -> PaletteBuffer {
+        let buffer = &mut [0];
+        let texture = &mut [0];
+
+        unsafe {
+            gl::GenBuffers(buffer.len() as i32, buffer.unsafe_mut_ref(0));
+            gl::GenTextures(texture.len() as i32, texture.unsafe_mut_ref(0));
+        }
+
+        gl::BindBuffer(gl::TEXTURE_BUFFER, buffer[0]);
+        gl::BindTexture(gl::TEXTURE_BUFFER, texture[0]);
+        gl::TexBuffer(gl::TEXTURE_BUFFER, gl::RGBA32F, buffer[0]);
+        unsafe {
+            gl::BufferData(gl::TEXTURE_BUFFER,
+                           (mem::size_of::<Matrix4<f32>>()*cfg.max_size()) as GLsizeiptr,
+                           ptr::null(), gl::DYNAMIC_DRAW);
+        }
+        assert!(0 == gl::GetError());
+
+        PaletteBuffer {
+            ptr_palette: ptr::mut_null(),
+            palette: buffer[0],
+            texture_palette: texture[0],
+            size: cfg.max_size()
+        }
+    }
+
+    pub fn map(&mut self) {
+        gl::BindBuffer(gl::TEXTURE_BUFFER, self.palette);
+        self.ptr_palette = gl::MapBufferRange(gl::TEXTURE_BUFFER, 0,
+                (mem::size_of::<Matrix4<f32>>()*self.size) as GLsizeiptr,
+                gl::MAP_WRITE_BIT | gl::MAP_INVALIDATE_BUFFER_BIT
+        ) as *mut Matrix4<f32>;
+    }
+
+    pub fn unmap(&mut self) {
+        gl::BindBuffer(gl::TEXTURE_BUFFER, self.palette);
+        gl::UnmapBuffer(gl::TEXTURE_BUFFER);
+        self.ptr_palette = ptr::mut_null();
+    }
+
+    pub fn build(&mut self, db: &RenderData, scene: ObjectKey, layers: Layers) {
+        let size = self.size;
+        let mut next = 0;
+        unsafe {
+            mut_buf_as_slice(self.ptr_palette, size, |out| {
+                for (id, _) in query(db.drawable_iter()).with(db.location_iter())
+                                                        .in_scene(db.get_common(), scene)
+                                                        .in_layers(db.get_common(), layers) {
+                    let offset = palette_slot(db, *id, &mut next, size);
+                    if offset == NO_PALETTE {
+                        continue;
+                    }
+                    let skin = db.skin(*id).unwrap();
+                    let palette = db.palette(skin.joints.as_slice(), skin.inverse_bind.as_slice());
+                    for (i, mat) in palette.iter().enumerate() {
+                        out[offset as uint + i] = *mat;
+                    }
+                }
+            });
+        }
+    }
+
+    pub fn id(&self) -> GLuint {self.texture_palette}
+}
@@ -7,7 +7,8 @@ use std::ptr;
 use libc::c_void;
 
 use graphics::geometry::{Vertex, VertexGeo, VertexGeoTex, VertexGeoNorm, VertexGeoTexNorm, VertexGeoTexNormTan};
-use graphics::geometry::{Geo, GeoTex, GeoNorm, GeoTexNorm, GeoTexNormTan};
+use graphics::geometry::VertexGeoTexNormSkin;
+use graphics::geometry::{Geo, GeoTex, GeoNorm, GeoTexNorm, GeoTexNormTan, GeoTexNormSkin};
 
 #[deriving(Clone, Default)]
 pub struct VertexBuffer {
@@ -50,6 +51,11 @@ impl VertexBuffer {
                     (mem::transmute(data.get(0)),
                      data.len() * mem::size_of::<VertexGeoTexNormTan>(),
                      mem::size_of::<VertexGeoTexNormTan>())
+                },
+                GeoTexNormSkin(ref data) => {
+                    (mem::transmute(data.get(0)),
+                     data.len() * mem::size_of::<VertexGeoTexNormSkin>(),
+                     mem::size_of::<VertexGeoTexNormSkin>())
                 }
             };
             let stride = stride as i32;
@@ -72,7 +78,7 @@ impl VertexBuffer {
 
             let offset = match *vertex {
                 Geo(_) | GeoNorm(_) => offset,
-                GeoTex(_) | GeoTexNorm(_) | GeoTexNormTan(_) => {
+                GeoTex(_) | GeoTexNorm(_) | GeoTexNormTan(_) | GeoTexNormSkin(_) => {
                     gl::EnableVertexAttribArray(1);
                     gl::VertexAttribPointer(1, 2, gl::FLOAT, gl::FALSE, stride, offset as *c_void);
                     offset + 8
@@ -81,7 +87,7 @@ impl VertexBuffer {
 
             let offset = match *vertex {
                 Geo(_) | GeoTex(_) => offset,
-                GeoNorm(_) | GeoTexNorm(_) | GeoTexNormTan(_) => {
+                GeoNorm(_) | GeoTexNorm(_) | GeoTexNormTan(_) | GeoTexNormSkin(_) => {
                     gl::EnableVertexAttribArray(2);
                     gl::VertexAttribPointer(2, 3, gl::FLOAT, gl::FALSE, stride, offset as *c_void);
                     offset + 12
@@ -95,6 +101,13 @@ impl VertexBuffer {
                     gl::EnableVertexAttribArray(3);
                     gl::VertexAttribPointer(2, 3, gl::FLOAT, gl::FALSE, stride, offset as *c_void);
                 }
+                GeoTexNormSkin(_) => {
+                    // joint indices stay integers in the shader
+                    gl::EnableVertexAttribArray(4);
+                    gl::VertexAttribIPointer(4, 4, gl::UNSIGNED_INT, stride, offset as *c_void);
+                    gl::EnableVertexAttribArray(5);
+                    gl::VertexAttribPointer(5, 4, gl::FLOAT, gl::FALSE, stride, (offset + 16) as *c_void);
+                }
             };
 
             gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, vbo[1]);
@@ -1,14 +1,87 @@
+use std::default::Default;
+use std::io::IoResult;
+use std::iter::Peekable;
+use std::vec::MoveItems;
+
 use cow::btree::{BTreeMap, BTreeMapIterator, BTreeSet, BTreeSetIterator};
 
+use snapshot;
+use snapshot::Snapshot;
+use diff::{Diff, diff_maps};
+use event;
+use event::{Event, EventLog, EventSeq, Events};
+use event::{ObjectCreated, ObjectRemoved, ObjectMoved, ObjectRenamed, LayersChanged};
+
+fn is_pattern(s: &str) -> bool {
+    s.contains_char('*') || s.contains_char('?')
+}
+
+/// Match `name` against a pattern where `*` is any run of characters and
+/// `?` is any single character.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let n: Vec<char> = name.chars().collect();
+    let (p, n) = (p.as_slice(), n.as_slice());
+
+    let (mut pi, mut ni) = (0u, 0u);
+    // the last `*` seen and the position in `name` it is matched up to
+    let mut star: Option<(uint, uint)> = None;
+
+    while ni < n.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == n[ni]) {
+            pi += 1;
+            ni += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ni));
+            pi += 1;
+        } else {
+            match star {
+                Some((sp, sn)) => {
+                    pi = sp + 1;
+                    ni = sn + 1;
+                    star = Some((sp, sn + 1));
+                }
+                None => return false
+            }
+        }
+    }
+
+    while pi < p.len() && p[pi] == '*' {
+        pi += 1;
+    }
+    pi == p.len()
+}
+
 #[deriving(Clone, Default)]
 pub struct FrameInfo {
-    count: uint,  /* unique frame identifier */
-    time: f64,    /* current time in seconds */
-    delta: f64,   /* time from last frame */
+    pub count: uint,  /* unique frame identifier */
+    pub time: f64,    /* current time in seconds */
+    pub delta: f64,   /* time from last frame */
 }
 
+impl FrameInfo {
+    /// The first frame of a simulation that steps `delta` seconds at a time.
+    pub fn new(delta: f64) -> FrameInfo {
+        FrameInfo {
+            count: 0,
+            time: 0.,
+            delta: delta
+        }
+    }
 
-#[deriving(Clone, Default)]
+    /// The frame that follows this one. The time is derived from the count
+    /// so it does not drift from adding up `delta`.
+    pub fn next(&self) -> FrameInfo {
+        FrameInfo {
+            count: self.count + 1,
+            time: (self.count + 1) as f64 * self.delta,
+            delta: self.delta
+        }
+    }
+}
+
+
+#[deriving(Clone, Default, PartialEq)]
 pub struct Object {
     pub parent: ObjectKey,
     pub name: ObjectKey,
@@ -17,17 +90,76 @@ pub struct Object {
 pub type ObjectKey = u32;
 pub type StringKey = u32;
 
+/// A bitmask of the layers an object is on. The renderer and the physics
+/// step only look at objects that share a bit with their mask.
+pub type Layers = u32;
+
+/// The layer every object is on until it is given other layers.
+pub static DEFAULT_LAYER: Layers = 1;
+pub static ALL_LAYERS: Layers = 0xffffffff;
+
+/// The keys of a prefab and the keys of an instance of it, made by
+/// `Common::instantiate`. Each data block uses it to copy its rows.
+#[deriving(Clone)]
+pub struct Remap {
+    root: ObjectKey,
+    map: BTreeMap<ObjectKey, ObjectKey>
+}
+
+impl Remap {
+    /// The root of the instance.
+    pub fn root(&self) -> ObjectKey { self.root }
+
+    /// The copy of `key`, if `key` is part of the prefab.
+    pub fn get(&self, key: ObjectKey) -> Option<ObjectKey> {
+        self.map.find(&key).map(|k| *k)
+    }
+
+    /// The copy of `key` if it is part of the prefab, otherwise `key`.
+    /// References to objects outside of the prefab are shared by every instance.
+    pub fn remap(&self, key: ObjectKey) -> ObjectKey {
+        match self.get(key) {
+            Some(k) => k,
+            None => key
+        }
+    }
+
+    pub fn len(&self) -> uint { self.map.len() }
+
+    /// Pairs of prefab and instance keys, sorted by the prefab key.
+    pub fn iter<'a>(&'a self) -> BTreeMapIterator<'a, ObjectKey, ObjectKey> {
+        self.map.iter()
+    }
+}
+
 #[deriving(Clone)]
 pub struct CommonData {
     last_sid:       StringKey,
     strings:        BTreeMap<StringKey, String>,
     string_to_key:  BTreeMap<String, StringKey>,
+    // number of objects using each string, a string is dropped with its last user
+    string_refs:    BTreeMap<StringKey, u32>,
+    string_bytes:   uint,
 
     last_oid:       ObjectKey,
     objects:        BTreeMap<ObjectKey, Object>,
     parent_child:   BTreeMap<ObjectKey, BTreeMap<StringKey, ObjectKey>>,
 
-    scene_children: BTreeMap<ObjectKey, BTreeSet<ObjectKey>>
+    scene_children: BTreeMap<ObjectKey, BTreeSet<ObjectKey>>,
+    // scenes whose members are also members of the key scene
+    scene_includes: BTreeMap<ObjectKey, BTreeSet<ObjectKey>>,
+    active_scene:   Option<ObjectKey>,
+
+    // only objects that are not on `DEFAULT_LAYER` alone are stored
+    layers:         BTreeMap<ObjectKey, Layers>,
+
+    // number of times `set_parent` moved an object
+    moves:          u64,
+    events:         EventLog
+}
+
+impl Default for CommonData {
+    fn default() -> CommonData { CommonData::new() }
 }
 
 impl CommonData {
@@ -36,15 +168,30 @@ impl CommonData {
             last_sid:           1,
             strings:            BTreeMap::new(),
             string_to_key:      BTreeMap::new(),
+            string_refs:        BTreeMap::new(),
+            string_bytes:       0,
 
             last_oid:           1,
             objects:            BTreeMap::new(),
             parent_child:       BTreeMap::new(),
 
-            scene_children:     BTreeMap::new()
+            scene_children:     BTreeMap::new(),
+            scene_includes:     BTreeMap::new(),
+            active_scene:       None,
+
+            layers:             BTreeMap::new(),
+
+            moves:              0,
+            events:             EventLog::new(event::DEFAULT_LIMIT)
         }   
     }
 
+    /// Objects that were created, deleted, renamed or moved between
+    /// two generations.
+    pub fn diff(old: &CommonData, new: &CommonData) -> Diff {
+        diff_maps(old.objects.iter(), new.objects.iter(), |a, b| a == b)
+    }
+
     fn ifind(&self, node: Option<ObjectKey>, str_key: &str) -> Option<ObjectKey> {
         let node = match node {
             Some(key) => key,
@@ -67,15 +214,91 @@ impl CommonData {
         }
     }
 
-    fn name(&self, key: ObjectKey) -> String {
+    fn path(&self, key: ObjectKey) -> Option<String> {
+        let node = match self.objects.find(&key) {
+            Some(node) => node,
+            None => return None
+        };
+        let name = self.strings.find(&node.name).unwrap();
+
+        if node.parent == 0 {
+            Some(name.clone())
+        } else {
+            self.path(node.parent).map(|p| format!("{:s}/{:s}", p, *name))
+        }
+    }
+
+    fn parent_of(&self, key: ObjectKey) -> ObjectKey {
         match self.objects.find(&key) {
-            Some(node) => {
-                format!("{:s}/{:s}", self.name(node.parent), *self.strings.find(&node.name).unwrap())
-            },
-            None => "base".to_string()
+            Some(obj) => obj.parent,
+            None => 0
         }
     }
 
+    // push `key` and everything below it
+    fn descendants(&self, key: ObjectKey, out: &mut Vec<ObjectKey>) {
+        out.push(key);
+        match self.parent_child.find(&key) {
+            Some(children) => {
+                for (_, child) in children.iter() {
+                    self.descendants(*child, out);
+                }
+            }
+            None => ()
+        }
+    }
+
+    // resolve a path made of names, `.`, `..` and glob patterns starting
+    // from `base`. A leading `/` starts from the root.
+    fn resolve(&self, base: ObjectKey, path: &str) -> Vec<ObjectKey> {
+        let mut nodes = vec!(if path.starts_with("/") { 0 } else { base });
+
+        for seg in path.split('/') {
+            let mut next = Vec::new();
+            match seg {
+                "" | "." => continue,
+                ".." => {
+                    for n in nodes.iter() {
+                        next.push(self.parent_of(*n));
+                    }
+                }
+                "**" => {
+                    for n in nodes.iter() {
+                        self.descendants(*n, &mut next);
+                    }
+                }
+                _ if !is_pattern(seg) => {
+                    for n in nodes.iter() {
+                        match self.ifind(Some(*n), seg) {
+                            Some(child) => next.push(child),
+                            None => ()
+                        }
+                    }
+                }
+                _ => {
+                    for n in nodes.iter() {
+                        match self.parent_child.find(n) {
+                            Some(children) => {
+                                for (sid, child) in children.iter() {
+                                    if glob_match(seg, self.strings.find(sid).unwrap().as_slice()) {
+                                        next.push(*child);
+                                    }
+                                }
+                            }
+                            None => ()
+                        }
+                    }
+                }
+            }
+            next.sort();
+            next.dedup();
+            nodes = next;
+        }
+
+        nodes.retain(|k| *k != 0);
+        nodes
+    }
+
 
     fn new_key(&mut self) -> ObjectKey {
         let new_key = self.last_oid;
@@ -102,6 +325,131 @@ impl CommonData {
         }
     }
 
+    fn scene_of(&self, key: ObjectKey) -> Option<ObjectKey> {
+        let mut parent = match self.objects.find(&key) {
+            Some(obj) => obj.parent,
+            None => return None
+        };
+
+        while parent != 0 {
+            match self.scene_children.find(&parent) {
+                Some(_) => return Some(parent),
+                None => {
+                    parent = self.objects.find(&parent).unwrap().parent;
+                }
+            }
+        }
+        None
+    }
+
+    fn subtree(&self, key: ObjectKey, out: &mut Vec<ObjectKey>) {
+        match self.parent_child.find(&key) {
+            Some(children) => {
+                for (_, child) in children.iter() {
+                    self.subtree(*child, out);
+                }
+            }
+            None => ()
+        }
+        out.push(key);
+    }
+
+    fn scene_members(&self, key: ObjectKey, out: &mut Vec<ObjectKey>) {
+        out.push(key);
+        if self.scene_children.find(&key).is_some() {
+            return;
+        }
+
+        match self.parent_child.find(&key) {
+            Some(children) => {
+                for (_, child) in children.iter() {
+                    self.scene_members(*child, out);
+                }
+            }
+            None => ()
+        }
+    }
+
+    /// True if `ancestor` is `key` or one of its parents.
+    pub fn is_ancestor(&self, ancestor: ObjectKey, key: ObjectKey) -> bool {
+        let mut key = key;
+        while key != 0 {
+            if key == ancestor {
+                return true;
+            }
+            key = match self.objects.find(&key) {
+                Some(obj) => obj.parent,
+                None => 0
+            };
+        }
+        false
+    }
+
+    // `scene` and every scene it includes, directly or through another scene
+    fn included_scenes(&self, scene: ObjectKey, out: &mut Vec<ObjectKey>) {
+        if out.contains(&scene) {
+            return;
+        }
+        out.push(scene);
+        match self.scene_includes.find(&scene) {
+            Some(includes) => {
+                for s in includes.iter() {
+                    self.included_scenes(*s, out);
+                }
+            }
+            None => ()
+        }
+    }
+
+    fn remove(&mut self, key: ObjectKey) {
+        let obj = match self.objects.find(&key) {
+            Some(obj) => *obj,
+            None => return
+        };
+
+        match self.scene_of(key) {
+            Some(scene) => {
+                self.scene_children.find_mut(&scene).unwrap().remove(&key);
+            }
+            None => ()
+        }
+
+        let empty = match self.parent_child.find_mut(&obj.parent) {
+            Some(child_list) => {
+                if child_list.find(&obj.name) == Some(&key) {
+                    child_list.remove(&obj.name);
+                }
+                child_list.len() == 0
+            }
+            None => false
+        };
+
+        if empty {
+            self.parent_child.remove(&obj.parent);
+        }
+
+        self.parent_child.remove(&key);
+        if self.scene_children.remove(&key) {
+            self.scene_includes.remove(&key);
+            let including: Vec<ObjectKey> = self.scene_includes.iter()
+                .filter(|&(_, includes)| includes.contains(&key))
+                .map(|(scene, _)| *scene)
+                .collect();
+            for scene in including.iter() {
+                self.scene_includes.find_mut(scene).unwrap().remove(&key);
+            }
+            if self.active_scene == Some(key) {
+                self.active_scene = None;
+            }
+        }
+        self.layers.remove(&key);
+        self.objects.remove(&key);
+        self.release_string(obj.name);
+        self.events.push(ObjectRemoved(key));
+    }
+
+    /// Intern `s` and take a reference to it, each call must be paired
+    /// with a `release_string` once the user of the string is gone.
     fn new_string(&mut self, s: &str) -> StringKey {
         let (update, name) = match self.string_to_key.find(&s.to_string()) {
             None => {
@@ -112,15 +460,186 @@ impl CommonData {
             }
         };
 
-        if update {
+        let name = if update {
             let name = self.last_sid;
             self.last_sid += 1;
             self.strings.insert(name, s.to_string());
             self.string_to_key.insert(s.to_string(), name);
+            self.string_bytes += s.len();
             name
         } else {
             name
+        };
+
+        let count = match self.string_refs.find(&name) {
+            Some(count) => *count,
+            None => 0
+        };
+        self.string_refs.insert(name, count + 1);
+        name
+    }
+
+    fn release_string(&mut self, sid: StringKey) {
+        let count = match self.string_refs.find(&sid) {
+            Some(count) => *count,
+            None => return
+        };
+
+        if count > 1 {
+            self.string_refs.insert(sid, count - 1);
+            return;
+        }
+
+        self.string_refs.remove(&sid);
+        match self.strings.pop(&sid) {
+            Some(s) => {
+                self.string_bytes -= s.len();
+                self.string_to_key.remove(&s);
+            }
+            None => ()
+        }
+    }
+}
+
+/// The size of the string table of a `CommonData`.
+#[deriving(Clone, Default, PartialEq, Show)]
+pub struct StringStats {
+    /// Number of distinct names in use.
+    pub count: uint,
+    /// Bytes of text of the distinct names, not counting the overhead of
+    /// the maps that hold them.
+    pub bytes: uint
+}
+
+impl Snapshot for CommonData {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()> {
+        try!(snapshot::write_tag(w, snapshot::TAG_COMMON));
+
+        try!(w.write_le_u32(self.last_sid));
+        try!(w.write_le_u32(self.strings.len() as u32));
+        for (sid, s) in self.strings.iter() {
+            try!(w.write_le_u32(*sid));
+            try!(snapshot::write_string(w, s.as_slice()));
+        }
+
+        try!(w.write_le_u32(self.last_oid));
+        try!(w.write_le_u32(self.objects.len() as u32));
+        for (oid, obj) in self.objects.iter() {
+            try!(w.write_le_u32(*oid));
+            try!(w.write_le_u32(obj.parent));
+            try!(w.write_le_u32(obj.name));
+        }
+
+        try!(w.write_le_u32(self.scene_children.len() as u32));
+        for (scene, _) in self.scene_children.iter() {
+            try!(w.write_le_u32(*scene));
+        }
+
+        try!(w.write_le_u32(self.scene_includes.len() as u32));
+        for (scene, includes) in self.scene_includes.iter() {
+            try!(w.write_le_u32(*scene));
+            try!(w.write_le_u32(includes.len() as u32));
+            for s in includes.iter() {
+                try!(w.write_le_u32(*s));
+            }
+        }
+        try!(w.write_le_u32(self.active_scene.unwrap_or(0)));
+
+        try!(w.write_le_u32(self.layers.len() as u32));
+        for (oid, layers) in self.layers.iter() {
+            try!(w.write_le_u32(*oid));
+            try!(w.write_le_u32(*layers));
+        }
+        Ok(())
+    }
+
+    fn read_from(r: &mut Reader) -> IoResult<CommonData> {
+        Snapshot::read_version(r, snapshot::VERSION)
+    }
+
+    fn read_version(r: &mut Reader, version: u32) -> IoResult<CommonData> {
+        try!(snapshot::expect_tag(r, snapshot::TAG_COMMON));
+        let mut common = CommonData::new();
+
+        common.last_sid = try!(r.read_le_u32());
+        for _ in range(0, try!(r.read_le_u32())) {
+            let sid = try!(r.read_le_u32());
+            let s = try!(snapshot::read_string(r));
+            common.string_bytes += s.len();
+            common.string_to_key.insert(s.clone(), sid);
+            common.strings.insert(sid, s);
+        }
+
+        common.last_oid = try!(r.read_le_u32());
+        for _ in range(0, try!(r.read_le_u32())) {
+            let oid = try!(r.read_le_u32());
+            let obj = Object {
+                parent: try!(r.read_le_u32()),
+                name: try!(r.read_le_u32())
+            };
+            if common.strings.find(&obj.name).is_none() {
+                return Err(snapshot::invalid("object name is not in the string table"));
+            }
+            let count = match common.string_refs.find(&obj.name) {
+                Some(count) => *count,
+                None => 0
+            };
+            common.string_refs.insert(obj.name, count + 1);
+            common.objects.insert(oid, obj);
+            common.update_parent_child(obj.parent, obj.name, oid);
+        }
+
+        // drop names that no object uses anymore
+        let unused: Vec<StringKey> = common.strings.iter()
+            .filter(|&(sid, _)| common.string_refs.find(sid).is_none())
+            .map(|(sid, _)| *sid)
+            .collect();
+        for sid in unused.iter() {
+            common.string_refs.insert(*sid, 1);
+            common.release_string(*sid);
+        }
+
+        for _ in range(0, try!(r.read_le_u32())) {
+            let scene = try!(r.read_le_u32());
+            common.scene_children.insert(scene, BTreeSet::new());
+        }
+
+        if version >= 3 {
+            for _ in range(0, try!(r.read_le_u32())) {
+                let scene = try!(r.read_le_u32());
+                let mut includes = BTreeSet::new();
+                for _ in range(0, try!(r.read_le_u32())) {
+                    includes.insert(try!(r.read_le_u32()));
+                }
+                common.scene_includes.insert(scene, includes);
+            }
+            common.active_scene = match try!(r.read_le_u32()) {
+                0 => None,
+                scene => Some(scene)
+            };
+        }
+
+        // objects from before version 2 keep the default layers
+        if version >= 2 {
+            for _ in range(0, try!(r.read_le_u32())) {
+                let oid = try!(r.read_le_u32());
+                let layers = try!(r.read_le_u32());
+                common.layers.insert(oid, layers);
+            }
+        }
+
+        // scene membership is derived from the tree, rebuild it
+        let keys: Vec<ObjectKey> = common.objects.iter().map(|(k, _)| *k).collect();
+        for key in keys.iter() {
+            match common.scene_of(*key) {
+                Some(scene) => {
+                    common.scene_children.find_mut(&scene).unwrap().insert(*key);
+                }
+                None => ()
+            }
         }
+
+        Ok(common)
     }
 }
 
@@ -152,6 +671,7 @@ pub trait Common {
 
         self.get_common_mut().objects.insert(new_key, object);
         self.get_common_mut().update_parent_child(parent, object.name, new_key);
+        self.log_event(ObjectCreated(new_key));
 
         let mut scene_id = None;
         while parent != 0 {
@@ -177,16 +697,335 @@ pub trait Common {
         new_key
     }
 
-    fn scene_iter<'a>(&'a self, oid: ObjectKey) -> BTreeSetIterator<'a, u32> {
-        let sc = self.get_common().scene_children.find(&oid)
-            .expect("Failed to find scene");
-        sc.iter()
+    /// Move an object and its children under a new parent. Scene membership
+    /// follows the object, so a subtree can be moved from one scene into another.
+    /// Fails if the parent is part of the subtree or already has a child of the
+    /// same name. Game data with positions should use `Positions::move_object`,
+    /// which also moves the locations of the subtree.
+    fn set_parent(&mut self, key: ObjectKey, parent: Option<ObjectKey>) -> bool {
+        let parent = match parent {
+            Some(key) => key,
+            None => 0
+        };
+
+        let obj = match self.object(key) {
+            Some(obj) => *obj,
+            None => return false
+        };
+
+        if obj.parent == parent {
+            return true;
+        }
+
+        if parent != 0 && self.object(parent).is_none() {
+            return false;
+        }
+
+        if self.get_common().is_ancestor(key, parent) {
+            return false;
+        }
+
+        match self.get_common().parent_child.find(&parent) {
+            Some(child_list) if child_list.find(&obj.name).is_some() => return false,
+            _ => ()
+        }
+
+        let mut members = Vec::new();
+        self.get_common().scene_members(key, &mut members);
+
+        match self.get_common().scene_of(key) {
+            Some(scene) => {
+                let sc = self.get_common_mut().scene_children.find_mut(&scene).unwrap();
+                for k in members.iter() {
+                    sc.remove(k);
+                }
+            }
+            None => ()
+        }
+
+        let empty = match self.get_common_mut().parent_child.find_mut(&obj.parent) {
+            Some(child_list) => {
+                if child_list.find(&obj.name) == Some(&key) {
+                    child_list.remove(&obj.name);
+                }
+                child_list.len() == 0
+            }
+            None => false
+        };
+        if empty {
+            self.get_common_mut().parent_child.remove(&obj.parent);
+        }
+        self.get_common_mut().update_parent_child(parent, obj.name, key);
+        self.get_common_mut().objects.insert(key, Object {
+            parent: parent,
+            name: obj.name
+        });
+
+        match self.get_common().scene_of(key) {
+            Some(scene) => {
+                let sc = self.get_common_mut().scene_children.find_mut(&scene).unwrap();
+                for k in members.iter() {
+                    sc.insert(*k);
+                }
+            }
+            None => ()
+        }
+
+        self.get_common_mut().moves += 1;
+        self.log_event(ObjectMoved(key));
+        true
+    }
+
+    /// Change the name of an object. Fails if a sibling already uses the name.
+    fn rename(&mut self, key: ObjectKey, name: &str) -> bool {
+        let obj = match self.object(key) {
+            Some(obj) => *obj,
+            None => return false
+        };
+
+        match self.get_common().ifind(Some(obj.parent), name) {
+            Some(k) => return k == key,
+            None => ()
+        }
+
+        let name = self.get_common_mut().new_string(name);
+        match self.get_common_mut().parent_child.find_mut(&obj.parent) {
+            Some(child_list) => {
+                if child_list.find(&obj.name) == Some(&key) {
+                    child_list.remove(&obj.name);
+                }
+            }
+            None => ()
+        }
+        self.get_common_mut().update_parent_child(obj.parent, name, key);
+        self.get_common_mut().objects.insert(key, Object {
+            parent: obj.parent,
+            name: name
+        });
+        self.get_common_mut().release_string(obj.name);
+
+        self.log_event(ObjectRenamed(key));
+        true
+    }
+
+    /// Remove a single object. Objects that still have children can not
+    /// be removed this way, use `delete_subtree` instead.
+    fn delete_object(&mut self, key: ObjectKey) -> bool {
+        if self.object(key).is_none() {
+            return false;
+        }
+
+        match self.get_common().parent_child.find(&key) {
+            Some(children) if children.len() != 0 => return false,
+            _ => ()
+        }
+
+        self.get_common_mut().remove(key);
+        true
+    }
+
+    /// Remove an object and all of its children. The removed keys are
+    /// returned children first, so that the other data blocks can drop
+    /// their rows for them in the same generation.
+    fn delete_subtree(&mut self, key: ObjectKey) -> Vec<ObjectKey> {
+        let keys = self.subtree(key);
+        for k in keys.iter() {
+            self.get_common_mut().remove(*k);
+        }
+        keys
+    }
+
+    /// Copy the subtree rooted at `prefab` under `parent` as `name`, every
+    /// copy gets a fresh key and keeps its layers. This only copies the tree,
+    /// the returned `Remap` is handed to the other data blocks so they can
+    /// copy their rows. Fails if `parent` is inside the prefab or already has
+    /// a child named `name`.
+    fn instantiate(&mut self, prefab: ObjectKey, parent: Option<ObjectKey>, name: &str) -> Option<Remap> {
+        if self.object(prefab).is_none() {
+            return None;
+        }
+
+        match parent {
+            Some(p) if self.object(p).is_none() || self.get_common().is_ancestor(prefab, p) => return None,
+            _ => ()
+        }
+
+        if self.get_common().ifind(parent, name).is_some() {
+            return None;
+        }
+
+        // parents before their children
+        let mut keys = self.subtree(prefab);
+        keys.reverse();
+
+        let root = self.new_object(parent, name);
+        let mut remap = Remap {
+            root: root,
+            map: BTreeMap::new()
+        };
+        remap.map.insert(prefab, root);
+
+        for old in keys.iter() {
+            let new = if *old == prefab {
+                root
+            } else {
+                let parent = remap.remap(self.object(*old).unwrap().parent);
+                let name = self.object_name(*old).unwrap().to_string();
+                let new = self.new_object(Some(parent), name.as_slice());
+                remap.map.insert(*old, new);
+                new
+            };
+
+            if self.get_common().scene_children.find(old).is_some() {
+                self.get_common_mut().scene_children.insert(new, BTreeSet::new());
+            }
+            let layers = self.layers(*old);
+            if layers != DEFAULT_LAYER {
+                self.set_layers(new, layers);
+            }
+        }
+
+        Some(remap)
+    }
+
+    /// All the keys in the subtree rooted at `key`, children before their parent.
+    fn subtree(&self, key: ObjectKey) -> Vec<ObjectKey> {
+        let mut keys = Vec::new();
+        if self.object(key).is_some() {
+            self.get_common().subtree(key, &mut keys);
+        }
+        keys
+    }
+
+    fn is_scene(&self, key: ObjectKey) -> bool {
+        self.get_common().scene_children.find(&key).is_some()
+    }
+
+    /// The members of `scene` and of every scene it includes, in key
+    /// order. Empty if `scene` is not a scene.
+    fn scene_iter<'a>(&'a self, scene: ObjectKey) -> SceneIter<'a> {
+        let common = self.get_common();
+        let mut scenes = Vec::new();
+        if self.is_scene(scene) {
+            common.included_scenes(scene, &mut scenes);
+        }
+
+        SceneIter {
+            iters: scenes.iter()
+                         .map(|s| common.scene_children.find(s).unwrap().iter().peekable())
+                         .collect()
+        }
+    }
+
+    /// True if `key` is a member of `scene` or of a scene it includes.
+    fn scene_contains(&self, scene: ObjectKey, key: ObjectKey) -> bool {
+        let common = self.get_common();
+        let mut scenes = Vec::new();
+        if self.is_scene(scene) {
+            common.included_scenes(scene, &mut scenes);
+        }
+        scenes.iter().any(|s| common.scene_children.find(s).unwrap().contains(&key))
+    }
+
+    /// Make the members of `other` members of `scene` too, for example a
+    /// scene holding the UI that is shared by every level. Fails if either
+    /// key is not a scene or they are the same scene.
+    fn include_scene(&mut self, scene: ObjectKey, other: ObjectKey) -> bool {
+        if scene == other || !self.is_scene(scene) || !self.is_scene(other) {
+            return false;
+        }
+
+        let common = self.get_common_mut();
+        let new = match common.scene_includes.find_mut(&scene) {
+            Some(includes) => {
+                includes.insert(other);
+                false
+            }
+            None => true
+        };
+        if new {
+            let mut includes = BTreeSet::new();
+            includes.insert(other);
+            common.scene_includes.insert(scene, includes);
+        }
+        true
+    }
+
+    fn exclude_scene(&mut self, scene: ObjectKey, other: ObjectKey) {
+        let common = self.get_common_mut();
+        let empty = match common.scene_includes.find_mut(&scene) {
+            Some(includes) => {
+                includes.remove(&other);
+                includes.len() == 0
+            }
+            None => false
+        };
+        if empty {
+            common.scene_includes.remove(&scene);
+        }
+    }
+
+    /// Load a new top level scene named `name` from a prefab. Prefabs that
+    /// are kept outside of any scene are never drawn, so a level can be
+    /// stored once and loaded any number of times.
+    fn load_scene(&mut self, prefab: ObjectKey, name: &str) -> Option<Remap> {
+        let remap = match self.instantiate(prefab, None, name) {
+            Some(remap) => remap,
+            None => return None
+        };
+
+        let root = remap.root();
+        if !self.is_scene(root) {
+            let mut members = Vec::new();
+            self.get_common().scene_members(root, &mut members);
+            let mut set = BTreeSet::new();
+            for k in members.iter() {
+                if *k != root {
+                    set.insert(*k);
+                }
+            }
+            self.get_common_mut().scene_children.insert(root, set);
+        }
+        Some(remap)
+    }
+
+    /// Remove a scene and everything in it. Like `delete_subtree` the
+    /// removed keys are returned so the other data blocks can drop them.
+    fn unload_scene(&mut self, scene: ObjectKey) -> Vec<ObjectKey> {
+        if !self.is_scene(scene) {
+            return Vec::new();
+        }
+        self.delete_subtree(scene)
+    }
+
+    /// Make the scene at `path` the active scene.
+    fn activate_scene(&mut self, path: &str) -> Option<ObjectKey> {
+        let scene = self.find(path);
+        match scene {
+            Some(scene) if self.is_scene(scene) => {
+                self.get_common_mut().active_scene = Some(scene);
+                Some(scene)
+            }
+            _ => None
+        }
+    }
+
+    /// The scene that was last activated, `None` if it was unloaded.
+    fn active_scene(&self) -> Option<ObjectKey> {
+        self.get_common().active_scene
     }
 
     fn object<'a>(&'a self, oid: ObjectKey) -> Option<&'a Object> {
         self.get_common().objects.find(&oid)
     }
 
+    fn object_name<'a>(&'a self, oid: ObjectKey) -> Option<&'a str> {
+        match self.object(oid) {
+            Some(obj) => self.get_common().strings.find(&obj.name).map(|s| s.as_slice()),
+            None => None
+        }
+    }
+
     fn find(&self, str_key: &str) -> Option<ObjectKey> {
         let mut node = None;
         for s in str_key.split('/') {
@@ -199,6 +1038,114 @@ pub trait Common {
         node
     }
 
+    /// Look up a path relative to `base`, `.` and `..` are understood and a
+    /// leading `/` starts from the root.
+    fn find_from(&self, base: ObjectKey, path: &str) -> Option<ObjectKey> {
+        if is_pattern(path) {
+            return None;
+        }
+        self.get_common().resolve(base, path).move_iter().next()
+    }
+
+    /// All objects matching a glob pattern. `*` and `?` match within a
+    /// single name and `**` matches any number of levels, including none.
+    fn glob(&self, pattern: &str) -> MoveItems<ObjectKey> {
+        self.get_common().resolve(0, pattern).move_iter()
+    }
+
+    /// `glob` relative to `base`.
+    fn glob_from(&self, base: ObjectKey, pattern: &str) -> MoveItems<ObjectKey> {
+        self.get_common().resolve(base, pattern).move_iter()
+    }
+
+    /// The path of `key` from the root, `find` on the path returns `key`.
+    fn full_path(&self, key: ObjectKey) -> Option<String> {
+        self.get_common().path(key)
+    }
+
+    /// The layers of `key`, objects are on `DEFAULT_LAYER` until set.
+    fn layers(&self, key: ObjectKey) -> Layers {
+        match self.get_common().layers.find(&key) {
+            Some(layers) => *layers,
+            None => DEFAULT_LAYER
+        }
+    }
+
+    fn set_layers(&mut self, key: ObjectKey, layers: Layers) {
+        if layers == DEFAULT_LAYER {
+            self.get_common_mut().layers.remove(&key);
+        } else {
+            self.get_common_mut().layers.insert(key, layers);
+        }
+        self.log_event(LayersChanged(key));
+    }
+
+    fn add_layers(&mut self, key: ObjectKey, layers: Layers) {
+        let old = self.layers(key);
+        self.set_layers(key, old | layers);
+    }
+
+    fn remove_layers(&mut self, key: ObjectKey, layers: Layers) {
+        let old = self.layers(key);
+        self.set_layers(key, old & !layers);
+    }
+
+    /// True if `key` is on any of the layers in `mask`.
+    fn in_layers(&self, key: ObjectKey, mask: Layers) -> bool {
+        self.layers(key) & mask != 0
+    }
+
+    /// Objects that are on layers other than `DEFAULT_LAYER` alone.
+    fn layer_iter<'a>(&'a self) -> BTreeMapIterator<'a, ObjectKey, Layers> {
+        self.get_common().layers.iter()
+    }
+
+    /// Append an event to the log of this generation.
+    fn log_event(&mut self, event: Event) {
+        self.get_common_mut().events.push(event);
+    }
+
+    /// The position after the last event, see `events_since`.
+    fn event_seq(&self) -> EventSeq {
+        self.get_common().events.seq()
+    }
+
+    /// The number of times an object was given a new parent. A data block
+    /// that caches something derived from the tree compares it to tell
+    /// whether it has `ObjectMoved` events to look for.
+    fn moves(&self) -> u64 {
+        self.get_common().moves
+    }
+
+    /// The events logged since `seq`, or `None` if the log no longer
+    /// reaches back that far or `seq` was taken from another branch of
+    /// generations, the caller has to rescan in that case.
+    fn events_since<'a>(&'a self, seq: EventSeq) -> Option<Events<'a>> {
+        self.get_common().events.since(seq)
+    }
+
+    /// Keep at most `limit` events in the log.
+    fn set_event_limit(&mut self, limit: uint) {
+        self.get_common_mut().events.set_limit(limit);
+    }
+
+    /// The size of the string table. Names are dropped from the table
+    /// when the last object using them is removed or renamed.
+    fn string_stats(&self) -> StringStats {
+        StringStats {
+            count: self.get_common().strings.len(),
+            bytes: self.get_common().string_bytes
+        }
+    }
+
+    /// The keys of the direct children of `key`.
+    fn children(&self, key: ObjectKey) -> Vec<ObjectKey> {
+        match self.get_common().parent_child.find(&key) {
+            Some(children) => children.iter().map(|(_, k)| *k).collect(),
+            None => Vec::new()
+        }
+    }
+
     fn walk_dir<'a>(&'a self, oid: ObjectKey) -> DirIter<'a> {
         let dir = self.get_common().parent_child.find(&oid).unwrap();
         DirIter {
@@ -208,7 +1155,10 @@ pub trait Common {
     }
 
     fn name(&self, key: ObjectKey) -> String {
-        self.get_common().name(key)
+        match self.full_path(key) {
+            Some(path) => path,
+            None => String::new()
+        }
     }
 }
 
@@ -217,6 +1167,40 @@ impl Common for CommonData {
     fn get_common_mut<'a>(&'a mut self) -> &'a mut CommonData {self}
 }
 
+/// Merges the member sets of a scene and the scenes it includes.
+pub struct SceneIter<'a> {
+    iters: Vec<Peekable<&'a ObjectKey, BTreeSetIterator<'a, ObjectKey>>>
+}
+
+impl<'a> Iterator<&'a ObjectKey> for SceneIter<'a> {
+    fn next(&mut self) -> Option<&'a ObjectKey> {
+        let mut min: Option<&'a ObjectKey> = None;
+        for iter in self.iters.mut_iter() {
+            match iter.peek() {
+                Some(&key) => {
+                    if min.map_or(true, |m| *key < *m) {
+                        min = Some(key);
+                    }
+                }
+                None => ()
+            }
+        }
+
+        let min = match min {
+            Some(min) => min,
+            None => return None
+        };
+
+        // a key can be in more than one of the sets
+        for iter in self.iters.mut_iter() {
+            if iter.peek().map_or(false, |key| **key == *min) {
+                iter.next();
+            }
+        }
+        Some(min)
+    }
+}
+
 pub struct DirIter<'a> {
     common: &'a CommonData,
     iter: BTreeMapIterator<'a, StringKey, ObjectKey>,
@@ -0,0 +1,191 @@
+//! Generic storage for per-object data.
+//!
+//! A `ComponentStore<T>` is a copy-on-write map from `ObjectKey` to `T`, it
+//! behaves exactly like the maps inside the built in data blocks. The
+//! `component!` macro declares the accessor trait for a store, and
+//! `game_data!` assembles a game struct from a list of data blocks and
+//! implements their accessor traits.
+//!
+//! ```ignore
+//! #[deriving(Clone)]
+//! pub struct Health(pub u32);
+//!
+//! component!(Healths: Health { get_health, get_health_mut })
+//!
+//! game_data!(GameData {
+//!     common: CommonData => Common { get_common, get_common_mut },
+//!     position: PositionData => Positions { get_position, get_position_mut },
+//!     health: ComponentStore<Health> => Healths { get_health, get_health_mut }
+//! } hooks {
+//!     instantiate_position, delete_position
+//! })
+//! ```
+//!
+//! The `hooks` list names the per-block methods that copy rows for the
+//! objects made by `Common::instantiate` and drop the rows of a removed
+//! object. `spawn` and `despawn` run all of them, so a prefab or subtree is
+//! carried over or removed in every block at once.
+
+use std::default::Default;
+use std::io::IoResult;
+
+use cow::btree::{BTreeMap, BTreeMapIterator};
+
+use common::{ObjectKey, Remap};
+use diff::{Diff, diff_maps};
+use snapshot::Snapshot;
+
+#[deriving(Clone)]
+pub struct ComponentStore<T> {
+    map: BTreeMap<ObjectKey, T>
+}
+
+impl<T: Clone + Send + Share> ComponentStore<T> {
+    pub fn new() -> ComponentStore<T> {
+        ComponentStore {
+            map: BTreeMap::new()
+        }
+    }
+
+    /// Set the component of `key`, returns true if it did not have one.
+    pub fn insert(&mut self, key: ObjectKey, value: T) -> bool {
+        self.map.insert(key, value)
+    }
+
+    pub fn get<'a>(&'a self, key: ObjectKey) -> Option<&'a T> {
+        self.map.find(&key)
+    }
+
+    pub fn get_mut<'a>(&'a mut self, key: ObjectKey) -> Option<&'a mut T> {
+        self.map.find_mut(&key)
+    }
+
+    /// Remove the component of `key`, returns true if it had one.
+    pub fn remove(&mut self, key: ObjectKey) -> bool {
+        self.map.remove(&key)
+    }
+
+    pub fn contains(&self, key: ObjectKey) -> bool {
+        self.map.find(&key).is_some()
+    }
+
+    pub fn len(&self) -> uint { self.map.len() }
+
+    pub fn iter<'a>(&'a self) -> BTreeMapIterator<'a, ObjectKey, T> {
+        self.map.iter()
+    }
+
+    /// Give each copy made by `Common::instantiate` the component of the
+    /// object it was copied from.
+    pub fn instantiate(&mut self, remap: &Remap) {
+        for (old, new) in remap.iter() {
+            let value = self.map.find(old).map(|v| v.clone());
+            match value {
+                Some(v) => { self.map.insert(*new, v); }
+                None => ()
+            }
+        }
+    }
+
+    /// Compare two generations of the store, `eq` is used to check if a
+    /// component that is in both was modified.
+    pub fn diff(old: &ComponentStore<T>, new: &ComponentStore<T>, eq: |&T, &T| -> bool) -> Diff {
+        diff_maps(old.map.iter(), new.map.iter(), eq)
+    }
+}
+
+impl<T: Clone + Send + Share> Default for ComponentStore<T> {
+    fn default() -> ComponentStore<T> { ComponentStore::new() }
+}
+
+impl<T: Clone + Send + Share + Snapshot> Snapshot for ComponentStore<T> {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()> {
+        try!(w.write_le_u32(self.map.len() as u32));
+        for (key, v) in self.map.iter() {
+            try!(w.write_le_u32(*key));
+            try!(v.write_to(w));
+        }
+        Ok(())
+    }
+
+    fn read_from(r: &mut Reader) -> IoResult<ComponentStore<T>> {
+        let mut store = ComponentStore::new();
+        for _ in range(0, try!(r.read_le_u32())) {
+            let key = try!(r.read_le_u32());
+            store.insert(key, try!(Snapshot::read_from(r)));
+        }
+        Ok(store)
+    }
+}
+
+/// Declare the accessor trait of a `ComponentStore`.
+#[macro_export]
+macro_rules! component(
+    ($tr:ident: $ty:ty { $get:ident, $get_mut:ident }) => (
+        pub trait $tr {
+            fn $get<'a>(&'a self) -> &'a ::snowmew::component::ComponentStore<$ty>;
+            fn $get_mut<'a>(&'a mut self) -> &'a mut ::snowmew::component::ComponentStore<$ty>;
+        }
+    )
+)
+
+/// Declare a game data struct made of the given data blocks and implement
+/// the accessor trait of each block. Every block must implement `Default`,
+/// `empty()` builds the struct with every block empty. With a `hooks` list
+/// the struct also gets `spawn` and `despawn`.
+#[macro_export]
+macro_rules! game_data(
+    ($name:ident { $($field:ident: $ty:ty => $tr:ident { $get:ident, $get_mut:ident }),+ }
+     hooks { $($instantiate:ident, $delete:ident);+ }) => (
+        game_data!($name { $($field: $ty => $tr { $get, $get_mut }),+ })
+
+        impl $name {
+            /// Copy a prefab with `Common::instantiate` and give the copies
+            /// the rows of every data block.
+            #[allow(dead_code)]
+            pub fn spawn(&mut self, prefab: ::snowmew::common::ObjectKey,
+                         parent: Option<::snowmew::common::ObjectKey>,
+                         name: &str) -> Option<::snowmew::common::Remap> {
+                let remap = match self.instantiate(prefab, parent, name) {
+                    Some(remap) => remap,
+                    None => return None
+                };
+                $(self.$instantiate(&remap);)+
+                Some(remap)
+            }
+
+            /// Remove an object and its children with `Common::delete_subtree`
+            /// and drop their rows from every data block.
+            #[allow(dead_code)]
+            pub fn despawn(&mut self, key: ::snowmew::common::ObjectKey) -> Vec<::snowmew::common::ObjectKey> {
+                let keys = self.delete_subtree(key);
+                for k in keys.iter() {
+                    $(self.$delete(*k);)+
+                }
+                keys
+            }
+        }
+    );
+    ($name:ident { $($field:ident: $ty:ty => $tr:ident { $get:ident, $get_mut:ident }),+ }) => (
+        #[deriving(Clone)]
+        pub struct $name {
+            $($field: $ty),+
+        }
+
+        impl $name {
+            #[allow(dead_code)]
+            pub fn empty() -> $name {
+                $name {
+                    $($field: ::std::default::Default::default()),+
+                }
+            }
+        }
+
+        $(
+            impl $tr for $name {
+                fn $get<'a>(&'a self) -> &'a $ty { &self.$field }
+                fn $get_mut<'a>(&'a mut self) -> &'a mut $ty { &mut self.$field }
+            }
+        )+
+    )
+)
@@ -0,0 +1,85 @@
+//! Compare two generations of the database.
+//!
+//! Every data block is a set of maps keyed by `ObjectKey`, so the difference
+//! between two generations is found by walking both maps in key order.
+
+use cow::btree::BTreeMapIterator;
+
+use common::ObjectKey;
+
+#[deriving(Clone, Default, PartialEq, Show)]
+pub struct Diff {
+    pub added: Vec<ObjectKey>,
+    pub removed: Vec<ObjectKey>,
+    pub modified: Vec<ObjectKey>
+}
+
+impl Diff {
+    pub fn new() -> Diff {
+        Diff {
+            added: Vec::new(),
+            removed: Vec::new(),
+            modified: Vec::new()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.len() == 0 &&
+        self.removed.len() == 0 &&
+        self.modified.len() == 0
+    }
+
+    /// Every key that was added or modified, these are the keys that a
+    /// consumer will need to reload.
+    pub fn changed(&self) -> Vec<ObjectKey> {
+        let mut out: Vec<ObjectKey> = self.added.iter().chain(self.modified.iter()).map(|k| *k).collect();
+        out.sort();
+        out
+    }
+}
+
+/// Diff two maps, `eq` is used to check if a value that is present in both
+/// generations was modified.
+pub fn diff_maps<'a, V>(old: BTreeMapIterator<'a, ObjectKey, V>,
+                        new: BTreeMapIterator<'a, ObjectKey, V>,
+                        eq: |&V, &V| -> bool) -> Diff {
+    let mut diff = Diff::new();
+    let mut old = old.peekable();
+    let mut new = new.peekable();
+
+    loop {
+        let order = match (old.peek(), new.peek()) {
+            (None, None) => break,
+            (Some(_), None) => Less,
+            (None, Some(_)) => Greater,
+            (Some(&(ko, _)), Some(&(kn, _))) => ko.cmp(kn)
+        };
+
+        match order {
+            Less => {
+                let (k, _) = old.next().unwrap();
+                diff.removed.push(*k);
+            }
+            Greater => {
+                let (k, _) = new.next().unwrap();
+                diff.added.push(*k);
+            }
+            Equal => {
+                let (k, vo) = old.next().unwrap();
+                let (_, vn) = new.next().unwrap();
+                if !eq(vo, vn) {
+                    diff.modified.push(*k);
+                }
+            }
+        }
+    }
+
+    diff
+}
+
+/// Diff two maps where the values are never modified in place, only the
+/// keys that were added or removed are reported.
+pub fn diff_keys<'a, V>(old: BTreeMapIterator<'a, ObjectKey, V>,
+                        new: BTreeMapIterator<'a, ObjectKey, V>) -> Diff {
+    diff_maps(old, new, |_, _| true)
+}
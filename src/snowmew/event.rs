@@ -0,0 +1,186 @@
+//! A log of the changes made to the database.
+//!
+//! The data block traits append an `Event` to the log in `CommonData` for
+//! every change they make, so each generation carries the log of how it was
+//! built. A consumer remembers the `EventSeq` it has read up to and drains
+//! the events added since then instead of rescanning the maps.
+//!
+//! Generations that branch off a common ancestor, for example after a
+//! checkout in `History`, number their events the same way. Every event is
+//! also stamped with a number that is unique to the process, an `EventSeq`
+//! carries the stamp of the last event it covers so a consumer that is
+//! handed a generation from another branch is told to rescan.
+//!
+//! ```ignore
+//! match self.seq.and_then(|seq| db.events_since(seq)) {
+//!     Some(events) => for (_, ev) in events { ... },
+//!     None => self.rescan(db)
+//! }
+//! self.seq = Some(db.event_seq());
+//! ```
+
+use std::sync::atomics::{AtomicUint, INIT_ATOMIC_UINT, SeqCst};
+
+use cow::btree::BTreeMap;
+
+use common::ObjectKey;
+
+#[deriving(Clone, PartialEq, Show)]
+pub enum Event {
+    ObjectCreated(ObjectKey),
+    ObjectRemoved(ObjectKey),
+    /// The object was given a new parent.
+    ObjectMoved(ObjectKey),
+    ObjectRenamed(ObjectKey),
+    LayersChanged(ObjectKey),
+    /// The local transform of the object was set.
+    PositionChanged(ObjectKey),
+    DrawableSet(ObjectKey),
+    /// The drawable was bound to a skeleton.
+    SkinSet(ObjectKey),
+    VertexBufferAdded(ObjectKey),
+    GeometryAdded(ObjectKey),
+    MaterialAdded(ObjectKey),
+    TextureAdded(ObjectKey),
+    LightChanged(ObjectKey),
+    /// A static or dynamic collider was set.
+    ColliderChanged(ObjectKey)
+}
+
+impl Event {
+    /// The object the event is about.
+    pub fn key(&self) -> ObjectKey {
+        match *self {
+            ObjectCreated(k) | ObjectRemoved(k) | ObjectMoved(k) | ObjectRenamed(k) |
+            LayersChanged(k) | PositionChanged(k) | DrawableSet(k) | SkinSet(k) | VertexBufferAdded(k) |
+            GeometryAdded(k) | MaterialAdded(k) | TextureAdded(k) | LightChanged(k) |
+            ColliderChanged(k) => k
+        }
+    }
+}
+
+/// Number of events kept by default, older events are dropped.
+pub static DEFAULT_LIMIT: uint = 1 << 16;
+
+static mut NEXT_STAMP: AtomicUint = INIT_ATOMIC_UINT;
+
+fn next_stamp() -> u64 {
+    // 0 is left for the start of the log
+    unsafe { NEXT_STAMP.fetch_add(1, SeqCst) as u64 + 1 }
+}
+
+/// How far a consumer has read into the log of a lineage of generations.
+#[deriving(Clone, PartialEq, Show)]
+pub struct EventSeq {
+    /// The sequence number of the next event to be read.
+    pub seq: u64,
+    // the stamp of the event before `seq`, 0 if there is none
+    stamp: u64
+}
+
+impl EventSeq {
+    /// The start of every log, before any event was logged.
+    pub fn start() -> EventSeq {
+        EventSeq {
+            seq: 0,
+            stamp: 0
+        }
+    }
+}
+
+/// A bounded log of events, numbered from the first event ever logged.
+#[deriving(Clone)]
+pub struct EventLog {
+    first: u64,
+    next: u64,
+    limit: uint,
+    // stamp of the last event that was dropped, or of the start of the log
+    trimmed: u64,
+    events: BTreeMap<u64, (u64, Event)>
+}
+
+impl EventLog {
+    pub fn new(limit: uint) -> EventLog {
+        assert!(limit > 0);
+        EventLog {
+            first: 0,
+            next: 0,
+            limit: limit,
+            trimmed: 0,
+            events: BTreeMap::new()
+        }
+    }
+
+    pub fn push(&mut self, event: Event) {
+        self.events.insert(self.next, (next_stamp(), event));
+        self.next += 1;
+        self.trim();
+    }
+
+    // the stamp of the event before `seq`, `None` if it was dropped
+    fn stamp_before(&self, seq: u64) -> Option<u64> {
+        if seq == self.first {
+            Some(self.trimmed)
+        } else if seq > self.first && seq <= self.next {
+            self.events.find(&(seq - 1)).map(|&(stamp, _)| stamp)
+        } else {
+            None
+        }
+    }
+
+    /// The position after the last event, a consumer that has read
+    /// everything in this generation stores it.
+    pub fn seq(&self) -> EventSeq {
+        EventSeq {
+            seq: self.next,
+            stamp: self.stamp_before(self.next).unwrap()
+        }
+    }
+
+    pub fn len(&self) -> uint { self.events.len() }
+
+    pub fn set_limit(&mut self, limit: uint) {
+        assert!(limit > 0);
+        self.limit = limit;
+        self.trim();
+    }
+
+    /// The events logged at or after `seq`. Returns `None` if some of them
+    /// were already dropped, or if `seq` is from another branch of
+    /// generations, the consumer has to rescan in that case.
+    pub fn since<'a>(&'a self, seq: EventSeq) -> Option<Events<'a>> {
+        match self.stamp_before(seq.seq) {
+            Some(stamp) if stamp == seq.stamp => {
+                Some(Events {
+                    log: self,
+                    seq: seq.seq
+                })
+            }
+            _ => None
+        }
+    }
+
+    fn trim(&mut self) {
+        while self.events.len() > self.limit {
+            let (stamp, _) = self.events.pop(&self.first).unwrap();
+            self.trimmed = stamp;
+            self.first += 1;
+        }
+    }
+}
+
+pub struct Events<'a> {
+    log: &'a EventLog,
+    seq: u64
+}
+
+impl<'a> Iterator<(u64, &'a Event)> for Events<'a> {
+    fn next(&mut self) -> Option<(u64, &'a Event)> {
+        if self.seq >= self.log.next {
+            return None;
+        }
+        let seq = self.seq;
+        self.seq += 1;
+        self.log.events.find(&seq).map(|&(_, ref ev)| (seq, ev))
+    }
+}
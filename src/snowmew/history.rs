@@ -0,0 +1,249 @@
+//! Undo / redo on top of the copy-on-write database.
+//!
+//! Holding onto an old generation only costs the nodes that have been
+//! modified since, so each commit simply keeps a clone of the game data.
+//! Commits form a tree, committing after an undo starts a new branch.
+//!
+//! The budget of a history counts changes, not bytes. A commit costs one
+//! plus the number of events logged between it and its parent. Data blocks
+//! that do not log events count for nothing and a single event can stand
+//! for a change of any size, so the budget limits how many edits are kept
+//! rather than how much memory they hold on to.
+
+use collections::TrieMap;
+
+use common::Common;
+
+pub type CommitId = uint;
+
+// the changes a commit counts against the budget
+fn cost<GD: Common>(parent: &GD, gd: &GD) -> uint {
+    let (old, new) = (parent.event_seq().seq, gd.event_seq().seq);
+    let changes = if new > old { (new - old) as uint } else { 0 };
+    1 + changes
+}
+
+struct Commit<GD> {
+    tag: String,
+    data: GD,
+    cost: uint,
+    parent: Option<CommitId>,
+    children: Vec<CommitId>,
+    // the child that `redo` will move to
+    redo: Option<CommitId>
+}
+
+pub struct History<GD> {
+    commits: TrieMap<Commit<GD>>,
+    head: CommitId,
+    next_id: CommitId,
+    budget: uint,
+    changes: uint
+}
+
+impl<GD: Common + Clone> History<GD> {
+    /// Create a history starting at `gd` that keeps at most `budget`
+    /// changes, see `prune` for what is dropped once it is over.
+    pub fn new(gd: GD, budget: uint) -> History<GD> {
+        let mut commits = TrieMap::new();
+        commits.insert(0, Commit {
+            tag: "initial".to_string(),
+            data: gd,
+            cost: 1,
+            parent: None,
+            children: Vec::new(),
+            redo: None
+        });
+
+        History {
+            commits: commits,
+            head: 0,
+            next_id: 1,
+            budget: budget,
+            changes: 1
+        }
+    }
+
+    fn get<'a>(&'a self, id: CommitId) -> &'a Commit<GD> {
+        self.commits.find(&id).expect("commit not found")
+    }
+
+    fn get_mut<'a>(&'a mut self, id: CommitId) -> &'a mut Commit<GD> {
+        self.commits.find_mut(&id).expect("commit not found")
+    }
+
+    /// Record a new generation as a child of the current head.
+    pub fn commit(&mut self, tag: &str, gd: GD) -> CommitId {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let head = self.head;
+        let cost = cost(self.head(), &gd);
+        self.changes += cost;
+        self.commits.insert(id, Commit {
+            tag: tag.to_string(),
+            data: gd,
+            cost: cost,
+            parent: Some(head),
+            children: Vec::new(),
+            redo: None
+        });
+        {
+            let parent = self.get_mut(head);
+            parent.children.push(id);
+            parent.redo = Some(id);
+        }
+        self.head = id;
+
+        self.prune();
+        id
+    }
+
+    // the commits that undo and redo can reach from the head
+    fn active(&self) -> Vec<CommitId> {
+        let mut out = self.log();
+        let mut id = self.head;
+        loop {
+            match self.get(id).redo {
+                Some(child) => {
+                    out.push(child);
+                    id = child;
+                }
+                None => return out
+            }
+        }
+    }
+
+    // leaves that undo and redo can not reach go first, then the oldest
+    // undo step and last the furthest redo step. The head is never picked.
+    fn victim(&self) -> Option<CommitId> {
+        let active = self.active();
+        let leaf = self.commits.iter()
+            .find(|&(id, c)| c.children.len() == 0 && !active.contains(&id))
+            .map(|(id, _)| id);
+        if leaf.is_some() {
+            return leaf;
+        }
+
+        let oldest = *self.log().last().unwrap();
+        if oldest != self.head {
+            return Some(oldest);
+        }
+
+        let furthest = *active.last().unwrap();
+        if furthest != self.head {
+            Some(furthest)
+        } else {
+            None
+        }
+    }
+
+    fn remove(&mut self, id: CommitId) {
+        let commit = self.commits.pop(&id).unwrap();
+        self.changes -= commit.cost;
+        match commit.parent {
+            Some(parent) => {
+                let parent = self.get_mut(parent);
+                parent.children.retain(|c| *c != id);
+                if parent.redo == Some(id) {
+                    parent.redo = None;
+                }
+            }
+            None => ()
+        }
+        for child in commit.children.iter() {
+            self.get_mut(*child).parent = None;
+        }
+    }
+
+    // drop commits until the changes are back under budget
+    fn prune(&mut self) {
+        while self.changes > self.budget {
+            match self.victim() {
+                Some(id) => self.remove(id),
+                None => return
+            }
+        }
+    }
+
+    /// The game data at the current head.
+    pub fn head<'a>(&'a self) -> &'a GD {
+        &self.get(self.head).data
+    }
+
+    pub fn head_id(&self) -> CommitId { self.head }
+
+    pub fn tag<'a>(&'a self, id: CommitId) -> Option<&'a str> {
+        self.commits.find(&id).map(|c| c.tag.as_slice())
+    }
+
+    /// Move the head to its parent, returning the restored game data.
+    pub fn undo(&mut self) -> Option<GD> {
+        let head = self.head;
+        match self.get(head).parent {
+            Some(parent) => {
+                self.get_mut(parent).redo = Some(head);
+                self.head = parent;
+                Some(self.head().clone())
+            }
+            None => None
+        }
+    }
+
+    /// Move the head to the child that was last undone or committed.
+    pub fn redo(&mut self) -> Option<GD> {
+        match self.get(self.head).redo {
+            Some(child) => {
+                self.head = child;
+                Some(self.head().clone())
+            }
+            None => None
+        }
+    }
+
+    /// The commits that branch off from the current head, oldest first.
+    pub fn branches(&self) -> Vec<CommitId> {
+        self.get(self.head).children.clone()
+    }
+
+    /// Move the head to any commit that is still in the history.
+    pub fn checkout(&mut self, id: CommitId) -> Option<GD> {
+        if self.commits.find(&id).is_none() {
+            return None;
+        }
+
+        // keep redo pointing down the path we just took
+        let mut child = id;
+        loop {
+            let parent = match self.get(child).parent {
+                Some(parent) => parent,
+                None => break
+            };
+            self.get_mut(parent).redo = Some(child);
+            child = parent;
+        }
+
+        self.head = id;
+        Some(self.head().clone())
+    }
+
+    /// The commits from the head back to the oldest ancestor still held.
+    pub fn log(&self) -> Vec<CommitId> {
+        let mut out = vec!(self.head);
+        let mut id = self.head;
+        loop {
+            match self.get(id).parent {
+                Some(parent) => {
+                    out.push(parent);
+                    id = parent;
+                }
+                None => return out
+            }
+        }
+    }
+
+    pub fn len(&self) -> uint { self.commits.len() }
+
+    /// The changes held by the commits, counted against the budget.
+    pub fn changes(&self) -> uint { self.changes }
+}
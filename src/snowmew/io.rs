@@ -102,6 +102,25 @@ impl InputState {
         }
     }
 
+    /// An input state that is not attached to any window, used to feed
+    /// recorded or generated input into a game.
+    pub fn synthetic(framebuffer_size: (i32, i32), screen_size: (i32, i32)) -> InputState {
+        InputState {
+            history: None,
+            keyboard: HashSet::new(),
+            mouse: HashSet::new(),
+            should_close: false,
+            focus: true,
+            framebuffer_size: framebuffer_size,
+            screen_size: screen_size,
+            predicted: Quaternion::identity()
+        }
+    }
+
+    pub fn push_event(&mut self, time: Option<f64>, event: WindowEvent) {
+        self.event(time, event)
+    }
+
     fn event(&mut self, time: Option<f64>, event: WindowEvent) {
         self.history = Some(Arc::new( InputHistory{
             older: self.history.clone(),
@@ -136,6 +155,27 @@ impl InputState {
         self.mouse.contains(&button)
     }
 
+    /// The keys that are held down, in no particular order.
+    pub fn keys_down(&self) -> Vec<Key> {
+        self.keyboard.iter().map(|k| *k).collect()
+    }
+
+    /// The mouse buttons that are held down, in no particular order.
+    pub fn buttons_down(&self) -> Vec<MouseButton> {
+        self.mouse.iter().map(|b| *b).collect()
+    }
+
+    /// The last position the cursor was seen at.
+    pub fn cursor_pos(&self) -> Option<(f64, f64)> {
+        for (_, event) in self.iter() {
+            match event {
+                CursorPosEvent(x, y) => return Some((x, y)),
+                _ => ()
+            }
+        }
+        None
+    }
+
     pub fn time(&self) -> f64 {
         for (t, _) in self.iter() {
             match t {
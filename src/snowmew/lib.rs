@@ -23,15 +23,25 @@ extern crate libc;
 extern crate OpenCL;
 extern crate ovr = "oculus-vr";
 
-pub use common::{ObjectKey};
+pub use common::{ObjectKey, FrameInfo};
 
 use sync::Arc;
 use OpenCL::hl::{Device, get_platforms, GPU, CPU};
 use std::io::timer::Timer;
+use time::precise_time_s;
+use std::io::{File, BufferedWriter};
 
 pub mod common;
+pub mod component;
 pub mod camera;
 pub mod io;
+pub mod diff;
+pub mod event;
+pub mod history;
+pub mod manager;
+pub mod query;
+pub mod replay;
+pub mod snapshot;
 
 fn get_cl() -> Option<Arc<Device>> {
     let platforms = get_platforms();
@@ -118,20 +128,80 @@ impl DisplayConfig {
 
 pub trait Render<T> {
     fn update(&mut self, db: T, scene: ObjectKey, camera: ObjectKey);
+
+    /// Called with the last two simulated generations, `alpha` is how far
+    /// the current time is between `last` (0.) and `db` (1.). The default
+    /// ignores `last` and draws the newest generation.
+    fn update_interpolated(&mut self, _: T, db: T, _: f32, scene: ObjectKey, camera: ObjectKey) {
+        self.update(db, scene, camera)
+    }
 }
 
 pub trait RenderFactory<T, R: Render<T>> {
     fn init(self, window: io::Window, size: (i32, i32), cl: Option<Arc<Device>>) -> R;
 }
 
+/// A render that drops every frame, used when running without a display.
+pub struct NullRender;
+
+impl<T> Render<T> for NullRender {
+    fn update(&mut self, _: T, _: ObjectKey, _: ObjectKey) {}
+}
+
+/// Run the game closure without glfw or a window. Each tick takes the next
+/// `InputState` from `input`, the last one is repeated if `input` runs out.
+/// Every tick advances the simulation by exactly `timestep` seconds and runs
+/// `managers` after the game closure, like `start` does. Stops after `ticks`
+/// ticks or when the input requests the game to close, the final game data
+/// is returned.
+pub fn start_headless<GD: Clone + Send, R: Render<GD>, I: Iterator<io::InputState>>(
+        gd: GD, render: &mut R, managers: &mut manager::Scheduler<GD>,
+        ticks: uint, timestep: f64, input: I,
+        game: |GD, &FrameInfo, &io::InputState, &io::InputState| -> (GD, ObjectKey, ObjectKey)) -> GD {
+    let mut gd = gd;
+    let mut input = input;
+    let mut frame = FrameInfo::new(timestep);
+    let mut input_last = match input.next() {
+        Some(input) => input,
+        None => return gd
+    };
+
+    for _ in range(0, ticks) {
+        if input_last.should_close() {
+            break;
+        }
+        let input = match input.next() {
+            Some(input) => input,
+            None => input_last.clone()
+        };
+        let (new_gd, scene, camera) = game(gd, &frame, &input, &input_last);
+        let new_gd = managers.run(new_gd, &frame);
+        render.update(new_gd.clone(), scene, camera);
+        gd = new_gd;
+        input_last = input;
+        frame = frame.next();
+    }
+    gd
+}
+
 pub struct SnowmewConfig<GD, R> {
     pub display: DisplayConfig,
     pub use_opencl: bool,
+    /// how often the loop wakes up to poll input and render
     pub cadance_ms: u64,
-    pub render: Option<R>
+    /// the fixed step the game closure is run at
+    pub timestep_ms: u64,
+    /// the most simulation steps run in one wake up, any time beyond
+    /// that is dropped so that a slow frame can't snowball. Must be at
+    /// least 1, with 0 the game would never step.
+    pub max_catchup: uint,
+    pub render: Option<R>,
+    pub record: Option<Path>,
+    /// run after the game closure on every simulation step
+    pub managers: manager::Scheduler<GD>
 }
 
-impl<GD: Clone, R: Render<GD>, RF: RenderFactory<GD, R>> SnowmewConfig<GD, RF> {
+impl<GD: Clone + Send, R: Render<GD>, RF: RenderFactory<GD, R>> SnowmewConfig<GD, RF> {
     pub fn new() -> SnowmewConfig<GD, RF> {
         SnowmewConfig {
             display: DisplayConfig {
@@ -142,11 +212,16 @@ impl<GD: Clone, R: Render<GD>, RF: RenderFactory<GD, R>> SnowmewConfig<GD, RF> {
             },
             use_opencl: true,
             cadance_ms: 8,
-            render: None
+            timestep_ms: 8,
+            max_catchup: 5,
+            render: None,
+            record: None,
+            managers: manager::Scheduler::new()
         }
     }
 
-    pub fn start(self, gd: GD, game: |GD, &io::InputState, &io::InputState| -> (GD, ObjectKey, ObjectKey)) {
+    pub fn start(self, gd: GD, game: |GD, &FrameInfo, &io::InputState, &io::InputState| -> (GD, ObjectKey, ObjectKey)) {
+        assert!(self.max_catchup > 0, "max_catchup must be at least 1");
         let mut gd = gd;
         let mut im = io::IOManager::new(setup_glfw());
 
@@ -164,16 +239,67 @@ impl<GD: Clone, R: Render<GD>, RF: RenderFactory<GD, R>> SnowmewConfig<GD, RF> {
         let mut timer = Timer::new().unwrap();
         let timer_port = timer.periodic(self.cadance_ms);
 
+        let mut managers = self.managers;
+        let timestep = self.timestep_ms as f64 / 1000.;
         let mut input_last = im.get(&ih);
+        let mut recorder = match self.record {
+            Some(ref path) => {
+                let file = File::create(path).ok().expect("Could not create input recording");
+                Some(replay::Recorder::new(BufferedWriter::new(file), &input_last, timestep)
+                        .ok().expect("Could not write input recording"))
+            }
+            None => None
+        };
+
+        let mut frame = FrameInfo::new(timestep);
+        let mut last_gd = gd.clone();
+        let mut view = None;
+        let mut accumulator = 0.;
+        let mut last_time = precise_time_s();
+
         while !input_last.should_close() {
             timer_port.recv();
             im.poll();
             let input = im.get(&ih);
-            let (new_gd, scene, camera) = game(gd, &input, &input_last);
-            render.update(new_gd.clone(), scene, camera);
-            gd = new_gd;
-            input_last = input;
+
+            let now = precise_time_s();
+            accumulator += now - last_time;
+            last_time = now;
+
+            let mut steps = 0;
+            while accumulator >= timestep && steps < self.max_catchup {
+                match recorder {
+                    Some(ref mut r) => r.record(&input).ok().expect("Could not write input recording"),
+                    None => ()
+                }
+                let (new_gd, scene, camera) = game(gd.clone(), &frame, &input, &input_last);
+                let new_gd = managers.run(new_gd, &frame);
+                last_gd = gd;
+                gd = new_gd;
+                // blending across a change of scene or camera would show
+                // objects from the old view, cut to the new one instead
+                if view.map_or(false, |old| old != (scene, camera)) {
+                    last_gd = gd.clone();
+                }
+                view = Some((scene, camera));
+                input_last = input.clone();
+                frame = frame.next();
+                accumulator -= timestep;
+                steps += 1;
+            }
+
+            // we fell too far behind, drop the time we could not simulate
+            if steps == self.max_catchup {
+                accumulator = 0.;
+            }
+
+            match view {
+                Some((scene, camera)) => {
+                    let alpha = (accumulator / timestep) as f32;
+                    render.update_interpolated(last_gd.clone(), gd.clone(), alpha, scene, camera);
+                }
+                None => ()
+            }
         }
     }
 }
-
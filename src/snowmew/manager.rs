@@ -0,0 +1,189 @@
+//! Managers are the components that run alongside the game closure.
+//!
+//! Active managers write to the database and are grouped into stages. Every
+//! manager in a stage is handed the same generation and runs on its own task,
+//! their outputs are then merged into that generation in the order the
+//! managers were added. The next stage starts from the merged generation.
+//! Passive managers are handed the final generation of each frame and are
+//! never waited on. Only the latest generation is kept for them, a passive
+//! manager that falls behind skips the frames it missed instead of queueing
+//! them up.
+
+use std::comm::{Sender, Receiver, SyncSender, RecvDisconnected};
+use std::task;
+use sync::{Arc, Mutex};
+
+use common::FrameInfo;
+
+pub trait Manager<GD>: Send {
+    /// Run one frame against a stable generation. An active manager returns
+    /// the generation with its changes applied, a passive manager or an
+    /// active manager with nothing to write returns `None`.
+    fn step(&mut self, gd: GD, frame: &FrameInfo) -> Option<GD>;
+}
+
+/// Copies the data that an active manager owns from its output into the
+/// generation being built. This is where a manager declares which data
+/// blocks it writes, anything it does not copy is thrown away.
+pub type Merge<GD> = fn(GD, &GD) -> GD;
+
+struct Worker<GD> {
+    name: String,
+    send: Sender<(GD, FrameInfo)>,
+    recv: Receiver<Option<GD>>
+}
+
+impl<GD: Send> Worker<GD> {
+    fn spawn<M: Manager<GD>>(name: &str, manager: M) -> Worker<GD> {
+        let (send, task_recv) = channel::<(GD, FrameInfo)>();
+        let (task_send, recv) = channel();
+
+        let mut taskbuilder = task::TaskBuilder::new();
+        taskbuilder = taskbuilder.named(name.to_string().into_maybe_owned());
+        taskbuilder.spawn(proc() {
+            let mut manager = manager;
+            for (gd, frame) in task_recv.iter() {
+                task_send.send(manager.step(gd, &frame));
+            }
+        });
+
+        Worker {
+            name: name.to_string(),
+            send: send,
+            recv: recv
+        }
+    }
+
+    // a manager that failed takes its task down with it, report which one
+    // it was instead of a bare channel error
+    fn start(&self, gd: GD, frame: &FrameInfo) {
+        if self.send.send_opt((gd, frame.clone())).is_err() {
+            fail!("manager `{}` has failed", self.name);
+        }
+    }
+
+    fn finish(&self) -> Option<GD> {
+        match self.recv.recv_opt() {
+            Ok(out) => out,
+            Err(()) => fail!("manager `{}` has failed", self.name)
+        }
+    }
+}
+
+// a passive manager, the slot holds the newest generation it has not seen
+struct Passive<GD> {
+    name: String,
+    slot: Arc<Mutex<Option<(GD, FrameInfo)>>>,
+    wake: SyncSender<()>
+}
+
+impl<GD: Send> Passive<GD> {
+    fn spawn<M: Manager<GD>>(name: &str, manager: M) -> Passive<GD> {
+        let slot = Arc::new(Mutex::new(None));
+        let (wake, task_wake) = sync_channel(1);
+
+        let task_slot = slot.clone();
+        let mut taskbuilder = task::TaskBuilder::new();
+        taskbuilder = taskbuilder.named(name.to_string().into_maybe_owned());
+        taskbuilder.spawn(proc() {
+            let mut manager = manager;
+            for () in task_wake.iter() {
+                let next = task_slot.lock().take();
+                match next {
+                    Some((gd, frame)) => { manager.step(gd, &frame); }
+                    None => ()
+                }
+            }
+        });
+
+        Passive {
+            name: name.to_string(),
+            slot: slot,
+            wake: wake
+        }
+    }
+
+    // replace whatever the manager has not picked up yet, a wake up that is
+    // still pending will find the new generation
+    fn start(&self, gd: GD, frame: &FrameInfo) {
+        *self.slot.lock() = Some((gd, frame.clone()));
+        match self.wake.try_send(()) {
+            Err(RecvDisconnected(_)) => fail!("manager `{}` has failed", self.name),
+            _ => ()
+        }
+    }
+}
+
+struct Stage<GD> {
+    order: uint,
+    managers: Vec<(Worker<GD>, Merge<GD>)>
+}
+
+pub struct Scheduler<GD> {
+    stages: Vec<Stage<GD>>,
+    passive: Vec<Passive<GD>>
+}
+
+impl<GD: Send + Clone> Scheduler<GD> {
+    pub fn new() -> Scheduler<GD> {
+        Scheduler {
+            stages: Vec::new(),
+            passive: Vec::new()
+        }
+    }
+
+    /// Add an active manager to `stage`, stages are run from lowest to
+    /// highest. Within a stage the outputs are merged in the order the
+    /// managers were added.
+    pub fn add_active<M: Manager<GD>>(&mut self, name: &str, stage: uint, manager: M, merge: Merge<GD>) {
+        let worker = Worker::spawn(name, manager);
+
+        let idx = match self.stages.iter().position(|s| s.order >= stage) {
+            Some(idx) => idx,
+            None => self.stages.len()
+        };
+
+        if idx == self.stages.len() || self.stages.get(idx).order != stage {
+            self.stages.insert(idx, Stage {
+                order: stage,
+                managers: Vec::new()
+            });
+        }
+        self.stages.get_mut(idx).managers.push((worker, merge));
+    }
+
+    /// Add a passive manager, it sees the latest generation the scheduler
+    /// produced whenever it is ready for one but can not write to the
+    /// database.
+    pub fn add_passive<M: Manager<GD>>(&mut self, name: &str, manager: M) {
+        self.passive.push(Passive::spawn(name, manager));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.len() == 0 && self.passive.len() == 0
+    }
+
+    /// Run every manager for one frame, returns the merged generation.
+    pub fn run(&mut self, gd: GD, frame: &FrameInfo) -> GD {
+        let mut gd = gd;
+
+        for stage in self.stages.iter() {
+            for &(ref worker, _) in stage.managers.iter() {
+                worker.start(gd.clone(), frame);
+            }
+
+            for &(ref worker, merge) in stage.managers.iter() {
+                match worker.finish() {
+                    Some(out) => gd = merge(gd, &out),
+                    None => ()
+                }
+            }
+        }
+
+        for worker in self.passive.iter() {
+            worker.start(gd.clone(), frame);
+        }
+
+        gd
+    }
+}
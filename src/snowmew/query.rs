@@ -0,0 +1,260 @@
+//! Join queries over the maps of the database.
+//!
+//! Every data block stores its components in maps that are sorted by
+//! `ObjectKey`, so a query is a merge of sorted iterators. A query starts
+//! from one map and each step narrows it down, the item type grows with
+//! each joined map:
+//!
+//! ```ignore
+//! for (key, ((loc, vel), coll)) in query(db.location_iter())
+//!                                     .with(velocity.iter())
+//!                                     .with(colliders.iter())
+//!                                     .in_scene(db.get_common(), scene) {
+//!     ...
+//! }
+//! ```
+
+use std::iter::Peekable;
+
+use common::{ObjectKey, CommonData, Common, Layers, SceneIter};
+
+/// Start a query from an iterator sorted by key, such as a `BTreeMapIterator`.
+pub fn query<'a, V, I: Iterator<(&'a ObjectKey, V)>>(iter: I) -> Query<I> {
+    Query { iter: iter }
+}
+
+pub struct Query<I> {
+    iter: I
+}
+
+/// A slice of the results of a query that can be handed to a worker task.
+/// `offset` is the index of the first key in the full result.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Chunk {
+    pub offset: uint,
+    pub keys: Vec<ObjectKey>
+}
+
+impl<'a, V, I: Iterator<(&'a ObjectKey, V)>> Query<I> {
+    /// Only keys that are also in `other`, its value is added to the item.
+    pub fn with<W, J: Iterator<(&'a ObjectKey, W)>>(self, other: J) -> Query<With<'a, I, J, W>> {
+        Query { iter: With { a: self.iter, b: other.peekable() } }
+    }
+
+    /// Every key, with the value from `other` if it has one.
+    pub fn maybe<W, J: Iterator<(&'a ObjectKey, W)>>(self, other: J) -> Query<Maybe<'a, I, J, W>> {
+        Query { iter: Maybe { a: self.iter, b: other.peekable() } }
+    }
+
+    /// Only keys that are not in `other`.
+    pub fn without<W, J: Iterator<(&'a ObjectKey, W)>>(self, other: J) -> Query<Without<'a, I, J, W>> {
+        Query { iter: Without { a: self.iter, b: other.peekable() } }
+    }
+
+    /// Only keys that are in the sorted set `set`.
+    pub fn in_set<J: Iterator<&'a ObjectKey>>(self, set: J) -> Query<InSet<'a, I, J>> {
+        Query { iter: InSet { a: self.iter, b: set.peekable() } }
+    }
+
+    /// Only keys that are members of `scene`, or of a scene it includes.
+    pub fn in_scene(self, common: &'a CommonData, scene: ObjectKey)
+            -> Query<InSet<'a, I, SceneIter<'a>>> {
+        self.in_set(common.scene_iter(scene))
+    }
+
+    /// Only `root` and the keys below it.
+    pub fn in_subtree(self, common: &'a CommonData, root: ObjectKey) -> Query<InSubtree<'a, I>> {
+        Query {
+            iter: InSubtree {
+                a: self.iter,
+                common: common,
+                root: root
+            }
+        }
+    }
+
+    /// Only keys that are on any of the layers in `mask`.
+    pub fn in_layers(self, common: &'a CommonData, mask: Layers) -> Query<InLayers<'a, I>> {
+        Query {
+            iter: InLayers {
+                a: self.iter,
+                common: common,
+                mask: mask
+            }
+        }
+    }
+
+    /// Split the keys of the result into chunks of at most `size` keys.
+    pub fn chunks(self, size: uint) -> Vec<Chunk> {
+        assert!(size > 0);
+        let mut out: Vec<Chunk> = Vec::new();
+        for (idx, (key, _)) in self.iter.enumerate() {
+            if idx % size == 0 {
+                out.push(Chunk {
+                    offset: idx,
+                    keys: Vec::with_capacity(size)
+                });
+            }
+            let last = out.len() - 1;
+            out.get_mut(last).keys.push(*key);
+        }
+        out
+    }
+}
+
+impl<'a, V, I: Iterator<(&'a ObjectKey, V)>> Iterator<(&'a ObjectKey, V)> for Query<I> {
+    fn next(&mut self) -> Option<(&'a ObjectKey, V)> {
+        self.iter.next()
+    }
+}
+
+// Advance `b` until its key is at least `key`, returns true if it stopped
+// on `key`.
+fn seek<'a, W, J: Iterator<(&'a ObjectKey, W)>>(b: &mut Peekable<(&'a ObjectKey, W), J>, key: &ObjectKey) -> bool {
+    loop {
+        let order = match b.peek() {
+            Some(&(kb, _)) => kb.cmp(key),
+            None => return false
+        };
+        match order {
+            Less => { b.next(); }
+            Equal => return true,
+            Greater => return false
+        }
+    }
+}
+
+pub struct With<'a, I, J, W> {
+    a: I,
+    b: Peekable<(&'a ObjectKey, W), J>
+}
+
+impl<'a, V, W, I: Iterator<(&'a ObjectKey, V)>, J: Iterator<(&'a ObjectKey, W)>>
+        Iterator<(&'a ObjectKey, (V, W))> for With<'a, I, J, W> {
+    fn next(&mut self) -> Option<(&'a ObjectKey, (V, W))> {
+        loop {
+            let (key, v) = match self.a.next() {
+                Some(item) => item,
+                None => return None
+            };
+            if seek(&mut self.b, key) {
+                let (_, w) = self.b.next().unwrap();
+                return Some((key, (v, w)));
+            } else if self.b.is_empty() {
+                return None;
+            }
+        }
+    }
+}
+
+pub struct Maybe<'a, I, J, W> {
+    a: I,
+    b: Peekable<(&'a ObjectKey, W), J>
+}
+
+impl<'a, V, W, I: Iterator<(&'a ObjectKey, V)>, J: Iterator<(&'a ObjectKey, W)>>
+        Iterator<(&'a ObjectKey, (V, Option<W>))> for Maybe<'a, I, J, W> {
+    fn next(&mut self) -> Option<(&'a ObjectKey, (V, Option<W>))> {
+        match self.a.next() {
+            Some((key, v)) => {
+                if seek(&mut self.b, key) {
+                    let (_, w) = self.b.next().unwrap();
+                    Some((key, (v, Some(w))))
+                } else {
+                    Some((key, (v, None)))
+                }
+            }
+            None => None
+        }
+    }
+}
+
+pub struct Without<'a, I, J, W> {
+    a: I,
+    b: Peekable<(&'a ObjectKey, W), J>
+}
+
+impl<'a, V, W, I: Iterator<(&'a ObjectKey, V)>, J: Iterator<(&'a ObjectKey, W)>>
+        Iterator<(&'a ObjectKey, V)> for Without<'a, I, J, W> {
+    fn next(&mut self) -> Option<(&'a ObjectKey, V)> {
+        loop {
+            let (key, v) = match self.a.next() {
+                Some(item) => item,
+                None => return None
+            };
+            if !seek(&mut self.b, key) {
+                return Some((key, v));
+            }
+        }
+    }
+}
+
+pub struct InSet<'a, I, J> {
+    a: I,
+    b: Peekable<&'a ObjectKey, J>
+}
+
+impl<'a, V, I: Iterator<(&'a ObjectKey, V)>, J: Iterator<&'a ObjectKey>>
+        Iterator<(&'a ObjectKey, V)> for InSet<'a, I, J> {
+    fn next(&mut self) -> Option<(&'a ObjectKey, V)> {
+        loop {
+            let (key, v) = match self.a.next() {
+                Some(item) => item,
+                None => return None
+            };
+            loop {
+                let order = match self.b.peek() {
+                    Some(kb) => kb.cmp(&key),
+                    None => return None
+                };
+                match order {
+                    Less => { self.b.next(); }
+                    Equal => return Some((key, v)),
+                    Greater => break
+                }
+            }
+        }
+    }
+}
+
+pub struct InSubtree<'a, I> {
+    a: I,
+    common: &'a CommonData,
+    root: ObjectKey
+}
+
+impl<'a, V, I: Iterator<(&'a ObjectKey, V)>> Iterator<(&'a ObjectKey, V)> for InSubtree<'a, I> {
+    fn next(&mut self) -> Option<(&'a ObjectKey, V)> {
+        loop {
+            match self.a.next() {
+                Some((key, v)) => {
+                    if self.common.is_ancestor(self.root, *key) {
+                        return Some((key, v));
+                    }
+                }
+                None => return None
+            }
+        }
+    }
+}
+
+pub struct InLayers<'a, I> {
+    a: I,
+    common: &'a CommonData,
+    mask: Layers
+}
+
+impl<'a, V, I: Iterator<(&'a ObjectKey, V)>> Iterator<(&'a ObjectKey, V)> for InLayers<'a, I> {
+    fn next(&mut self) -> Option<(&'a ObjectKey, V)> {
+        loop {
+            match self.a.next() {
+                Some((key, v)) => {
+                    if self.common.in_layers(*key, self.mask) {
+                        return Some((key, v));
+                    }
+                }
+                None => return None
+            }
+        }
+    }
+}
@@ -0,0 +1,349 @@
+//! Record the input of a session and play it back without a window.
+//!
+//! A recording is the simulation timestep and the starting window and input
+//! state, the keys and buttons held and where the cursor was, followed by
+//! one frame per simulation step. Each frame holds the events
+//! that arrived since the previous step, so playing it back rebuilds the
+//! exact same sequence of `InputState`s that the game closure saw.
+
+use std::io::{IoResult, IoError, EndOfFile, MemWriter};
+use std::num::FromPrimitive;
+
+use glfw;
+use glfw::{WindowEvent, KeyEvent, MouseButtonEvent, CursorPosEvent};
+use glfw::{CloseEvent, FocusEvent, Press, Release, Repeat};
+
+use common::{ObjectKey, FrameInfo};
+use io::InputState;
+use manager::Scheduler;
+use snapshot;
+
+static MAGIC: u32 = 0x504c5052; // "RPLP"
+static VERSION: u32 = 3;
+
+fn write_action(w: &mut Writer, action: glfw::Action) -> IoResult<()> {
+    w.write_u8(match action {
+        Release => 0,
+        Press => 1,
+        Repeat => 2
+    })
+}
+
+fn read_action(r: &mut Reader) -> IoResult<glfw::Action> {
+    match try!(r.read_u8()) {
+        0 => Ok(Release),
+        1 => Ok(Press),
+        2 => Ok(Repeat),
+        _ => Err(snapshot::invalid("unknown key action"))
+    }
+}
+
+fn read_modifiers(r: &mut Reader) -> IoResult<glfw::Modifiers> {
+    match glfw::Modifiers::from_bits(try!(r.read_le_i32())) {
+        Some(m) => Ok(m),
+        None => Err(snapshot::invalid("unknown key modifiers"))
+    }
+}
+
+/// Write a single event, returns false if the event is not one that
+/// affects an `InputState` and was skipped.
+fn write_event(w: &mut Writer, time: f64, event: &WindowEvent) -> IoResult<bool> {
+    match *event {
+        KeyEvent(key, scancode, action, mods) => {
+            try!(w.write_u8(0));
+            try!(w.write_le_f64(time));
+            try!(w.write_le_i32(key as i32));
+            try!(w.write_le_i32(scancode as i32));
+            try!(write_action(w, action));
+            try!(w.write_le_i32(mods.bits() as i32));
+        }
+        MouseButtonEvent(button, action, mods) => {
+            try!(w.write_u8(1));
+            try!(w.write_le_f64(time));
+            try!(w.write_le_i32(button as i32));
+            try!(write_action(w, action));
+            try!(w.write_le_i32(mods.bits() as i32));
+        }
+        CursorPosEvent(x, y) => {
+            try!(w.write_u8(2));
+            try!(w.write_le_f64(time));
+            try!(w.write_le_f64(x));
+            try!(w.write_le_f64(y));
+        }
+        CloseEvent => {
+            try!(w.write_u8(3));
+            try!(w.write_le_f64(time));
+        }
+        FocusEvent(focus) => {
+            try!(w.write_u8(4));
+            try!(w.write_le_f64(time));
+            try!(snapshot::write_bool(w, focus));
+        }
+        _ => return Ok(false)
+    }
+    Ok(true)
+}
+
+fn read_event(r: &mut Reader) -> IoResult<(f64, WindowEvent)> {
+    let kind = try!(r.read_u8());
+    let time = try!(r.read_le_f64());
+    let event = match kind {
+        0 => {
+            let key = match FromPrimitive::from_i32(try!(r.read_le_i32())) {
+                Some(key) => key,
+                None => return Err(snapshot::invalid("unknown key"))
+            };
+            let scancode = try!(r.read_le_i32());
+            let action = try!(read_action(r));
+            KeyEvent(key, scancode, action, try!(read_modifiers(r)))
+        }
+        1 => {
+            let button = match FromPrimitive::from_i32(try!(r.read_le_i32())) {
+                Some(button) => button,
+                None => return Err(snapshot::invalid("unknown mouse button"))
+            };
+            let action = try!(read_action(r));
+            MouseButtonEvent(button, action, try!(read_modifiers(r)))
+        }
+        2 => {
+            let x = try!(r.read_le_f64());
+            CursorPosEvent(x, try!(r.read_le_f64()))
+        }
+        3 => CloseEvent,
+        4 => FocusEvent(try!(snapshot::read_bool(r))),
+        _ => return Err(snapshot::invalid("unknown input event"))
+    };
+    Ok((time, event))
+}
+
+// the input that is already held when the recording starts, sorted so
+// that the same state is always written the same way
+fn write_held(w: &mut Writer, input: &InputState) -> IoResult<()> {
+    try!(w.write_le_f64(input.time()));
+
+    let mut keys: Vec<i32> = input.keys_down().iter().map(|k| *k as i32).collect();
+    keys.sort();
+    try!(w.write_le_u32(keys.len() as u32));
+    for k in keys.iter() {
+        try!(w.write_le_i32(*k));
+    }
+
+    let mut buttons: Vec<i32> = input.buttons_down().iter().map(|b| *b as i32).collect();
+    buttons.sort();
+    try!(w.write_le_u32(buttons.len() as u32));
+    for b in buttons.iter() {
+        try!(w.write_le_i32(*b));
+    }
+
+    match input.cursor_pos() {
+        Some((x, y)) => {
+            try!(snapshot::write_bool(w, true));
+            try!(w.write_le_f64(x));
+            w.write_le_f64(y)
+        }
+        None => snapshot::write_bool(w, false)
+    }
+}
+
+// replay the input written by `write_held` into `state`, at the time of
+// the last event before the recording started
+fn read_held(r: &mut Reader, state: &mut InputState) -> IoResult<()> {
+    let time = Some(try!(r.read_le_f64()));
+
+    for _ in range(0, try!(r.read_le_u32())) {
+        let key = match FromPrimitive::from_i32(try!(r.read_le_i32())) {
+            Some(key) => key,
+            None => return Err(snapshot::invalid("unknown key"))
+        };
+        state.push_event(time, KeyEvent(key, 0, Press, glfw::Modifiers::empty()));
+    }
+
+    for _ in range(0, try!(r.read_le_u32())) {
+        let button = match FromPrimitive::from_i32(try!(r.read_le_i32())) {
+            Some(button) => button,
+            None => return Err(snapshot::invalid("unknown mouse button"))
+        };
+        state.push_event(time, MouseButtonEvent(button, Press, glfw::Modifiers::empty()));
+    }
+
+    if try!(snapshot::read_bool(r)) {
+        let x = try!(r.read_le_f64());
+        state.push_event(time, CursorPosEvent(x, try!(r.read_le_f64())));
+    }
+    Ok(())
+}
+
+/// Writes a frame to the recording for each `InputState` it is given.
+pub struct Recorder<W> {
+    writer: W,
+    last: InputState
+}
+
+impl<W: Writer> Recorder<W> {
+    pub fn new(writer: W, initial: &InputState, timestep: f64) -> IoResult<Recorder<W>> {
+        let mut writer = writer;
+        try!(writer.write_le_u32(MAGIC));
+        try!(writer.write_le_u32(VERSION));
+        try!(writer.write_le_f64(timestep));
+
+        let (fw, fh) = initial.framebuffer_size();
+        let (sw, sh) = initial.screen_size();
+        try!(writer.write_le_i32(fw));
+        try!(writer.write_le_i32(fh));
+        try!(writer.write_le_i32(sw));
+        try!(writer.write_le_i32(sh));
+        try!(snapshot::write_bool(&mut writer, initial.is_focused()));
+        try!(write_held(&mut writer, initial));
+
+        Ok(Recorder {
+            writer: writer,
+            last: initial.clone()
+        })
+    }
+
+    pub fn record(&mut self, input: &InputState) -> IoResult<()> {
+        // the delta iterator runs from newest to oldest
+        let mut events: Vec<(Option<f64>, WindowEvent)> = input.iter_delta(&self.last).collect();
+        events.reverse();
+
+        let mut buf = MemWriter::new();
+        let mut count = 0u32;
+        for &(time, ref event) in events.iter() {
+            let time = match time {
+                Some(time) => time,
+                None => continue
+            };
+            if try!(write_event(&mut buf, time, event)) {
+                count += 1;
+            }
+        }
+
+        try!(self.writer.write_le_u32(count));
+        try!(self.writer.write(buf.get_ref()));
+        self.last = input.clone();
+        Ok(())
+    }
+
+    pub fn unwrap(self) -> W { self.writer }
+}
+
+/// Rebuilds the recorded `InputState`s, one per recorded frame. As an
+/// iterator it stops at the first frame that can not be read, `error`
+/// tells a damaged recording apart from one that simply ended.
+pub struct Replay<R> {
+    reader: R,
+    state: InputState,
+    timestep: f64,
+    error: Option<IoError>
+}
+
+impl<R: Reader> Replay<R> {
+    pub fn new(reader: R) -> IoResult<Replay<R>> {
+        let mut reader = reader;
+        if try!(reader.read_le_u32()) != MAGIC {
+            return Err(snapshot::invalid("not a snowmew input recording"));
+        }
+        let version = try!(reader.read_le_u32());
+        if version > VERSION {
+            return Err(snapshot::invalid("recording version is newer than this build"));
+        }
+        // version 1 recorded a frame per tick of the main loop and had no
+        // timestep, its frames can not be lined up with simulation steps
+        if version < 2 {
+            return Err(snapshot::invalid("recording was made before the fixed timestep"));
+        }
+        let timestep = try!(reader.read_le_f64());
+
+        let fw = try!(reader.read_le_i32());
+        let fh = try!(reader.read_le_i32());
+        let sw = try!(reader.read_le_i32());
+        let sh = try!(reader.read_le_i32());
+        let focus = try!(snapshot::read_bool(&mut reader));
+
+        let mut state = InputState::synthetic((fw, fh), (sw, sh));
+        if !focus {
+            state.push_event(None, FocusEvent(false));
+        }
+        // version 2 did not keep the input held at the start
+        if version >= 3 {
+            try!(read_held(&mut reader, &mut state));
+        }
+
+        Ok(Replay {
+            reader: reader,
+            state: state,
+            timestep: timestep,
+            error: None
+        })
+    }
+
+    /// The state the recording started from.
+    pub fn initial(&self) -> InputState {
+        self.state.clone()
+    }
+
+    /// The simulation step in seconds that the recording was made at.
+    pub fn timestep(&self) -> f64 {
+        self.timestep
+    }
+
+    /// The error that stopped the iterator, if it did not reach the end
+    /// of the recording.
+    pub fn error<'a>(&'a self) -> Option<&'a IoError> {
+        self.error.as_ref()
+    }
+
+    /// Read the next frame, returns `None` at the end of the recording.
+    pub fn next_frame(&mut self) -> IoResult<Option<InputState>> {
+        let count = match self.reader.read_le_u32() {
+            Ok(count) => count,
+            Err(ref e) if e.kind == EndOfFile => return Ok(None),
+            Err(e) => return Err(e)
+        };
+
+        for _ in range(0, count) {
+            let (time, event) = try!(read_event(&mut self.reader));
+            self.state.push_event(Some(time), event);
+        }
+        Ok(Some(self.state.clone()))
+    }
+}
+
+impl<R: Reader> Iterator<InputState> for Replay<R> {
+    fn next(&mut self) -> Option<InputState> {
+        if self.error.is_some() {
+            return None;
+        }
+        match self.next_frame() {
+            Ok(frame) => frame,
+            Err(e) => {
+                self.error = Some(e);
+                None
+            }
+        }
+    }
+}
+
+/// Drive a game closure from a recording instead of a window. The closure
+/// sees the same frames and `(current, last)` pairs that it saw when the
+/// recording was made, `managers` are run after it as they were in the live
+/// session. The final game data is returned, or the error that stopped the
+/// replay if the recording is damaged.
+pub fn replay<GD: Clone + Send, R: Reader>(gd: GD, frames: Replay<R>, managers: &mut Scheduler<GD>,
+                                           game: |GD, &FrameInfo, &InputState, &InputState| -> (GD, ObjectKey, ObjectKey)) -> IoResult<GD> {
+    let mut frames = frames;
+    let mut gd = gd;
+    let mut input_last = frames.initial();
+    let mut frame = FrameInfo::new(frames.timestep());
+
+    loop {
+        let input = match try!(frames.next_frame()) {
+            Some(input) => input,
+            None => return Ok(gd)
+        };
+        let (new_gd, _, _) = game(gd, &frame, &input, &input_last);
+        gd = managers.run(new_gd, &frame);
+        input_last = input;
+        frame = frame.next();
+    }
+}
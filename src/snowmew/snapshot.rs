@@ -0,0 +1,285 @@
+//! A versioned binary snapshot of the database.
+//!
+//! A snapshot is a header followed by one block per data block of the
+//! game struct. Each block starts with its tag so that a reader can check
+//! that the blocks are being read back in the order they were written.
+//!
+//! Snapshots written by an older `VERSION` are still read, a block whose
+//! layout changed reads the old layout in `read_version` and leaves what
+//! was added since at its default.
+
+use std::io::{IoError, IoResult, InvalidInput};
+
+use cgmath::vector::{Vector2, Vector3, Vector4};
+use cgmath::matrix::Matrix4;
+use cgmath::point::Point3;
+use cgmath::quaternion::Quaternion;
+use cgmath::transform::Decomposed;
+
+use cow::btree::BTreeMap;
+
+use common::ObjectKey;
+
+static MAGIC: u32 = 0x574f4e53; // "SNOW"
+pub static VERSION: u32 = 4;
+/// The first version that is still read.
+pub static OLDEST_VERSION: u32 = 1;
+
+pub static TAG_COMMON: u32 = 0x4e4d4f43;   // "COMN"
+pub static TAG_POSITION: u32 = 0x534f5050; // "PPOS"
+pub static TAG_GRAPHICS: u32 = 0x53465247; // "GRFS"
+pub static TAG_PHYSICS: u32 = 0x53594850;  // "PHYS"
+
+pub trait Snapshot {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()>;
+    fn read_from(r: &mut Reader) -> IoResult<Self>;
+
+    /// Read a value written with `version`, see `read_header`. Only types
+    /// whose layout changed between versions need to override this.
+    fn read_version(r: &mut Reader, _: u32) -> IoResult<Self> {
+        Snapshot::read_from(r)
+    }
+}
+
+pub fn invalid(desc: &'static str) -> IoError {
+    IoError {
+        kind: InvalidInput,
+        desc: desc,
+        detail: None
+    }
+}
+
+pub fn write_header(w: &mut Writer) -> IoResult<()> {
+    try!(w.write_le_u32(MAGIC));
+    w.write_le_u32(VERSION)
+}
+
+/// Read the snapshot header, returning the version the file was written with.
+pub fn read_header(r: &mut Reader) -> IoResult<u32> {
+    if try!(r.read_le_u32()) != MAGIC {
+        return Err(invalid("not a snowmew snapshot"));
+    }
+
+    let version = try!(r.read_le_u32());
+    if version > VERSION {
+        return Err(invalid("snapshot version is newer than this build"));
+    }
+    // version 2 added object layers to the common block, version 3 added
+    // scene includes and the active scene, version 4 added skins to the
+    // graphics block
+    if version < OLDEST_VERSION {
+        return Err(invalid("snapshot version is too old to be read"));
+    }
+    Ok(version)
+}
+
+pub fn write_tag(w: &mut Writer, tag: u32) -> IoResult<()> {
+    w.write_le_u32(tag)
+}
+
+pub fn expect_tag(r: &mut Reader, tag: u32) -> IoResult<()> {
+    if try!(r.read_le_u32()) != tag {
+        Err(invalid("unexpected block in snapshot"))
+    } else {
+        Ok(())
+    }
+}
+
+pub fn write_string(w: &mut Writer, s: &str) -> IoResult<()> {
+    try!(w.write_le_u32(s.len() as u32));
+    w.write_str(s)
+}
+
+pub fn read_string(r: &mut Reader) -> IoResult<String> {
+    let len = try!(r.read_le_u32());
+    let bytes = try!(r.read_exact(len as uint));
+    match String::from_utf8(bytes) {
+        Ok(s) => Ok(s),
+        Err(_) => Err(invalid("string is not valid utf-8"))
+    }
+}
+
+/// Write a map keyed by object, in key order.
+pub fn write_map<T: Snapshot>(w: &mut Writer, map: &BTreeMap<ObjectKey, T>) -> IoResult<()> {
+    try!(w.write_le_u32(map.len() as u32));
+    for (oid, v) in map.iter() {
+        try!(w.write_le_u32(*oid));
+        try!(v.write_to(w));
+    }
+    Ok(())
+}
+
+pub fn read_map<T: Snapshot>(r: &mut Reader) -> IoResult<BTreeMap<ObjectKey, T>> {
+    let mut map = BTreeMap::new();
+    for _ in range(0, try!(r.read_le_u32())) {
+        let oid = try!(r.read_le_u32());
+        map.insert(oid, try!(Snapshot::read_from(r)));
+    }
+    Ok(map)
+}
+
+pub fn write_bool(w: &mut Writer, b: bool) -> IoResult<()> {
+    w.write_u8(if b { 1 } else { 0 })
+}
+
+pub fn read_bool(r: &mut Reader) -> IoResult<bool> {
+    Ok(try!(r.read_u8()) != 0)
+}
+
+impl Snapshot for u32 {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()> { w.write_le_u32(*self) }
+    fn read_from(r: &mut Reader) -> IoResult<u32> { r.read_le_u32() }
+}
+
+impl Snapshot for f32 {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()> { w.write_le_f32(*self) }
+    fn read_from(r: &mut Reader) -> IoResult<f32> { r.read_le_f32() }
+}
+
+impl<T: Snapshot> Snapshot for Option<T> {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()> {
+        match *self {
+            Some(ref v) => {
+                try!(write_bool(w, true));
+                v.write_to(w)
+            }
+            None => write_bool(w, false)
+        }
+    }
+
+    fn read_from(r: &mut Reader) -> IoResult<Option<T>> {
+        if try!(read_bool(r)) {
+            Ok(Some(try!(Snapshot::read_from(r))))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl<T: Snapshot> Snapshot for Vec<T> {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()> {
+        try!(w.write_le_u32(self.len() as u32));
+        for v in self.iter() {
+            try!(v.write_to(w));
+        }
+        Ok(())
+    }
+
+    fn read_from(r: &mut Reader) -> IoResult<Vec<T>> {
+        let len = try!(r.read_le_u32()) as uint;
+        let mut out = Vec::with_capacity(len);
+        for _ in range(0, len) {
+            out.push(try!(Snapshot::read_from(r)));
+        }
+        Ok(out)
+    }
+}
+
+impl Snapshot for Vector2<f32> {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()> {
+        try!(w.write_le_f32(self.x));
+        w.write_le_f32(self.y)
+    }
+
+    fn read_from(r: &mut Reader) -> IoResult<Vector2<f32>> {
+        let x = try!(r.read_le_f32());
+        let y = try!(r.read_le_f32());
+        Ok(Vector2::new(x, y))
+    }
+}
+
+impl Snapshot for Vector3<f32> {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()> {
+        try!(w.write_le_f32(self.x));
+        try!(w.write_le_f32(self.y));
+        w.write_le_f32(self.z)
+    }
+
+    fn read_from(r: &mut Reader) -> IoResult<Vector3<f32>> {
+        let x = try!(r.read_le_f32());
+        let y = try!(r.read_le_f32());
+        let z = try!(r.read_le_f32());
+        Ok(Vector3::new(x, y, z))
+    }
+}
+
+impl Snapshot for Vector4<f32> {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()> {
+        try!(w.write_le_f32(self.x));
+        try!(w.write_le_f32(self.y));
+        try!(w.write_le_f32(self.z));
+        w.write_le_f32(self.w)
+    }
+
+    fn read_from(r: &mut Reader) -> IoResult<Vector4<f32>> {
+        let x = try!(r.read_le_f32());
+        let y = try!(r.read_le_f32());
+        let z = try!(r.read_le_f32());
+        let w = try!(r.read_le_f32());
+        Ok(Vector4::new(x, y, z, w))
+    }
+}
+
+impl Snapshot for Matrix4<f32> {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()> {
+        try!(self.x.write_to(w));
+        try!(self.y.write_to(w));
+        try!(self.z.write_to(w));
+        self.w.write_to(w)
+    }
+
+    fn read_from(r: &mut Reader) -> IoResult<Matrix4<f32>> {
+        let x = try!(Snapshot::read_from(r));
+        let y = try!(Snapshot::read_from(r));
+        let z = try!(Snapshot::read_from(r));
+        let w = try!(Snapshot::read_from(r));
+        Ok(Matrix4::from_cols(x, y, z, w))
+    }
+}
+
+impl Snapshot for Point3<f32> {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()> {
+        try!(w.write_le_f32(self.x));
+        try!(w.write_le_f32(self.y));
+        w.write_le_f32(self.z)
+    }
+
+    fn read_from(r: &mut Reader) -> IoResult<Point3<f32>> {
+        let x = try!(r.read_le_f32());
+        let y = try!(r.read_le_f32());
+        let z = try!(r.read_le_f32());
+        Ok(Point3::new(x, y, z))
+    }
+}
+
+impl Snapshot for Quaternion<f32> {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()> {
+        try!(w.write_le_f32(self.s));
+        self.v.write_to(w)
+    }
+
+    fn read_from(r: &mut Reader) -> IoResult<Quaternion<f32>> {
+        let s = try!(r.read_le_f32());
+        let v: Vector3<f32> = try!(Snapshot::read_from(r));
+        Ok(Quaternion::from_sv(s, v))
+    }
+}
+
+impl Snapshot for Decomposed<f32, Vector3<f32>, Quaternion<f32>> {
+    fn write_to(&self, w: &mut Writer) -> IoResult<()> {
+        try!(w.write_le_f32(self.scale));
+        try!(self.rot.write_to(w));
+        self.disp.write_to(w)
+    }
+
+    fn read_from(r: &mut Reader) -> IoResult<Decomposed<f32, Vector3<f32>, Quaternion<f32>>> {
+        let scale = try!(r.read_le_f32());
+        let rot = try!(Snapshot::read_from(r));
+        let disp = try!(Snapshot::read_from(r));
+        Ok(Decomposed {
+            scale: scale,
+            rot: rot,
+            disp: disp
+        })
+    }
+}
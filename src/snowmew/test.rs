@@ -1,10 +1,19 @@
 #![feature(globs)]
+#![feature(phase)]
 
+#[phase(plugin, link)]
 extern crate snowmew;
 extern crate cow;
+extern crate glfw;
 
 mod core {
-    use snowmew::common::{CommonData, Common};
+    use std::io::{MemWriter, MemReader};
+
+    use snowmew::common::{CommonData, Common, ObjectKey, glob_match};
+    use snowmew::common::{DEFAULT_LAYER, ALL_LAYERS, StringStats};
+    use snowmew::snapshot;
+    use snowmew::snapshot::Snapshot;
+    use snowmew::history::History;
 
     #[test]
     fn db_new_object() {
@@ -14,4 +23,1075 @@ mod core {
 
         assert!(db.find("main").unwrap() == id);
     }
-}
\ No newline at end of file
+
+    fn tree() -> (CommonData, Vec<ObjectKey>) {
+        let mut db = CommonData::new();
+        let import = db.new_object(None, "import");
+        let objects = db.new_object(Some(import), "objects");
+        let lamp = db.new_object(Some(objects), "lamp");
+        let lamp_post = db.new_object(Some(objects), "lamp_post");
+        let chair = db.new_object(Some(objects), "chair");
+        let lamp2 = db.new_object(Some(chair), "lamp2");
+        (db, vec!(import, objects, lamp, lamp_post, chair, lamp2))
+    }
+
+    #[test]
+    fn db_glob() {
+        let (db, keys) = tree();
+        let (objects, lamp, lamp_post, chair, lamp2) =
+            (*keys.get(1), *keys.get(2), *keys.get(3), *keys.get(4), *keys.get(5));
+
+        let all: Vec<ObjectKey> = db.glob("import/objects/*").collect();
+        assert!(all == vec!(lamp, lamp_post, chair));
+
+        let lamps: Vec<ObjectKey> = db.glob("**/lamp*").collect();
+        assert!(lamps == vec!(lamp, lamp_post, lamp2));
+
+        let short: Vec<ObjectKey> = db.glob("import/*/lamp?").collect();
+        assert!(short.len() == 0);
+
+        let rel: Vec<ObjectKey> = db.glob_from(chair, "../lamp*").collect();
+        assert!(rel == vec!(lamp, lamp_post));
+        assert!(db.glob("nothing/*").next().is_none());
+        assert!(db.glob_from(objects, "**").count() == 5);
+    }
+
+    #[test]
+    fn db_find_relative() {
+        let (db, keys) = tree();
+        let (import, objects, lamp, chair, lamp2) =
+            (*keys.get(0), *keys.get(1), *keys.get(2), *keys.get(4), *keys.get(5));
+
+        assert!(db.find_from(chair, "lamp2") == Some(lamp2));
+        assert!(db.find_from(chair, "../lamp") == Some(lamp));
+        assert!(db.find_from(lamp2, "../../..") == Some(import));
+        assert!(db.find_from(lamp2, "./../.") == Some(chair));
+        assert!(db.find_from(lamp2, "/import/objects") == Some(objects));
+        assert!(db.find_from(import, "..").is_none());
+        assert!(db.find_from(import, "objects/*").is_none());
+    }
+
+    #[test]
+    fn db_full_path() {
+        let (db, keys) = tree();
+        for key in keys.iter() {
+            let path = db.full_path(*key).unwrap();
+            assert!(db.find(path.as_slice()) == Some(*key));
+        }
+        assert!(db.full_path(*keys.get(5)) == Some("import/objects/chair/lamp2".to_string()));
+        assert!(db.full_path(1000).is_none());
+    }
+
+    #[test]
+    fn db_glob_match() {
+        assert!(glob_match("lamp*", "lamp"));
+        assert!(glob_match("lamp*", "lamp_post"));
+        assert!(glob_match("*post", "lamp_post"));
+        assert!(glob_match("l?mp", "lamp"));
+        assert!(glob_match("*a*p*", "lamp_post"));
+        assert!(!glob_match("lamp?", "lamp"));
+        assert!(!glob_match("*x*", "lamp"));
+    }
+
+    #[test]
+    fn db_delete_object() {
+        let mut db = CommonData::new();
+
+        let main = db.new_object(None, "main");
+        let child = db.new_object(Some(main), "child");
+
+        assert!(!db.delete_object(main));
+        assert!(db.delete_object(child));
+        assert!(db.find("main/child").is_none());
+        assert!(db.object(child).is_none());
+        assert!(db.find("main").unwrap() == main);
+    }
+
+    #[test]
+    fn db_instantiate() {
+        let mut db = CommonData::new();
+        let scene = db.new_scene("scene");
+        let lamp = db.new_object(None, "lamp");
+        let shade = db.new_object(Some(lamp), "shade");
+        let bulb = db.new_object(Some(shade), "bulb");
+        db.set_layers(bulb, 0x2);
+
+        let remap = db.instantiate(lamp, Some(scene), "lamp2").unwrap();
+        let root = remap.root();
+        assert!(remap.len() == 3);
+        assert!(remap.get(lamp) == Some(root));
+        assert!(db.find("scene/lamp2").unwrap() == root);
+
+        let bulb2 = db.find("scene/lamp2/shade/bulb").unwrap();
+        assert!(remap.get(bulb) == Some(bulb2));
+        assert!(bulb2 != bulb);
+        assert!(db.layers(bulb2) == 0x2);
+        assert!(db.scene_iter(scene).any(|k| *k == bulb2));
+
+        // keys outside of the prefab are left alone
+        assert!(remap.remap(scene) == scene);
+
+        assert!(db.find("lamp/shade/bulb").unwrap() == bulb);
+        assert!(db.instantiate(lamp, Some(scene), "lamp2").is_none());
+        assert!(db.instantiate(lamp, Some(shade), "lamp3").is_none());
+    }
+
+    #[test]
+    fn db_delete_subtree() {
+        let mut db = CommonData::new();
+
+        let scene = db.new_scene("scene");
+        let a = db.new_object(Some(scene), "a");
+        let b = db.new_object(Some(a), "b");
+
+        let removed = db.delete_subtree(a);
+        assert!(removed == vec!(b, a));
+        assert!(db.find("scene/a").is_none());
+        assert!(db.scene_iter(scene).next().is_none());
+        assert!(db.find("scene").unwrap() == scene);
+    }
+
+    #[test]
+    fn db_set_parent() {
+        let mut db = CommonData::new();
+
+        let scene_a = db.new_scene("a");
+        let scene_b = db.new_scene("b");
+        let obj = db.new_object(Some(scene_a), "obj");
+        let child = db.new_object(Some(obj), "child");
+
+        assert!(db.set_parent(obj, Some(scene_b)));
+        assert!(db.find("a/obj").is_none());
+        assert!(db.find("b/obj/child").unwrap() == child);
+        assert!(db.scene_iter(scene_a).next().is_none());
+        assert!(db.scene_iter(scene_b).map(|k| *k).collect::<Vec<u32>>() == vec!(obj, child));
+
+        // can not become a child of itself
+        assert!(!db.set_parent(obj, Some(child)));
+    }
+
+    #[test]
+    fn db_scene_includes() {
+        let mut db = CommonData::new();
+        let level = db.new_scene("level");
+        let ui = db.new_scene("ui");
+        let rock = db.new_object(Some(level), "rock");
+        let button = db.new_object(Some(ui), "button");
+        let loose = db.new_object(None, "loose");
+
+        assert!(db.include_scene(level, ui));
+        assert!(db.scene_iter(level).map(|k| *k).collect::<Vec<u32>>() == vec!(rock, button));
+        assert!(db.scene_iter(ui).map(|k| *k).collect::<Vec<u32>>() == vec!(button));
+        assert!(db.scene_contains(level, button));
+        assert!(!db.scene_contains(ui, rock));
+
+        // cycles are harmless, every member is seen once
+        assert!(db.include_scene(ui, level));
+        assert!(db.scene_iter(ui).map(|k| *k).collect::<Vec<u32>>() == vec!(rock, button));
+        db.exclude_scene(ui, level);
+
+        assert!(!db.include_scene(level, level));
+        assert!(!db.include_scene(level, loose));
+        assert!(db.scene_iter(loose).next().is_none());
+
+        db.delete_subtree(ui);
+        assert!(db.scene_iter(level).map(|k| *k).collect::<Vec<u32>>() == vec!(rock));
+    }
+
+    #[test]
+    fn db_scene_load() {
+        let mut db = CommonData::new();
+        let prefab = db.new_object(None, "level1");
+        let rock = db.new_object(Some(prefab), "rock");
+        db.new_object(Some(rock), "moss");
+
+        let remap = db.load_scene(prefab, "loaded").unwrap();
+        let scene = remap.root();
+        assert!(db.is_scene(scene));
+        assert!(!db.is_scene(prefab));
+        let members: Vec<u32> = db.scene_iter(scene).map(|k| *k).collect();
+        assert!(members == vec!(remap.get(rock).unwrap(), db.find("loaded/rock/moss").unwrap()));
+
+        assert!(db.activate_scene("level1").is_none());
+        assert!(db.activate_scene("loaded") == Some(scene));
+        assert!(db.active_scene() == Some(scene));
+
+        let removed = db.unload_scene(scene);
+        assert!(removed.len() == 3);
+        assert!(db.active_scene() == None);
+        assert!(db.scene_iter(scene).next().is_none());
+        assert!(db.unload_scene(prefab).len() == 0);
+    }
+
+    #[test]
+    fn db_rename() {
+        let mut db = CommonData::new();
+
+        let main = db.new_object(None, "main");
+        let a = db.new_object(Some(main), "a");
+        db.new_object(Some(main), "b");
+
+        assert!(!db.rename(a, "b"));
+        assert!(db.rename(a, "c"));
+        assert!(db.find("main/a").is_none());
+        assert!(db.find("main/c").unwrap() == a);
+    }
+
+    #[test]
+    fn db_same_name_siblings() {
+        let mut db = CommonData::new();
+
+        let main = db.new_object(None, "main");
+        let other = db.new_object(None, "other");
+        let a = db.new_object(Some(main), "a");
+        let b = db.new_object(Some(main), "a");
+        let c = db.new_object(Some(main), "a");
+        assert!(db.find("main/a").unwrap() == c);
+
+        // moving or renaming a shadowed sibling leaves the path to its owner
+        assert!(db.set_parent(a, Some(other)));
+        assert!(db.find("main/a").unwrap() == c);
+        assert!(db.rename(b, "b"));
+        assert!(db.find("main/a").unwrap() == c);
+        assert!(db.find("main/b").unwrap() == b);
+        assert!(db.find("other/a").unwrap() == a);
+    }
+
+    #[test]
+    fn db_layers() {
+        let mut db = CommonData::new();
+        let a = db.new_object(None, "a");
+        let b = db.new_object(None, "b");
+
+        assert!(db.layers(a) == DEFAULT_LAYER);
+        db.add_layers(a, 0x4);
+        assert!(db.layers(a) == DEFAULT_LAYER | 0x4);
+        assert!(db.in_layers(a, 0x4));
+        assert!(!db.in_layers(b, 0x4));
+        assert!(db.in_layers(b, ALL_LAYERS));
+
+        db.remove_layers(a, DEFAULT_LAYER);
+        assert!(db.layers(a) == 0x4);
+        assert!(!db.in_layers(a, DEFAULT_LAYER));
+        assert!(db.layer_iter().map(|(k, l)| (*k, *l)).collect::<Vec<(u32, u32)>>() == vec!((a, 0x4)));
+
+        // back on the default layer alone, nothing needs to be stored
+        db.set_layers(a, DEFAULT_LAYER);
+        assert!(db.layer_iter().count() == 0);
+
+        db.set_layers(b, 0);
+        assert!(!db.in_layers(b, ALL_LAYERS));
+        db.delete_object(b);
+        assert!(db.layer_iter().count() == 0);
+    }
+
+    #[test]
+    fn db_string_table() {
+        let mut db = CommonData::new();
+        let a = db.new_object(None, "a");
+        let b = db.new_object(Some(a), "b");
+        let c = db.new_object(Some(b), "a");
+        assert!(db.string_stats() == StringStats { count: 2, bytes: 2 });
+
+        // "a" is still used by `c`
+        assert!(db.rename(a, "root"));
+        assert!(db.string_stats() == StringStats { count: 3, bytes: 6 });
+
+        db.delete_object(c);
+        assert!(db.string_stats() == StringStats { count: 2, bytes: 5 });
+        assert!(db.find("root/b").unwrap() == b);
+
+        db.delete_subtree(a);
+        assert!(db.string_stats() == StringStats { count: 0, bytes: 0 });
+
+        let d = db.new_object(None, "a");
+        assert!(db.find("a").unwrap() == d);
+    }
+
+    #[test]
+    fn db_snapshot() {
+        let mut db = CommonData::new();
+
+        let scene = db.new_scene("scene");
+        let a = db.new_object(Some(scene), "a");
+        let b = db.new_object(Some(a), "b");
+        db.set_layers(b, 0x2);
+        let ui = db.new_scene("ui");
+        db.include_scene(scene, ui);
+        db.activate_scene("scene");
+
+        let mut w = MemWriter::new();
+        snapshot::write_header(&mut w).unwrap();
+        db.write_to(&mut w).unwrap();
+
+        let mut r = MemReader::new(w.unwrap());
+        assert!(snapshot::read_header(&mut r).unwrap() == snapshot::VERSION);
+        let mut db: CommonData = Snapshot::read_from(&mut r).unwrap();
+
+        assert!(db.find("scene/a/b").unwrap() == b);
+        assert!(db.layers(b) == 0x2);
+        assert!(db.string_stats() == StringStats { count: 4, bytes: 9 });
+        assert!(db.active_scene() == Some(scene));
+        assert!(db.scene_iter(scene).map(|k| *k).collect::<Vec<u32>>() == vec!(a, b));
+        let ui_item = db.new_object(Some(ui), "item");
+        assert!(db.scene_contains(scene, ui_item));
+        let c = db.new_object(Some(a), "c");
+        assert!(c > b);
+    }
+
+    #[test]
+    fn db_snapshot_version_1() {
+        // a common block the way version 1 wrote it, it had no scene
+        // includes, no active scene and no layers
+        let mut w = MemWriter::new();
+        snapshot::write_header(&mut w).unwrap();
+        snapshot::write_tag(&mut w, snapshot::TAG_COMMON).unwrap();
+        w.write_le_u32(3).unwrap();
+        w.write_le_u32(2).unwrap();
+        w.write_le_u32(1).unwrap();
+        snapshot::write_string(&mut w, "scene").unwrap();
+        w.write_le_u32(2).unwrap();
+        snapshot::write_string(&mut w, "a").unwrap();
+        w.write_le_u32(3).unwrap();
+        w.write_le_u32(2).unwrap();
+        for &(oid, parent, name) in [(1u32, 0u32, 1u32), (2, 1, 2)].iter() {
+            w.write_le_u32(oid).unwrap();
+            w.write_le_u32(parent).unwrap();
+            w.write_le_u32(name).unwrap();
+        }
+        w.write_le_u32(1).unwrap();
+        w.write_le_u32(1).unwrap();
+
+        let mut data = w.unwrap();
+        // the version follows the magic
+        *data.get_mut(4) = 1;
+        let mut r = MemReader::new(data);
+        let version = snapshot::read_header(&mut r).unwrap();
+        assert!(version == 1);
+        let db: CommonData = Snapshot::read_version(&mut r, version).unwrap();
+
+        assert!(db.find("scene/a") == Some(2));
+        assert!(db.scene_contains(1, 2));
+        assert!(db.layers(2) == DEFAULT_LAYER);
+        assert!(db.active_scene().is_none());
+    }
+
+    #[test]
+    fn db_diff() {
+        let mut old = CommonData::new();
+        let a = old.new_object(None, "a");
+        let b = old.new_object(None, "b");
+
+        let mut new = old.clone();
+        new.delete_object(a);
+        new.rename(b, "c");
+        let d = new.new_object(None, "d");
+
+        let diff = CommonData::diff(&old, &new);
+        assert!(diff.added == vec!(d));
+        assert!(diff.removed == vec!(a));
+        assert!(diff.modified == vec!(b));
+        assert!(CommonData::diff(&new, &new).is_empty());
+    }
+
+    #[test]
+    fn history_undo_redo() {
+        let mut db = CommonData::new();
+        let mut history = History::new(db.clone(), 1 << 20);
+
+        let a = db.new_object(None, "a");
+        history.commit("add a", db.clone());
+        db.new_object(None, "b");
+        history.commit("add b", db.clone());
+
+        let db = history.undo().unwrap();
+        assert!(db.find("b").is_none());
+        assert!(db.find("a").unwrap() == a);
+
+        let db = history.undo().unwrap();
+        assert!(db.find("a").is_none());
+        assert!(history.undo().is_none());
+
+        history.redo().unwrap();
+        let db = history.redo().unwrap();
+        assert!(db.find("b").is_some());
+        assert!(history.redo().is_none());
+    }
+
+    #[test]
+    fn history_branch() {
+        let mut db = CommonData::new();
+        let mut history = History::new(db.clone(), 1 << 20);
+
+        db.new_object(None, "a");
+        let first = history.commit("add a", db.clone());
+
+        let mut db = history.undo().unwrap();
+        db.new_object(None, "b");
+        let second = history.commit("add b", db.clone());
+
+        history.undo();
+        assert!(history.branches() == vec!(first, second));
+        assert!(history.redo().unwrap().find("b").is_some());
+
+        let db = history.checkout(first).unwrap();
+        assert!(db.find("a").is_some());
+        assert!(db.find("b").is_none());
+    }
+
+    #[test]
+    fn history_budget() {
+        let mut db = CommonData::new();
+        // each commit adds one object and costs two changes, keep four of them
+        let mut history = History::new(db.clone(), 4 * 2);
+
+        for i in range(0u, 10) {
+            db.new_object(None, format!("obj_{}", i).as_slice());
+            history.commit("add", db.clone());
+        }
+
+        assert!(history.len() == 4);
+        assert!(history.log().len() == 4);
+        for _ in range(0u, 3) {
+            assert!(history.undo().is_some());
+        }
+        assert!(history.undo().is_none());
+        assert!(history.head().find("obj_6").is_some());
+        assert!(history.head().find("obj_7").is_none());
+    }
+
+    #[test]
+    fn history_prunes_branches_first() {
+        let mut db = CommonData::new();
+        // the initial commit costs one change, the others two
+        let mut history = History::new(db.clone(), 1 + 3 * 2);
+
+        db.new_object(None, "a");
+        let a = history.commit("add a", db.clone());
+        let mut b = db.clone();
+        b.new_object(None, "b");
+        let b = history.commit("add b", b);
+
+        // a fourth commit is over budget, the branch that was left goes
+        // before any ancestor of the head
+        history.undo();
+        db.new_object(None, "x");
+        let x = history.commit("add x", db.clone());
+        db.new_object(None, "y");
+        history.commit("add y", db.clone());
+
+        assert!(history.tag(b).is_none());
+        assert!(history.log().len() == 4);
+        history.undo();
+        history.undo();
+        assert!(history.head_id() == a);
+        assert!(history.branches() == vec!(x));
+        assert!(history.changes() <= 1 + 3 * 2);
+    }
+}
+
+mod replay {
+    use std::io::{MemWriter, MemReader};
+
+    use glfw;
+    use glfw::{KeyEvent, CursorPosEvent, Press, Release};
+
+    use snowmew::io::InputState;
+    use snowmew::common::CommonData;
+    use snowmew::manager::Scheduler;
+    use snowmew::replay::{Recorder, Replay, replay};
+
+    fn record() -> Vec<u8> {
+        let mut input = InputState::synthetic((800, 600), (800, 600));
+        let mut recorder = Recorder::new(MemWriter::new(), &input, 0.1).unwrap();
+
+        input.push_event(Some(0.1), KeyEvent(glfw::KeyW, 0, Press, glfw::Modifiers::empty()));
+        input.push_event(Some(0.1), CursorPosEvent(10., 10.));
+        recorder.record(&input).unwrap();
+
+        input.push_event(Some(0.2), CursorPosEvent(15., 20.));
+        recorder.record(&input).unwrap();
+
+        input.push_event(Some(0.3), KeyEvent(glfw::KeyW, 0, Release, glfw::Modifiers::empty()));
+        recorder.record(&input).unwrap();
+
+        recorder.unwrap().unwrap()
+    }
+
+    #[test]
+    fn replay_frames() {
+        let mut frames = Replay::new(MemReader::new(record())).unwrap();
+
+        let first = frames.next().unwrap();
+        assert!(first.key_down(glfw::KeyW));
+        let second = frames.next().unwrap();
+        assert!(second.key_down(glfw::KeyW));
+        assert!(second.cursor_delta(first.time()) == Some((5., 10.)));
+        let third = frames.next().unwrap();
+        assert!(!third.key_down(glfw::KeyW));
+        assert!(frames.next().is_none());
+        assert!(frames.error().is_none());
+    }
+
+    #[test]
+    fn replay_rejects_version_1() {
+        let mut data = record();
+        // the version follows the magic
+        *data.get_mut(4) = 1;
+        assert!(Replay::new(MemReader::new(data)).is_err());
+    }
+
+    #[test]
+    fn replay_game() {
+        let frames = Replay::new(MemReader::new(record())).unwrap();
+
+        let mut ticks = 0u;
+        let mut forward = 0u;
+        replay(CommonData::new(), frames, &mut Scheduler::new(), |gd, frame, input, _| {
+            assert!(frame.count == ticks);
+            assert!(frame.delta == 0.1);
+            ticks += 1;
+            if input.key_down(glfw::KeyW) {
+                forward += 1;
+            }
+            (gd, 0, 0)
+        }).unwrap();
+
+        assert!(ticks == 3);
+        assert!(forward == 2);
+    }
+
+    #[test]
+    fn replay_held_at_start() {
+        let mut input = InputState::synthetic((800, 600), (800, 600));
+        input.push_event(Some(0.05), KeyEvent(glfw::KeyA, 0, Press, glfw::Modifiers::empty()));
+        input.push_event(Some(0.05), CursorPosEvent(100., 50.));
+        let start = input.clone();
+        let mut recorder = Recorder::new(MemWriter::new(), &input, 0.1).unwrap();
+
+        input.push_event(Some(0.1), CursorPosEvent(110., 40.));
+        recorder.record(&input).unwrap();
+        let live = input.clone();
+
+        let mut frames = Replay::new(MemReader::new(recorder.unwrap().unwrap())).unwrap();
+        let initial = frames.initial();
+        assert!(initial.key_down(glfw::KeyA));
+        assert!(initial.cursor_pos() == Some((100., 50.)));
+        assert!(initial.time() == start.time());
+
+        let first = frames.next().unwrap();
+        assert!(first.key_down(glfw::KeyA));
+        assert!(first.cursor_delta(initial.time()) == live.cursor_delta(start.time()));
+        assert!(first.cursor_delta(initial.time()) == Some((10., -10.)));
+    }
+
+    #[test]
+    fn replay_truncated() {
+        let mut data = record();
+        let len = data.len();
+        data.truncate(len - 3);
+
+        let mut frames = Replay::new(MemReader::new(data.clone())).unwrap();
+        assert!(frames.next().is_some());
+        assert!(frames.next().is_some());
+        assert!(frames.next().is_none());
+        assert!(frames.error().is_some());
+
+        let frames = Replay::new(MemReader::new(data)).unwrap();
+        assert!(replay(CommonData::new(), frames, &mut Scheduler::new(), |gd, _, _, _| (gd, 0, 0)).is_err());
+    }
+}
+
+mod headless {
+    use std::iter::Repeat;
+
+    use snowmew;
+    use snowmew::io::InputState;
+    use snowmew::common::{CommonData, Common, ObjectKey, FrameInfo};
+    use snowmew::manager::{Manager, Scheduler};
+
+    struct CountingRender {
+        frames: uint,
+        last_scene: ObjectKey
+    }
+
+    impl snowmew::Render<CommonData> for CountingRender {
+        fn update(&mut self, _: CommonData, scene: ObjectKey, _: ObjectKey) {
+            self.frames += 1;
+            self.last_scene = scene;
+        }
+    }
+
+    #[test]
+    fn headless_ticks() {
+        let mut db = CommonData::new();
+        let scene = db.new_scene("scene");
+
+        let input = Repeat::new(InputState::synthetic((800, 600), (800, 600)));
+        let mut count = 0u;
+        let db = snowmew::start_headless(db, &mut snowmew::NullRender, &mut Scheduler::new(), 10, 0.5, input, |gd, _, _, _| {
+            let mut gd = gd;
+            gd.new_object(Some(scene), format!("obj_{}", count).as_slice());
+            count += 1;
+            (gd, scene, 0)
+        });
+
+        assert!(count == 10);
+        assert!(db.scene_iter(scene).count() == 10);
+    }
+
+    #[test]
+    fn headless_render() {
+        let mut db = CommonData::new();
+        let scene = db.new_scene("scene");
+
+        let mut render = CountingRender { frames: 0, last_scene: 0 };
+        let input = Repeat::new(InputState::synthetic((800, 600), (800, 600)));
+        snowmew::start_headless(db, &mut render, &mut Scheduler::new(), 5, 0.5, input, |gd, _, _, _| (gd, scene, 0));
+
+        assert!(render.frames == 5);
+        assert!(render.last_scene == scene);
+    }
+
+    #[test]
+    fn headless_frame_info() {
+        let input = Repeat::new(InputState::synthetic((800, 600), (800, 600)));
+        let mut frames = Vec::new();
+        snowmew::start_headless(CommonData::new(), &mut snowmew::NullRender, &mut Scheduler::new(), 4, 0.5, input,
+                                |gd, frame, _, _| {
+            frames.push(frame.clone());
+            (gd, 0, 0)
+        });
+
+        assert!(frames.len() == 4);
+        for (i, frame) in frames.iter().enumerate() {
+            assert!(frame.count == i);
+            assert!(frame.delta == 0.5);
+            assert!(frame.time == i as f64 * 0.5);
+        }
+    }
+
+    struct Spawner;
+    impl Manager<CommonData> for Spawner {
+        fn step(&mut self, gd: CommonData, frame: &FrameInfo) -> Option<CommonData> {
+            let mut gd = gd;
+            gd.new_object(None, format!("spawned_{}", frame.count).as_slice());
+            Some(gd)
+        }
+    }
+
+    fn take_all(_: CommonData, out: &CommonData) -> CommonData { out.clone() }
+
+    #[test]
+    fn headless_runs_managers() {
+        let mut managers = Scheduler::new();
+        managers.add_active("spawner", 0, Spawner, take_all);
+
+        let input = Repeat::new(InputState::synthetic((800, 600), (800, 600)));
+        let db = snowmew::start_headless(CommonData::new(), &mut snowmew::NullRender, &mut managers,
+                                         3, 0.5, input, |gd, _, _, _| (gd, 0, 0));
+
+        assert!(db.find("spawned_0").is_some());
+        assert!(db.find("spawned_2").is_some());
+        assert!(db.find("spawned_3").is_none());
+    }
+}
+
+mod manager {
+    use std::comm::{Sender, Receiver, channel};
+
+    use snowmew::common::FrameInfo;
+    use snowmew::manager::{Manager, Scheduler};
+
+    #[deriving(Clone)]
+    struct Counters {
+        a: uint,
+        b: uint,
+        c: uint
+    }
+
+    struct IncA;
+    impl Manager<Counters> for IncA {
+        fn step(&mut self, gd: Counters, _: &FrameInfo) -> Option<Counters> {
+            let mut gd = gd;
+            gd.a += 1;
+            Some(gd)
+        }
+    }
+
+    struct CopyA;
+    impl Manager<Counters> for CopyA {
+        fn step(&mut self, gd: Counters, _: &FrameInfo) -> Option<Counters> {
+            let mut gd = gd;
+            gd.b = gd.a * 10;
+            Some(gd)
+        }
+    }
+
+    struct SumAB;
+    impl Manager<Counters> for SumAB {
+        fn step(&mut self, gd: Counters, _: &FrameInfo) -> Option<Counters> {
+            let mut gd = gd;
+            gd.c = gd.a + gd.b;
+            gd.a = 1000;
+            Some(gd)
+        }
+    }
+
+    struct Watch(Sender<(uint, uint)>);
+    impl Manager<Counters> for Watch {
+        fn step(&mut self, gd: Counters, frame: &FrameInfo) -> Option<Counters> {
+            let Watch(ref ch) = *self;
+            ch.send((frame.count, gd.c));
+            None
+        }
+    }
+
+    fn merge_a(base: Counters, out: &Counters) -> Counters { Counters { a: out.a, .. base } }
+    fn merge_b(base: Counters, out: &Counters) -> Counters { Counters { b: out.b, .. base } }
+    fn merge_c(base: Counters, out: &Counters) -> Counters { Counters { c: out.c, .. base } }
+
+    #[test]
+    fn scheduler_stages() {
+        let mut sched = Scheduler::new();
+        // added out of order, stage 1 must still run after stage 0
+        sched.add_active("sum", 1, SumAB, merge_c);
+        sched.add_active("inc", 0, IncA, merge_a);
+        sched.add_active("copy", 0, CopyA, merge_b);
+
+        let mut frame = FrameInfo::new(0.1);
+        let gd = sched.run(Counters { a: 0, b: 0, c: 0 }, &frame);
+        // copy saw the same generation as inc
+        assert!(gd.a == 1 && gd.b == 0);
+        // sum saw the merged generation, its write to `a` was not merged
+        assert!(gd.c == 1);
+
+        frame = frame.next();
+        let gd = sched.run(gd, &frame);
+        assert!(gd.a == 2 && gd.b == 10 && gd.c == 12);
+    }
+
+    #[test]
+    fn frame_time_from_count() {
+        let mut frame = FrameInfo::new(0.1);
+        for _ in range(0u, 1000) {
+            frame = frame.next();
+        }
+        assert!(frame.count == 1000);
+        assert!(frame.time == 1000f64 * 0.1);
+    }
+
+    #[test]
+    fn scheduler_passive() {
+        let (send, recv) = channel();
+        let mut sched = Scheduler::new();
+        sched.add_active("inc", 0, IncA, merge_a);
+        sched.add_active("sum", 1, SumAB, merge_c);
+        sched.add_passive("watch", Watch(send));
+
+        // waiting for the watcher each frame, it sees every generation
+        let mut frame = FrameInfo::new(0.1);
+        let mut gd = Counters { a: 0, b: 0, c: 0 };
+        for i in range(0u, 3) {
+            gd = sched.run(gd, &frame);
+            frame = frame.next();
+            assert!(recv.recv() == (i, i + 1));
+        }
+    }
+
+    struct Gate(Sender<uint>, Receiver<()>);
+    impl Manager<Counters> for Gate {
+        fn step(&mut self, _: Counters, frame: &FrameInfo) -> Option<Counters> {
+            let Gate(ref started, ref open) = *self;
+            started.send(frame.count);
+            open.recv();
+            None
+        }
+    }
+
+    #[test]
+    fn scheduler_passive_skips_behind() {
+        let (started_send, started) = channel();
+        let (open, open_recv) = channel();
+        let mut sched = Scheduler::new();
+        sched.add_active("inc", 0, IncA, merge_a);
+        sched.add_passive("gate", Gate(started_send, open_recv));
+
+        let mut frame = FrameInfo::new(0.1);
+        let mut gd = Counters { a: 0, b: 0, c: 0 };
+        gd = sched.run(gd, &frame);
+        assert!(started.recv() == 0);
+
+        // the gate is stuck on the first frame while these are produced
+        for _ in range(0u, 10) {
+            frame = frame.next();
+            gd = sched.run(gd, &frame);
+        }
+        assert!(gd.a == 11);
+
+        // once it is let go it picks up the newest frame only
+        open.send(());
+        assert!(started.recv() == 10);
+        open.send(());
+        assert!(started.try_recv().is_err());
+    }
+}
+
+mod component {
+    use std::io::{MemWriter, MemReader};
+
+    use snowmew::common::{CommonData, Common, ObjectKey, Remap};
+    use snowmew::component::ComponentStore;
+    use snowmew::snapshot::Snapshot;
+
+    #[deriving(Clone, PartialEq)]
+    struct Health(u32);
+
+    impl Snapshot for Health {
+        fn write_to(&self, w: &mut Writer) -> ::std::io::IoResult<()> {
+            let &Health(h) = self;
+            w.write_le_u32(h)
+        }
+
+        fn read_from(r: &mut Reader) -> ::std::io::IoResult<Health> {
+            Ok(Health(try!(r.read_le_u32())))
+        }
+    }
+
+    component!(Healths: Health { get_health, get_health_mut })
+
+    game_data!(TestData {
+        common: CommonData => Common { get_common, get_common_mut },
+        health: ComponentStore<Health> => Healths { get_health, get_health_mut }
+    } hooks {
+        instantiate_health, delete_health
+    })
+
+    impl TestData {
+        fn instantiate_health(&mut self, remap: &Remap) {
+            self.get_health_mut().instantiate(remap);
+        }
+
+        fn delete_health(&mut self, key: ObjectKey) {
+            self.get_health_mut().remove(key);
+        }
+    }
+
+    #[test]
+    fn component_store() {
+        let mut db = TestData::empty();
+        let a = db.new_object(None, "a");
+        let b = db.new_object(None, "b");
+
+        assert!(db.get_health_mut().insert(a, Health(10)));
+        assert!(db.get_health_mut().insert(b, Health(5)));
+        assert!(!db.get_health_mut().insert(b, Health(6)));
+        assert!(db.get_health().get(b) == Some(&Health(6)));
+
+        let old = db.clone();
+        *db.get_health_mut().get_mut(a).unwrap() = Health(1);
+        assert!(db.get_health_mut().remove(b));
+
+        // the old generation is untouched
+        assert!(old.get_health().get(a) == Some(&Health(10)));
+        assert!(old.get_health().contains(b));
+        assert!(db.get_health().len() == 1);
+
+        let diff = ComponentStore::diff(old.get_health(), db.get_health(), |a, b| a == b);
+        assert!(diff.modified == vec!(a));
+        assert!(diff.removed == vec!(b));
+    }
+
+    #[test]
+    fn game_data_spawn() {
+        let mut db = TestData::empty();
+        let prefab = db.new_object(None, "prefab");
+        let arm = db.new_object(Some(prefab), "arm");
+        db.get_health_mut().insert(prefab, Health(10));
+        db.get_health_mut().insert(arm, Health(3));
+
+        let remap = db.spawn(prefab, None, "copy").unwrap();
+        let copy = remap.root();
+        let copy_arm = remap.get(arm).unwrap();
+        assert!(db.get_health().get(copy) == Some(&Health(10)));
+        assert!(db.get_health().get(copy_arm) == Some(&Health(3)));
+
+        let removed = db.despawn(copy);
+        assert!(removed == vec!(copy_arm, copy));
+        assert!(db.find("copy").is_none());
+        assert!(!db.get_health().contains(copy) && !db.get_health().contains(copy_arm));
+        assert!(db.get_health().len() == 2);
+    }
+
+    #[test]
+    fn component_snapshot() {
+        let mut store = ComponentStore::new();
+        store.insert(1, Health(3));
+        store.insert(7, Health(9));
+
+        let mut w = MemWriter::new();
+        store.write_to(&mut w).unwrap();
+        let mut r = MemReader::new(w.unwrap());
+        let read: ComponentStore<Health> = Snapshot::read_from(&mut r).unwrap();
+
+        assert!(read.len() == 2);
+        assert!(read.get(1) == Some(&Health(3)));
+        assert!(read.get(7) == Some(&Health(9)));
+    }
+}
+
+mod query {
+    use snowmew::common::{CommonData, Common, DEFAULT_LAYER};
+    use snowmew::component::ComponentStore;
+    use snowmew::query::{query, Chunk};
+
+    fn store(keys: &[u32]) -> ComponentStore<u32> {
+        let mut s = ComponentStore::new();
+        for k in keys.iter() {
+            s.insert(*k, *k * 10);
+        }
+        s
+    }
+
+    #[test]
+    fn query_with() {
+        let a = store(&[1, 2, 3, 5, 8]);
+        let b = store(&[2, 3, 4, 8, 9]);
+        let c = store(&[3, 8]);
+
+        let out: Vec<(u32, ((u32, u32), u32))> = query(a.iter()).with(b.iter()).with(c.iter())
+            .map(|(k, ((x, y), z))| (*k, ((*x, *y), *z)))
+            .collect();
+        assert!(out == vec!((3, ((30, 30), 30)), (8, ((80, 80), 80))));
+    }
+
+    #[test]
+    fn query_maybe_without() {
+        let a = store(&[1, 2, 3]);
+        let b = store(&[2]);
+
+        let maybe: Vec<(u32, Option<u32>)> = query(a.iter()).maybe(b.iter())
+            .map(|(k, (_, y))| (*k, y.map(|y| *y)))
+            .collect();
+        assert!(maybe == vec!((1, None), (2, Some(20)), (3, None)));
+
+        let without: Vec<u32> = query(a.iter()).without(b.iter()).map(|(k, _)| *k).collect();
+        assert!(without == vec!(1, 3));
+    }
+
+    #[test]
+    fn query_scene_subtree() {
+        let mut db = CommonData::new();
+        let scene = db.new_scene("scene");
+        let a = db.new_object(Some(scene), "a");
+        let a_child = db.new_object(Some(a), "child");
+        let b = db.new_object(None, "b");
+        let s = store(&[scene, a, a_child, b]);
+
+        let in_scene: Vec<u32> = query(s.iter()).in_scene(&db, scene).map(|(k, _)| *k).collect();
+        assert!(in_scene == db.scene_iter(scene).map(|k| *k).collect());
+        assert!(!in_scene.contains(&b));
+
+        let in_subtree: Vec<u32> = query(s.iter()).in_subtree(&db, a).map(|(k, _)| *k).collect();
+        assert!(in_subtree == vec!(a, a_child));
+    }
+
+    #[test]
+    fn query_layers() {
+        let mut db = CommonData::new();
+        let a = db.new_object(None, "a");
+        let gizmo = db.new_object(None, "gizmo");
+        let trigger = db.new_object(None, "trigger");
+        db.set_layers(gizmo, 0x2);
+        db.add_layers(trigger, 0x4);
+        let s = store(&[a, gizmo, trigger]);
+
+        let default: Vec<u32> = query(s.iter()).in_layers(&db, DEFAULT_LAYER).map(|(k, _)| *k).collect();
+        assert!(default == vec!(a, trigger));
+
+        let editor: Vec<u32> = query(s.iter()).in_layers(&db, 0x2 | 0x4).map(|(k, _)| *k).collect();
+        assert!(editor == vec!(gizmo, trigger));
+    }
+
+    #[test]
+    fn query_chunks() {
+        let a = store(&[1, 2, 3, 4, 5]);
+        let b = store(&[1, 2, 4, 5, 6]);
+
+        let chunks = query(a.iter()).with(b.iter()).chunks(3);
+        assert!(chunks == vec!(Chunk { offset: 0, keys: vec!(1, 2, 4) },
+                               Chunk { offset: 3, keys: vec!(5) }));
+    }
+}
+
+mod event {
+    use snowmew::common::{CommonData, Common};
+    use snowmew::event::{Event, EventLog, EventSeq, ObjectCreated, ObjectRemoved, ObjectRenamed, LayersChanged};
+
+    fn drain(db: &CommonData, seq: EventSeq) -> Option<Vec<Event>> {
+        db.events_since(seq).map(|events| events.map(|(_, ev)| ev.clone()).collect())
+    }
+
+    #[test]
+    fn event_log() {
+        let mut db = CommonData::new();
+        let seq = db.event_seq();
+        let a = db.new_object(None, "a");
+        db.rename(a, "b");
+        db.set_layers(a, 0x2);
+        db.delete_object(a);
+
+        assert!(drain(&db, seq) == Some(vec!(ObjectCreated(a), ObjectRenamed(a),
+                                             LayersChanged(a), ObjectRemoved(a))));
+        assert!(drain(&db, db.event_seq()) == Some(vec!()));
+    }
+
+    #[test]
+    fn event_generations() {
+        let mut db = CommonData::new();
+        let a = db.new_object(None, "a");
+        let seen = db.event_seq();
+
+        let old = db.clone();
+        let b = db.new_object(None, "b");
+
+        assert!(drain(&old, seen.clone()) == Some(vec!()));
+        assert!(drain(&db, seen) == Some(vec!(ObjectCreated(b))));
+        assert!(drain(&db, EventSeq::start()) == Some(vec!(ObjectCreated(a), ObjectCreated(b))));
+
+        // a consumer that saw a newer generation than this one has to rescan
+        assert!(drain(&old, db.event_seq()).is_none());
+    }
+
+    #[test]
+    fn event_branches() {
+        let mut db = CommonData::new();
+        db.new_object(None, "a");
+        let base = db.event_seq();
+
+        let mut left = db.clone();
+        let mut right = db.clone();
+        left.new_object(None, "left");
+        let c = right.new_object(None, "right");
+
+        // both branches numbered their event the same way
+        let seen = left.event_seq();
+        assert!(seen.seq == right.event_seq().seq);
+        assert!(drain(&right, seen).is_none());
+        assert!(drain(&right, base) == Some(vec!(ObjectCreated(c))));
+    }
+
+    #[test]
+    fn event_limit() {
+        let mut log = EventLog::new(2);
+        log.push(ObjectCreated(1));
+        let one = log.seq();
+        log.push(ObjectCreated(2));
+        log.push(ObjectCreated(3));
+
+        assert!(log.len() == 2);
+        assert!(log.since(EventSeq::start()).is_none());
+        assert!(log.since(one).unwrap().map(|(seq, ev)| (seq, ev.clone())).collect::<Vec<(u64, Event)>>()
+                == vec!((1, ObjectCreated(2)), (2, ObjectCreated(3))));
+    }
+}